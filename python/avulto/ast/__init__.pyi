@@ -1,6 +1,6 @@
-from typing import Any
+import typing
 
-from .. import SourceLoc
+from .. import DME, Path, ProcDecl, SourceLoc, TypeDecl
 
 class UnaryOperator:
     NEG: "UnaryOperator"
@@ -61,6 +61,7 @@ class BinaryOperator:
     TO: "BinaryOperator"
 
 class NodeKind:
+    AS: "NodeKind"
     ASSIGN_OP: "NodeKind"
     ATTRIBUTE: "NodeKind"
     BINARY_OP: "NodeKind"
@@ -79,6 +80,9 @@ class NodeKind:
     FOR_LIST: "NodeKind"
     FOR_LOOP: "NodeKind"
     FOR_RANGE: "NodeKind"
+    FOR_KEY_VALUE: "NodeKind"
+    GLOBAL_CALL: "NodeKind"
+    GLOBAL_IDENTIFIER: "NodeKind"
     GOTO: "NodeKind"
     IDENTIFIER: "NodeKind"
     IF: "NodeKind"
@@ -110,22 +114,121 @@ class NodeKind:
     THROW: "NodeKind"
     TRY_CATCH: "NodeKind"
     UNARY_OP: "NodeKind"
-    UNKNOWN: "NodeKind"
     VAR: "NodeKind"
     VARS: "NodeKind"
     WHILE: "NodeKind"
 
+class Node:
+    kind: NodeKind
+    source_loc: SourceLoc
+    _fields: tuple[str, ...]
+
+    def iter_fields(self) -> list[tuple[str, typing.Any]]:
+        """Return the `(name, value)` pairs of the node's fields."""
+
+    def iter_child_nodes(self) -> list[Node | Expression | SwitchCase]:
+        """Return the direct children of the node, in field order."""
+
+class SwitchCase:
+    exact: list[Expression]
+    range: list[list[Expression]]
+    block: list[Node]
+    _fields: tuple[str, ...]
+
+    def iter_fields(self) -> list[tuple[str, typing.Any]]:
+        """Return the `(name, value)` pairs of the case's fields."""
+
+    def iter_child_nodes(self) -> list[Node | Expression]:
+        """Return the direct children of the case, in field order."""
+
 class Expression:
     kind: NodeKind
 
     # technically not part of the "parent class"
     # but we don't need to care about that
     source_loc: SourceLoc
+    _fields: tuple[str, ...]
+
+    def iter_fields(self) -> list[tuple[str, typing.Any]]:
+        """Return the `(name, value)` pairs of the expression's fields."""
+
+    def iter_child_nodes(self) -> list[Expression]:
+        """Return the direct sub-expressions, in field order."""
+
+    def evaluate(self, dme: DME, context_type: Path | str | TypeDecl | None = None) -> typing.Any:
+        """Fold the expression into a Python value.
+
+        Identifiers are looked up as defines in `dme`, then as vars on
+        `context_type`. Raises `avulto.exceptions.EvaluationError` if the
+        expression is not constant.
+        """
 
     class AssignOp(Expression):
-        lhs: Any
-        rhs: Any
+        lhs: typing.Any
+        rhs: typing.Any
         op: Operator
 
+    class ExternalCall(Expression):
+        library_name: Expression | None
+        function_name: Expression
+        args: list[Expression]
+
 # etc etc but I'm not writing all this out until I'm 100%
 # happy with the structure of the API
+
+class WalkContext:
+    parents: list[Node | Expression | SwitchCase]
+    """The nodes enclosing the node being visited, outermost first."""
+    parent: Node | Expression | SwitchCase | None
+    """The innermost node enclosing the node being visited."""
+    proc: ProcDecl | None
+    """The proc being walked, if the walk was started from a proc."""
+    type_decl: TypeDecl | None
+    """The type the proc being walked is defined on."""
+    loop_depth: int
+    """The number of loops enclosing the node being visited."""
+    spawn_depth: int
+    """The number of `spawn()` blocks enclosing the node being visited."""
+
+    def enclosing(self, kind: NodeKind) -> Node | Expression | None:
+        """Return the innermost enclosing node of the given kind, if any."""
+
+class NodeVisitor:
+    context: WalkContext
+
+    def visit(self, node: Node | Expression | SwitchCase) -> typing.Any:
+        """Call `visit_<Kind>(node)` if defined, otherwise `generic_visit(node)`."""
+
+    def generic_visit(self, node: Node | Expression | SwitchCase) -> None:
+        """Visit each child of `node`."""
+
+class NodeTransformer(NodeVisitor):
+    def generic_visit(self, node: Node | Expression | SwitchCase) -> typing.Any:
+        """Visit each child of `node`, returning a copy of `node` with any
+        replaced children, or `node` itself if none were replaced."""
+
+def parse_expression(src: str) -> Expression:
+    """Parse a single DM expression."""
+
+def parse_statements(src: str) -> list[Node]:
+    """Parse one or more DM statements, as they would appear in a proc body."""
+
+def unparse(node: Node | Expression | list[Node], indent: str = "\t") -> str:
+    """Convert a node, expression, or list of nodes back into DM source."""
+
+def kind_name(node: Node | Expression | SwitchCase) -> str:
+    """Return the name of the kind of `node`, as used in `visit_<Kind>` methods."""
+
+class Pattern:
+    """A structural pattern matching nodes of `kind` whose fields match `fields`."""
+
+    kind: str
+
+    def __init__(self, kind: str, **fields: typing.Any) -> None: ...
+    def matches(self, node: Node | Expression | SwitchCase) -> bool:
+        """Return whether `node` itself matches the pattern."""
+
+class Any:
+    """A wildcard matching any value in a `Pattern`."""
+
+    def __init__(self) -> None: ...
@@ -2,6 +2,8 @@ import os
 from typing import Iterator, Any
 import pathlib
 
+from . import ast
+
 class Coord3:
     """A three-dimensional integer coordinate. These are currently only returned by certain DMM properties, and are not constructable."""
 
@@ -30,6 +32,14 @@ class ProcArg:
     """The argument name."""
     arg_type: Path | None
     """The argument type, if available."""
+    default: ast.Expression | None
+    """The argument's default value, if any."""
+    input_type: str | None
+    """The argument's `as` input type, such as `"text"`, if any."""
+    in_list: ast.Expression | None
+    """The argument's `in` list, if any."""
+    source_loc: SourceLoc
+    """The source location of the argument."""
 
 class Path:
     """A DM typepath."""
@@ -161,9 +171,162 @@ class ProcDecl:
     """The proc arguments."""
     source_loc: SourceLoc
     """The source location of the proc declaration."""
+    is_verb: bool
+    """Whether the proc was declared as a verb."""
+    is_override: bool
+    """Whether the proc overrides an earlier definition."""
+    return_type: str | None
+    """The declared `as` return type of the proc, if any."""
+    settings: dict[str, Any]
+    """The proc's `set` statements, mapping setting names to their values."""
+    should_call_parent: bool
+    """Whether the proc sets `SHOULD_CALL_PARENT(TRUE)`."""
+    should_not_sleep: bool
+    """Whether the proc sets `SHOULD_NOT_SLEEP(TRUE)`."""
+    waitfor: bool
+    """The proc's `waitfor` setting, which defaults to True."""
+    may_sleep: bool
+    """Whether the proc may sleep, directly or through the procs it calls. Requires `parse_procs=True`."""
+
+    def signature(self) -> str:
+        """Renders the proc's declaration, e.g. `/obj/proc/attack(mob/target, damage = 10)`."""
+
+    def parent_proc(self) -> "ProcDecl | None":
+        """Returns the definition this proc overrides, if any."""
+
+    def cfg(self) -> ControlFlowGraph:
+        """Builds the control-flow graph of the proc body. Requires `parse_procs=True`."""
+
+    def infer_types(self) -> InferredTypes:
+        """Infers the static types of the expressions in the proc body. Requires `parse_procs=True`."""
+
+    def sleep_chain(self) -> list[CallSite] | None:
+        """Returns the calls through which the proc may sleep, ending with the sleeping builtin, or None."""
 
     def walk(self, walker: Any):
-        """Walks the proc AST with *walker*, calling any `visit_*` method names on *walker* if they exist for AST node types."""
+        """Walks the proc AST with *walker*, calling any `visit_*` method names on *walker* if they exist for AST node types.
+
+        The default values of the proc's arguments are walked first, followed by its body.
+
+        If *walker* is an `ast.NodeVisitor`, its `visit` method is called with each statement of the body instead."""
+
+class BasicBlock:
+    """
+    A run of statements in a proc with a single entry and exit.
+    """
+
+    id: int
+    """The index of the block in `ControlFlowGraph.blocks`."""
+    nodes: list[ast.Node]
+    """The statements in the block. A statement which branches, such as an `If` or a loop, is last."""
+    condition: ast.Expression | None
+    """The expression choosing which successor is taken, if any."""
+    successors: list[int]
+    """The ids of the blocks control can pass to."""
+    predecessors: list[int]
+    """The ids of the blocks control can come from."""
+
+class ControlFlowGraph:
+    """
+    The control-flow graph of a proc body.
+    """
+
+    blocks: list[BasicBlock]
+    """Every block, indexed by id."""
+    edges: list[tuple[int, int, str]]
+    """Each edge as a `(source, target, kind)` tuple."""
+    entry: int
+    """The id of the block the proc starts in."""
+    exit: int
+    """The id of the empty block the proc ends in."""
+
+    def unreachable_blocks(self) -> list[BasicBlock]:
+        """Returns the blocks containing statements which can never run."""
+
+    def falls_off_end(self) -> bool:
+        """Whether the end of the proc can be reached without a `return`, `throw` or `CRASH()`."""
+
+    def __len__(self) -> int: ...
+
+class InferredTypes:
+    """
+    The static types of the expressions in a proc body.
+    """
+
+    def type_of(self, target: Any) -> Path | None:
+        """Returns the static type of the expression node or source location `target`, if known."""
+
+    def __len__(self) -> int: ...
+
+class CallSite:
+    """
+    A single call from one proc to another.
+    """
+
+    caller: ProcDecl
+    """The proc containing the call."""
+    callee: ProcDecl | None
+    """The proc definition which is statically called, or None if it could not be resolved."""
+    name: str
+    """The name of the called proc."""
+    kind: str
+    """One of `"call"`, `"self_call"`, `"parent_call"`, `"proc_reference"` or `"dynamic"`."""
+    source_loc: SourceLoc
+    """The source location of the call."""
+    is_resolved: bool
+    """Whether the callee could be resolved."""
+
+class QueryMatch:
+    """
+    A single node found by `DME.query`.
+    """
+
+    node: ast.Node | ast.Expression | ast.SwitchCase
+    """The matching node."""
+    proc: ProcDecl
+    """The proc definition containing the node."""
+    source_loc: SourceLoc
+    """The source location of the node, or of its nearest enclosing node if it has none."""
+
+class CallGraph:
+    """
+    The static call graph of a DME's procs.
+    """
+
+    def calls(self, resolved: bool | None = None) -> list[CallSite]:
+        """Returns every call site. If `resolved` is set, only resolved or unresolved calls are returned."""
+
+    def calls_from(self, proc: ProcDecl) -> list[CallSite]:
+        """Returns the call sites in the body of `proc`."""
+
+    def calls_to(self, proc: ProcDecl) -> list[CallSite]:
+        """Returns the call sites which resolve to `proc`."""
+
+    def callers_of(self, proc: ProcDecl) -> list[ProcDecl]:
+        """Returns the procs which call `proc`."""
+
+    def callees_of(self, proc: ProcDecl) -> list[ProcDecl]:
+        """Returns the procs which `proc` calls, where they could be resolved."""
+
+    def to_dot(self) -> str:
+        """Returns the graph in Graphviz DOT format."""
+
+    def to_graphml(self) -> str:
+        """Returns the graph in GraphML format."""
+
+    def __len__(self) -> int: ...
+
+class UnusedReport:
+    """
+    The procs, vars and types of a DME which are never used.
+    """
+
+    procs: list[ProcDecl]
+    """Proc definitions which are never called or referenced."""
+    vars: list[VarDecl]
+    """Vars which are declared but never read."""
+    types: list[Path]
+    """Types which are never instantiated, mapped or referenced."""
 
 class VarDecl:
     """
@@ -178,6 +341,28 @@ class VarDecl:
     """The declared type of the variable, if specified."""
     const_val: Any | None
     """The variable's value, if it can be evaluated as a constant expression."""
+    evaluated_val: Any | None
+    """The variable's value as `const_val`, or as evaluated in the context of its type if SpacemanDMM could not fold it."""
+    declaring_type: Path | None
+    """The type path the variable was originally declared on."""
+    is_static: bool
+    """Whether the variable is declared `static` (or `global`)."""
+    is_const: bool
+    """Whether the variable is declared `const`."""
+    is_tmp: bool
+    """Whether the variable is declared `tmp`."""
+    is_final: bool
+    """Whether the variable is declared `final`."""
+    is_global: bool
+    """An alias of `is_static`."""
+    is_private: bool
+    """Whether the variable is declared `SpacemanDMM_private`."""
+    is_protected: bool
+    """Whether the variable is declared `SpacemanDMM_protected`."""
+    input_type: str | None
+    """The `as` input type of the variable, such as `"num|text"`, if specified."""
+    var_type: str | None
+    """The full declared type of the variable, including modifiers, such as `"/static/list"`."""
 
 class TypeDecl:
     """
@@ -188,6 +373,19 @@ class TypeDecl:
     """The path of the type."""
     source_loc: SourceLoc
     """The source location of the type declaration."""
+    parent: "TypeDecl | None"
+    """The type this type inherits from, or None for the root."""
+    children: list["TypeDecl"]
+    """The types which inherit directly from this type, sorted by path."""
+
+    def ancestors(self) -> list["TypeDecl"]:
+        """Return the parent of this type, its parent, and so on up to the root type."""
+
+    def descendants(self) -> list["TypeDecl"]:
+        """Return every type inheriting from this type, each followed by its own descendants."""
+
+    def vars(self) -> list[VarDecl]:
+        """Return every variable visible on the type, with its effective value."""
 
     def proc_names(self, declared=False, modified=False, unmodified=False) -> list[str]:
         """Return a list of proc names for the type declaration."""
@@ -201,6 +399,27 @@ class TypeDecl:
     def var_decl(self, name, parents=True) -> VarDecl:
         """Return the proc declaration for variable *name*. If *parents* is True, check up type path if this type does not have this variable set."""
 
+    def walk_vars(self, walker: Any):
+        """Walks the initial values of the vars set on this type with *walker*, calling `enter_var(var_decl)` and `exit_var(var_decl)` if defined."""
+
+class DefineDecl:
+    """
+    A single preprocessor `#define`.
+    """
+
+    name: str
+    """The name of the define."""
+    value: str
+    """The replacement text of the define."""
+    args: list[str] | None
+    """The parameter names of a function-like define, or None for constant defines."""
+    is_variadic: bool
+    """Whether the last parameter of a function-like define is variadic."""
+    is_undefined: bool
+    """Whether the define is `#undef`'d before the end of the environment."""
+    source_loc: SourceLoc
+    """The source location of the define."""
+
 class DME:
     """
     A representation of a single Dreammaker environment.
@@ -210,16 +429,69 @@ class DME:
     """The original filename of the DMM."""
     types: dict[Path | str, TypeDecl]
     """A mapping of type paths to their declarations."""
+    defines: dict[str, DefineDecl]
+    """A mapping of define names to their most recent definition."""
 
     @staticmethod
-    def from_file(filename: os.PathLike | str, parse_procs: bool = False) -> "DME":
+    def from_file(
+        filename: os.PathLike | str,
+        parse_procs: bool = False,
+        defines: dict[str, Any] | None = None,
+        extra_includes: list[str] | None = None,
+        exclude: list[str] | None = None,
+    ) -> "DME":
         """Creates a DME from the given `filename`.
 
         If parse_procs is True, the entire AST of the codebase is traversed.
         This is slower than the default but provides more reflection
         information.
+
+        `defines` are defined before the DME is preprocessed, `extra_includes`
+        are included after the DME's own includes, and any of the DME's
+        includes matching a glob in `exclude` are skipped.
         """
 
+    def expand_macro(self, text: str) -> str:
+        """Returns `text` after expanding any defines in effect at the end of preprocessing."""
+
+    def call_graph(self) -> CallGraph:
+        """Builds the static call graph of every proc. Requires `parse_procs=True`."""
+
+    def find_references(
+        self, target: "Path | str | TypeDecl | ProcDecl | VarDecl | tuple[Path | str, str]"
+    ) -> list[SourceLoc]:
+        """Returns the location of every reference to a type, proc, or `(type, var name)` pair. Requires `parse_procs=True`."""
+
+    def unused(self, dmms: list[DMM] | None = None) -> UnusedReport:
+        """Returns the procs, vars and types which are never used. Types placed on any of `dmms` count as used. Requires `parse_procs=True`."""
+
+    def walk_procs(self, walker: Any, filter: Path | str | None = None, include_builtins: bool = False):
+        """Walks the body of every proc definition on `filter` and its subtypes. *walker* may be a list of walkers, which share one traversal. Calls `enter_proc(proc_decl)` and `exit_proc(proc_decl)` on walkers defining them. Requires `parse_procs=True`."""
+
+    def query(
+        self,
+        pattern: ast.Pattern,
+        filter: Path | str | None = None,
+        proc: str | None = None,
+        include_builtins: bool = False,
+    ) -> list[QueryMatch]:
+        """Finds every node in the bodies of procs on `filter` and its subtypes, optionally only procs named `proc`, matching `pattern`. Requires `parse_procs=True`."""
+
+    def export_types(
+        self,
+        format: str = "json",
+        prefix: Path | str | None = None,
+        include_vars: bool = True,
+        include_procs: bool = True,
+    ) -> str:
+        """Serialises `prefix` and its subtypes, with their vars and procs, as `"json"` or as a `"dot"` graph of the type tree."""
+
+    def source_loc(self, node: ast.Node | ast.Expression | ast.SwitchCase) -> SourceLoc | None:
+        """Returns the location of `node` in its source file, if it has one of its own."""
+
+    def source_span(self, expr: ast.Expression) -> tuple[SourceLoc, int, int] | None:
+        """Returns the location of the start of the source text `expr` was parsed from, and the byte offsets of its start and end in that file."""
+
     def typesof(self, prefix: Path | str) -> list[Path]:
         """Returns a list of type paths with the given `prefix`."""
 
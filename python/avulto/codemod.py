@@ -0,0 +1,203 @@
+"""
+Automated rewrites of DM source code.
+
+An :class:`ast.NodeTransformer` is run over the procs of a :class:`DME`, and
+the expressions it replaces are mapped back onto the original source files as
+a :class:`Patch` of minimal text edits. Everything outside of the replaced
+expressions, including formatting and comments, is left untouched.
+"""
+
+import difflib
+import pathlib
+from dataclasses import dataclass, field
+
+from avulto import DME, Dmlist, ast
+
+# Replacements of these kinds are parenthesized when they replace a nested
+# expression, so that they keep binding the same way.
+_LOOSE_KINDS = {"BinaryOp", "AssignOp", "TernaryOp"}
+
+_AST_TYPES = (ast.Node, ast.Expression, ast.SwitchCase)
+
+
+@dataclass
+class Edit:
+    """A single replacement of a span of text in a source file."""
+
+    file_path: pathlib.Path
+    """The absolute path of the edited file."""
+    start: int
+    """The offset of the first replaced byte."""
+    end: int
+    """The offset after the last replaced byte."""
+    old_text: str
+    new_text: str
+    source_loc: ast.SourceLoc
+    """The location of the start of the replaced expression."""
+
+
+@dataclass
+class Patch:
+    """The set of edits produced by :func:`rewrite`."""
+
+    edits: list[Edit] = field(default_factory=list)
+    skipped: list[tuple[ast.Node | ast.Expression, str]] = field(default_factory=list)
+    """Replaced nodes which could not be mapped onto the source, with the reason."""
+
+    def files(self) -> list[pathlib.Path]:
+        """Returns the paths of every file with at least one edit."""
+        return sorted({edit.file_path for edit in self.edits})
+
+    def new_text(self, file_path: pathlib.Path) -> str:
+        """Returns the contents of `file_path` with its edits applied."""
+        file_path = pathlib.Path(file_path)
+        data = file_path.read_bytes()
+        for edit in sorted(
+            (e for e in self.edits if e.file_path == file_path),
+            key=lambda e: e.start,
+            reverse=True,
+        ):
+            data = data[: edit.start] + edit.new_text.encode("utf-8") + data[edit.end :]
+        return data.decode("utf-8", errors="surrogateescape")
+
+    def diff(self) -> str:
+        """Returns the patch as a unified diff."""
+        out = []
+        for file_path in self.files():
+            old = file_path.read_bytes().decode("utf-8", errors="surrogateescape")
+            new = self.new_text(file_path)
+            out.extend(
+                difflib.unified_diff(
+                    old.splitlines(keepends=True),
+                    new.splitlines(keepends=True),
+                    fromfile=f"a/{file_path}",
+                    tofile=f"b/{file_path}",
+                )
+            )
+        return "".join(out)
+
+    def apply(self):
+        """Writes the edits to disk."""
+        for file_path in self.files():
+            new = self.new_text(file_path)
+            file_path.write_bytes(new.encode("utf-8", errors="surrogateescape"))
+
+
+class _Unmapped(Exception):
+    def __init__(self, node, reason: str):
+        super().__init__(reason)
+        self.node = node
+        self.reason = reason
+
+
+def _is_copy(old, new) -> bool:
+    """
+    Returns whether `new` is `old` as rebuilt by `generic_visit`: a node of
+    the same kind, whose fields are the same apart from its children.
+    """
+    if type(old) is not type(new):
+        return False
+    children = {id(child) for child in old.iter_child_nodes()}
+    for (_, old_value), (_, new_value) in zip(old.iter_fields(), new.iter_fields()):
+        if old_value is new_value:
+            continue
+        if isinstance(old_value, _AST_TYPES):
+            if id(old_value) not in children:
+                return False
+        elif not isinstance(old_value, (list, tuple, Dmlist)) and old_value != new_value:
+            return False
+    return len(old.iter_child_nodes()) == len(new.iter_child_nodes())
+
+
+class _Rewriter:
+    def __init__(self, dme: DME, transformer: ast.NodeTransformer, patch: Patch):
+        self.dme = dme
+        self.transformer = transformer
+        self.patch = patch
+        self.root = pathlib.Path(dme.filepath).parent
+        self.files: dict[pathlib.Path, bytes] = {}
+        self.seen: set[tuple[pathlib.Path, int, int]] = set()
+        self.depth = 0
+
+    def visit(self, node):
+        """
+        Stands in for the transformer's ``visit``, so that each statement of
+        a proc can be compared with what the transformer turned it into.
+        """
+        self.depth += 1
+        try:
+            result = type(self.transformer).visit(self.transformer, node)
+        finally:
+            self.depth -= 1
+        if self.depth == 0:
+            try:
+                edits = self.edits(node, result, nested=False)
+            except _Unmapped as e:
+                self.patch.skipped.append((e.node, e.reason))
+            else:
+                for edit in edits:
+                    key = (edit.file_path, edit.start, edit.end)
+                    if key not in self.seen:
+                        self.seen.add(key)
+                        self.patch.edits.append(edit)
+        return result
+
+    def edits(self, old, new, nested: bool) -> list[Edit]:
+        """Returns the edits turning the source of `old` into `new`."""
+        if new is old:
+            return []
+        if _is_copy(old, new):
+            try:
+                out = []
+                for old_child, new_child in zip(old.iter_child_nodes(), new.iter_child_nodes()):
+                    out.extend(
+                        self.edits(
+                            old_child, new_child, nested=isinstance(old, ast.Expression)
+                        )
+                    )
+                return out
+            except _Unmapped:
+                # Replace the whole expression if a part of it can't be.
+                if not isinstance(old, ast.Expression) or self.dme.source_span(old) is None:
+                    raise
+                return [self.replace(old, new, nested)]
+        if isinstance(old, ast.Expression) and isinstance(new, ast.Expression):
+            return [self.replace(old, new, nested)]
+        raise _Unmapped(old, "statements can only be rewritten within their expressions")
+
+    def replace(self, old: ast.Expression, new: ast.Expression, nested: bool) -> Edit:
+        span = self.dme.source_span(old)
+        if span is None:
+            raise _Unmapped(old, "expression could not be mapped onto its source text")
+        source_loc, start, end = span
+        path = self.root / source_loc.file_path
+        if path not in self.files:
+            self.files[path] = path.read_bytes()
+
+        new_text = ast.unparse(new)
+        if nested and ast.kind_name(new) in _LOOSE_KINDS:
+            new_text = f"({new_text})"
+        return Edit(
+            file_path=path,
+            start=start,
+            end=end,
+            old_text=self.files[path][start:end].decode("utf-8", errors="replace"),
+            new_text=new_text,
+            source_loc=source_loc,
+        )
+
+
+def rewrite(dme: DME, transformer: ast.NodeTransformer, prefix="/") -> Patch:
+    """
+    Runs `transformer` over every proc of the types under `prefix`, returning
+    the expressions it replaced as a :class:`Patch`. The DME must have been
+    loaded with ``parse_procs=True``.
+    """
+    patch = Patch()
+    rewriter = _Rewriter(dme, transformer, patch)
+    transformer.visit = rewriter.visit
+    try:
+        dme.walk_procs(transformer, prefix)
+    finally:
+        del transformer.visit
+    return patch
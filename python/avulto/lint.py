@@ -0,0 +1,297 @@
+"""
+Checks for common code smells in DM code.
+
+Each rule is a function taking a :class:`DME` loaded with ``parse_procs=True``
+and returning the :class:`Finding` objects it reports. :func:`run` runs any
+number of rules and returns their findings in source order.
+"""
+
+from dataclasses import dataclass
+from typing import Callable, Iterable
+
+from avulto import DME, ProcDecl, ast, exceptions
+
+_SHOULD_CALL_PARENT = "SpacemanDMM_should_call_parent"
+
+
+@dataclass
+class Finding:
+    """A single problem reported by a lint rule."""
+
+    rule: str
+    """The name of the rule, e.g. ``"del_usage"``."""
+    message: str
+    source_loc: object
+    """The :class:`SourceLoc` of the offending code."""
+    proc: ProcDecl | None
+    """The proc containing the offending code."""
+
+    def to_dict(self) -> dict:
+        """Returns the finding as a dict of plain values, suitable for JSON."""
+        return {
+            "rule": self.rule,
+            "message": self.message,
+            "file": str(self.source_loc.file_path),
+            "line": self.source_loc.line,
+            "column": self.source_loc.column,
+            "proc": self.proc.signature() if self.proc is not None else None,
+        }
+
+
+def _proc_key(proc: ProcDecl) -> tuple:
+    loc = proc.source_loc
+    return (str(proc.type_path), proc.name, str(loc.file_path), loc.line)
+
+
+# The node kinds the built-in rules look at.
+_KINDS = {
+    "ParentCall",
+    "Del",
+    "Spawn",
+    "Identifier",
+    "ForList",
+    "ForRange",
+    "ForKeyValue",
+    "Var",
+    "If",
+    "While",
+    "DoWhile",
+    "Call",
+}
+
+
+@dataclass
+class _Match:
+    node: object
+    proc: ProcDecl
+    located: object
+    """The node itself, or its nearest enclosing node with a location."""
+
+
+class _Walk(ast.NodeVisitor):
+    """A single walk over every proc, shared by the built-in rules."""
+
+    def __init__(self, dme: DME):
+        self.dme = dme
+        self.procs: list[ProcDecl] = []
+        self.matches: dict[str, list[_Match]] = {}
+        self._located = []
+        dme.walk_procs(self)
+
+    def enter_proc(self, proc):
+        self.procs.append(proc)
+
+    def visit(self, node):
+        has_loc = getattr(node, "source_loc", None) is not None
+        if has_loc:
+            self._located.append(node)
+        try:
+            kind = ast.kind_name(node)
+            if kind in _KINDS:
+                located = self._located[-1] if self._located else None
+                self.matches.setdefault(kind, []).append(_Match(node, self.context.proc, located))
+            self.generic_visit(node)
+        finally:
+            if has_loc:
+                self._located.pop()
+
+    def query(self, kind: str) -> Iterable[_Match]:
+        return self.matches.get(kind, [])
+
+    def source_loc(self, match: _Match):
+        if match.located is None:
+            return match.proc.source_loc
+        return self.dme.source_loc(match.located)
+
+
+def missing_parent_call(dme: DME, walk: _Walk | None = None) -> Iterable[Finding]:
+    """Overrides of procs marked ``SHOULD_CALL_PARENT`` which never call ``..()``."""
+    walk = walk or _Walk(dme)
+    calls_parent = {_proc_key(m.proc) for m in walk.query("ParentCall")}
+    for proc in walk.procs:
+        if _proc_key(proc) in calls_parent:
+            continue
+        parent = proc.parent_proc()
+        while parent is not None and _SHOULD_CALL_PARENT not in parent.settings:
+            parent = parent.parent_proc()
+        if parent is not None and parent.should_call_parent:
+            yield Finding(
+                "missing_parent_call",
+                f"{proc.name}() must call ..(), as required by {parent.signature()}",
+                proc.source_loc,
+                proc,
+            )
+
+
+def sleep_in_should_not_sleep(dme: DME, walk: _Walk | None = None) -> Iterable[Finding]:
+    """Sleeping calls reachable from procs marked ``SHOULD_NOT_SLEEP``."""
+    walk = walk or _Walk(dme)
+    for proc in walk.procs:
+        if not proc.should_not_sleep:
+            continue
+        chain = proc.sleep_chain()
+        if chain is None:
+            continue
+        path = " -> ".join(f"{site.name}()" for site in chain)
+        yield Finding(
+            "sleep_in_should_not_sleep",
+            f"{proc.name}() is marked SHOULD_NOT_SLEEP but may sleep: {path}",
+            chain[0].source_loc,
+            proc,
+        )
+
+
+def del_usage(dme: DME, walk: _Walk | None = None) -> Iterable[Finding]:
+    """Uses of ``del()``, which is slow compared to ``qdel()``."""
+    walk = walk or _Walk(dme)
+    for match in walk.query("Del"):
+        yield Finding("del_usage", "del() is slow; use qdel()", walk.source_loc(match), match.proc)
+
+
+def spawn_usage(dme: DME, walk: _Walk | None = None) -> Iterable[Finding]:
+    """Uses of ``spawn()``."""
+    walk = walk or _Walk(dme)
+    for match in walk.query("Spawn"):
+        yield Finding(
+            "spawn_usage",
+            "spawn() hides runtimes and ordering; use a timer or INVOKE_ASYNC",
+            walk.source_loc(match),
+            match.proc,
+        )
+
+
+def _name(value) -> str | None:
+    if isinstance(value, ast.Expression) and value.kind == ast.NodeKind.IDENTIFIER:
+        return value.name
+    if isinstance(value, str):
+        return value
+    return None
+
+
+def unused_local(dme: DME, walk: _Walk | None = None) -> Iterable[Finding]:
+    """Local vars which are declared but never referenced."""
+    walk = walk or _Walk(dme)
+    used: dict[tuple, set[str]] = {}
+    for match in walk.query("Identifier"):
+        used.setdefault(_proc_key(match.proc), set()).add(match.node.name)
+    # Loops may assign to an existing local without an identifier node.
+    loops = (("ForList", ("name",)), ("ForRange", ("name",)), ("ForKeyValue", ("key", "value")))
+    for kind, fields in loops:
+        for match in walk.query(kind):
+            names = used.setdefault(_proc_key(match.proc), set())
+            for field in fields:
+                name = _name(getattr(match.node, field))
+                if name is not None:
+                    names.add(name)
+
+    for match in walk.query("Var"):
+        name = _name(match.node.name)
+        if name is not None and name not in used.get(_proc_key(match.proc), ()):
+            yield Finding(
+                "unused_local",
+                f"local var {name} is never used",
+                walk.source_loc(match),
+                match.proc,
+            )
+
+
+def unreachable_code(dme: DME, walk: _Walk | None = None) -> Iterable[Finding]:
+    """Statements which can never run, such as those after a ``return``."""
+    walk = walk or _Walk(dme)
+    for proc in walk.procs:
+        try:
+            cfg = proc.cfg()
+        except exceptions.EmptyProcError:
+            continue
+        covered = set()
+        for block in cfg.unreachable_blocks():
+            if block.id in covered:
+                continue
+            # Only report the start of each unreachable region.
+            pending = [block.id]
+            while pending:
+                block_id = pending.pop()
+                if block_id not in covered:
+                    covered.add(block_id)
+                    pending.extend(cfg.blocks[block_id].successors)
+            source_loc = dme.source_loc(block.nodes[0])
+            if source_loc is not None:
+                yield Finding("unreachable_code", "unreachable code", source_loc, proc)
+
+
+def assignment_in_condition(dme: DME, walk: _Walk | None = None) -> Iterable[Finding]:
+    """Assignments used as the condition of an ``if`` or ``while``, usually a typo of ``==``."""
+    walk = walk or _Walk(dme)
+    for kind in ("If", "While", "DoWhile"):
+        for match in walk.query(kind):
+            if kind == "If":
+                conditions = [cond for cond, _ in match.node.if_arms]
+            else:
+                conditions = [match.node.condition]
+            for cond in conditions:
+                if ast.Pattern("AssignOp", op=ast.Operator.ASSIGN).matches(cond):
+                    yield Finding(
+                        "assignment_in_condition",
+                        f"assignment {ast.unparse(cond)} used as a condition; did you mean ==?",
+                        walk.source_loc(match),
+                        match.proc,
+                    )
+
+
+def istype_missing_path(dme: DME, walk: _Walk | None = None) -> Iterable[Finding]:
+    """``istype()`` checks against type paths which do not exist."""
+    walk = walk or _Walk(dme)
+    pattern = ast.Pattern("Call", name="istype", args=[ast.Any(), ast.Pattern("Prefab")])
+    for match in walk.query("Call"):
+        if not pattern.matches(match.node):
+            continue
+        path = match.node.args[1].prefab.path
+        if path not in dme.types:
+            yield Finding(
+                "istype_missing_path",
+                f"istype() against nonexistent type {path}",
+                walk.source_loc(match),
+                match.proc,
+            )
+
+
+RULES: dict[str, Callable[[DME, _Walk], Iterable[Finding]]] = {
+    "missing_parent_call": missing_parent_call,
+    "sleep_in_should_not_sleep": sleep_in_should_not_sleep,
+    "del_usage": del_usage,
+    "spawn_usage": spawn_usage,
+    "unused_local": unused_local,
+    "unreachable_code": unreachable_code,
+    "assignment_in_condition": assignment_in_condition,
+    "istype_missing_path": istype_missing_path,
+}
+"""Every built-in rule, by name. :func:`run` passes them a shared walk of the procs."""
+
+
+def run(dme: DME, rules: list[str | Callable] | None = None) -> list[Finding]:
+    """
+    Runs each of *rules* over *dme* and returns their findings, ordered by
+    file and position. Rules may be given by name or as functions; by
+    default, every rule in :data:`RULES` is run.
+    """
+    if rules is None:
+        rules = list(RULES)
+
+    # The built-in rules share a single walk over the procs.
+    walk = None
+    findings = []
+    for rule in rules:
+        if isinstance(rule, str):
+            if rule not in RULES:
+                raise ValueError(f"unknown lint rule {rule!r}")
+            rule = RULES[rule]
+        if rule in RULES.values():
+            walk = walk or _Walk(dme)
+            findings.extend(rule(dme, walk))
+        else:
+            findings.extend(rule(dme))
+
+    findings.sort(
+        key=lambda f: (str(f.source_loc.file_path), f.source_loc.line, f.source_loc.column, f.rule)
+    )
+    return findings
@@ -2,33 +2,52 @@ extern crate dreammaker;
 
 use std::collections::HashMap;
 
+use defines::{DefineDecl, DefineRecord};
 use dreammaker::{
     FileId, FileList, Location,
     ast::{Spanned, Statement},
     objtree::NodeIndex,
 };
-use nodes::{Node, OriginalSourceLocation};
+use nodes::{Node, OriginalSourceLocation, SwitchCase};
 use pyo3::{
     Bound, IntoPyObject, IntoPyObjectExt, Py, PyAny, PyRef, PyResult, Python, create_exception,
     exceptions::{PyException, PyKeyError, PyOSError, PyRuntimeError, PyValueError},
     pyclass, pymethods,
-    types::{PyAnyMethods, PyList, PyString, PyStringMethods},
+    types::{PyAnyMethods, PyDict, PyDictMethods, PyList, PyString, PyStringMethods},
 };
 
 use crate::{
+    dmm::Dmm,
     helpers,
     path::{self, Path},
     typedecl::{TypeDecl, VarDecl},
 };
 
+pub mod callgraph;
+pub mod cfg;
+pub mod defines;
+pub mod evaluate;
 pub mod expr_parse;
+pub mod export;
 pub mod expr_walk;
 pub mod expression;
+pub mod inference;
 pub mod node_parse;
 pub mod node_walk;
 pub mod nodes;
 pub mod operators;
 pub mod prefab;
+pub mod proc_walk;
+pub mod query;
+pub mod references;
+pub mod resolve;
+pub mod sleep;
+pub mod snippet;
+pub mod span;
+pub mod type_index;
+pub mod unparse;
+pub mod unused;
+pub mod visitor;
 
 create_exception!(avulto.exceptions, EmptyProcError, PyException);
 create_exception!(avulto.exceptions, MissingTypeError, PyException);
@@ -61,35 +80,11 @@ impl DmeTypeAccessor {
 #[pymethods]
 impl DmeTypeAccessor {
     fn __getitem__(&self, path: &Bound<PyAny>, py: Python<'_>) -> PyResult<Py<TypeDecl>> {
-        let dme = self.dme.bind(py).borrow();
+        let dme = self.dme.bind(py);
         if let Ok((obj_path, search_string)) = self.convert_path(path) {
-            match dme.objtree.find(&search_string) {
-                Some(type_ref) => {
-                    let type_ref_index = type_ref.index();
-                    let osl = Some(OriginalSourceLocation::from_location(&type_ref.location));
-                    let source_loc = Some(
-                        dme.populate_source_loc(&osl, py)
-                            .into_pyobject(py)
-                            .unwrap()
-                            .unbind(),
-                    );
-                    let dme = dme
-                        .into_pyobject(py)
-                        .expect("passing dme")
-                        .clone()
-                        .as_unbound()
-                        .clone_ref(py)
-                        .into_any();
-                    Ok(TypeDecl {
-                        dme,
-                        path: Path::make_trusted(obj_path.as_str()),
-                        node_index: type_ref_index,
-                        source_loc,
-                    }
-                    .into_pyobject(py)
-                    .expect("building typedecl")
-                    .into())
-                }
+            let index = dme.borrow().objtree.find(&search_string).map(|ty| ty.index());
+            match index {
+                Some(index) => Py::new(py, TypeDecl::build(dme, index, py)?),
                 None => Err(PyKeyError::new_err(format!(
                     "unrecognized path {}",
                     obj_path
@@ -120,6 +115,10 @@ pub struct Dme {
     filepath: Py<PyAny>,
     procs_parsed: bool,
     pub(crate) file_data: Py<FileData>,
+    defines: Vec<DefineRecord>,
+    pub(crate) type_index: type_index::TypeIndex,
+    pub(crate) source_texts: span::SourceTexts,
+    pub(crate) define_exprs: evaluate::DefineExprs,
 }
 
 #[pyclass]
@@ -181,22 +180,7 @@ impl FileData {
 
 impl Dme {
     fn collect_child_paths(&self, needle: &Path, strict: bool, out: &mut Vec<Path>) {
-        for ty in self.objtree.iter_types() {
-            // special handling for root
-            if ty.path.is_empty() && needle.abs.eq("/") {
-                if !strict {
-                    out.push(Path::root());
-                }
-                continue;
-            }
-            let trusted = Path::make_trusted(&ty.path.clone());
-            if needle.internal_parent_of_string(&trusted.abs, strict) {
-                out.push(trusted);
-            }
-        }
-
-        out.sort();
-        out.dedup();
+        self.type_index.subtype_paths(needle, strict, out);
     }
 
     pub fn populate_source_loc(
@@ -241,8 +225,7 @@ impl Dme {
         py: Python<'_>,
     ) -> PyResult<()> {
         let node = Node::from_statement(py, &stmt.elem, Some(stmt.location));
-        Node::walk(node.bind(py), &self_.into_pyobject(py).unwrap(), walker, py)?;
-        Ok(())
+        proc_walk::walk_ast(&self_.into_pyobject(py).unwrap(), walker, node.bind(py).as_any())
     }
 
     pub fn walk_proc(
@@ -262,6 +245,10 @@ impl Dme {
         let type_def = &objtree[node_index];
         if let Some(proc) = type_def.procs.get(&proc_name) {
             if let Some(ref code) = proc.value[proc_index].code {
+                let dme = self_.into_pyobject(py).unwrap();
+                for default in proc_walk::param_defaults(&proc.value[proc_index], py)? {
+                    proc_walk::walk_ast(&dme, walker, &default)?;
+                }
                 for stmt in code.iter() {
                     Dme::walk_stmt(self_.into_pyobject(py).unwrap().borrow(), stmt, walker, py)?;
                 }
@@ -281,65 +268,146 @@ impl Dme {
         Ok(())
     }
 
-    pub fn get_var_decl(
+    /// Finds the type declaring the var `name`, starting at `node_index` and
+    /// walking up through its parents.
+    fn find_var_declaring_type(&self, name: &str, node_index: NodeIndex) -> Option<NodeIndex> {
+        resolve::find_var_declaration(&self.objtree, node_index, name)
+    }
+
+    /// Builds the [VarDecl] for the var `name` whose value is set on
+    /// `value_index`. Values which SpacemanDMM did not fold are evaluated in
+    /// the context of `context_index` for `evaluated_val`, and left out of
+    /// `const_val`.
+    pub fn build_var_decl(
         &self,
-        name: String,
-        node_index: NodeIndex,
-        parents: bool,
+        name: &str,
+        value_index: NodeIndex,
+        context_index: NodeIndex,
         py: Python<'_>,
-    ) -> PyResult<Py<PyAny>> {
+    ) -> PyResult<VarDecl> {
         let objtree = &self.objtree;
-        let type_def = &objtree[node_index];
+        let type_def = &objtree[value_index];
+        let var = &type_def.vars[name];
+
+        let declaring_index = self.find_var_declaring_type(name, value_index);
+        let declaration = declaring_index.and_then(|idx| objtree[idx].vars[name].declaration.as_ref());
+        let declared_type = declaration
+            .filter(|decl| !decl.var_type.type_path.is_empty())
+            .map(|decl| Path::from_tree_path(&decl.var_type.type_path));
+        let flags = declaration.map(|decl| decl.var_type.flags);
+        let input_type = declaration
+            .filter(|decl| !decl.var_type.input_type.is_empty())
+            .map(|decl| decl.var_type.input_type.to_string());
+        let var_type = declaration.map(|decl| {
+            let mut out = String::new();
+            for (set, flag_name) in [
+                (decl.var_type.flags.is_static(), "static"),
+                (decl.var_type.flags.is_const(), "const"),
+                (decl.var_type.flags.is_tmp(), "tmp"),
+                (decl.var_type.flags.is_final(), "final"),
+                (decl.var_type.flags.is_private(), "private"),
+                (decl.var_type.flags.is_protected(), "protected"),
+            ] {
+                if set {
+                    out.push('/');
+                    out.push_str(flag_name);
+                }
+            }
+            for part in decl.var_type.type_path.iter() {
+                out.push('/');
+                out.push_str(part);
+            }
+            out
+        });
+
+        let const_val = var
+            .value
+            .constant
+            .as_ref()
+            .map(helpers::constant_to_python_value);
+        let evaluated_val = match (&const_val, &var.value.expression) {
+            (Some(const_val), _) => Some(const_val.clone_ref(py)),
+            (None, Some(expr)) => {
+                let expr = expression::Expression::parse(py, expr)
+                    .into_pyobject(py)?
+                    .unbind();
+                evaluate::Evaluator::new(self, Some(context_index), py)
+                    .eval(&expr)
+                    .and_then(|value| value.to_python(py))
+                    .ok()
+            }
+            (None, None) => None,
+        };
 
-        if let Some(var) = type_def.vars.get(&name) {
-            let declared_type = var
-                .declaration
-                .as_ref()
-                .map(|decl| Path::from_tree_path(&decl.var_type.type_path));
-            let const_val = var
-                .value
-                .constant
-                .as_ref()
-                .map(helpers::constant_to_python_value);
-            let mut source_loc: Option<Py<PyAny>> = None;
-            if !var.value.location.is_builtins() {
-                let osl = Some(OriginalSourceLocation::from_location(&var.value.location));
+        let mut source_loc: Option<Py<PyAny>> = None;
+        if !var.value.location.is_builtins() {
+            let osl = Some(OriginalSourceLocation::from_location(&var.value.location));
+            source_loc = Some(
+                self.populate_source_loc(&osl, py)
+                    .into_pyobject(py)
+                    .unwrap()
+                    .unbind(),
+            );
+        } else if let Some(decl) = &var.declaration {
+            if !decl.location.is_builtins() {
+                let osl = Some(OriginalSourceLocation::from_location(&decl.location));
                 source_loc = Some(
                     self.populate_source_loc(&osl, py)
                         .into_pyobject(py)
                         .unwrap()
                         .unbind(),
                 );
-            } else if let Some(decl) = &var.declaration {
-                if !decl.location.is_builtins() {
-                    let osl = Some(OriginalSourceLocation::from_location(&decl.location));
-                    source_loc = Some(
-                        self.populate_source_loc(&osl, py)
-                            .into_pyobject(py)
-                            .unwrap()
-                            .unbind(),
-                    );
-                }
             }
-            return VarDecl {
-                name,
-                type_path: Path::make_trusted(&type_def.path).into_py_any(py).unwrap(),
-                declared_type,
-                const_val,
-                source_loc,
-            }
-            .into_py_any(py);
         }
 
-        if parents && !type_def.is_root() {
-            if let Some(parent_type_index) = type_def.parent_type_index() {
-                return self.get_var_decl(name, parent_type_index, parents, py);
+        Ok(VarDecl {
+            name: name.to_string(),
+            type_path: Path::make_trusted(&type_def.path).into_py_any(py).unwrap(),
+            declaring_type: declaring_index.map(|idx| Path::make_trusted(&objtree[idx].path)),
+            declared_type,
+            const_val,
+            evaluated_val,
+            source_loc,
+            is_static: flags.is_some_and(|f| f.is_static()),
+            is_const: flags.is_some_and(|f| f.is_const()),
+            is_tmp: flags.is_some_and(|f| f.is_tmp()),
+            is_final: flags.is_some_and(|f| f.is_final()),
+            is_private: flags.is_some_and(|f| f.is_private()),
+            is_protected: flags.is_some_and(|f| f.is_protected()),
+            input_type,
+            var_type,
+        })
+    }
+
+    pub fn get_var_decl(
+        &self,
+        name: String,
+        node_index: NodeIndex,
+        parents: bool,
+        py: Python<'_>,
+    ) -> PyResult<Py<PyAny>> {
+        let objtree = &self.objtree;
+        let mut index = node_index;
+
+        loop {
+            let type_def = &objtree[index];
+            if type_def.vars.contains_key(&name) {
+                return self
+                    .build_var_decl(&name, index, node_index, py)?
+                    .into_py_any(py);
+            }
+            if !parents || type_def.is_root() {
+                break;
+            }
+            match type_def.parent_type_index() {
+                Some(parent_type_index) => index = parent_type_index,
+                None => break,
             }
         }
 
         Err(PyRuntimeError::new_err(format!(
             "cannot find value for {}/{}",
-            type_def.path, name
+            objtree[index].path, name
         )))
     }
 }
@@ -347,8 +415,15 @@ impl Dme {
 #[pymethods]
 impl Dme {
     #[staticmethod]
-    #[pyo3(signature = (filename, parse_procs=false))]
-    fn from_file(filename: &Bound<PyAny>, parse_procs: bool, py: Python<'_>) -> PyResult<Dme> {
+    #[pyo3(signature = (filename, parse_procs=false, defines=None, extra_includes=None, exclude=None))]
+    fn from_file(
+        filename: &Bound<PyAny>,
+        parse_procs: bool,
+        defines: Option<&Bound<PyDict>>,
+        extra_includes: Option<Vec<String>>,
+        exclude: Option<Vec<String>>,
+        py: Python<'_>,
+    ) -> PyResult<Dme> {
         let path = if let Ok(path) = filename.extract::<std::path::PathBuf>() {
             path
         } else if let Ok(pystr) = filename.cast::<PyString>() {
@@ -364,40 +439,106 @@ impl Dme {
             return Err(PyOSError::new_err(format!("file not found: {:?}", path)));
         }
         let ctx = dreammaker::Context::default();
-        let pp = match dreammaker::preprocessor::Preprocessor::new(&ctx, path.clone()) {
-            Ok(pp) => pp,
-            Err(e) => {
+        let mut pp = if extra_includes.is_none() && exclude.is_none() {
+            match dreammaker::preprocessor::Preprocessor::new(&ctx, path.clone()) {
+                Ok(pp) => pp,
+                Err(e) => {
+                    return Err(PyOSError::new_err(format!(
+                        "error opening {:?}: {}",
+                        path, e
+                    )));
+                }
+            }
+        } else {
+            let contents = match std::fs::read(&path) {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(e) => {
+                    return Err(PyOSError::new_err(format!(
+                        "error opening {:?}: {}",
+                        path, e
+                    )));
+                }
+            };
+            let buffer = defines::build_environment_buffer(
+                &contents,
+                &extra_includes.unwrap_or_default(),
+                &exclude.unwrap_or_default(),
+            )?;
+            dreammaker::preprocessor::Preprocessor::from_buffer(&ctx, path.clone(), buffer)
+        };
+        if let Some(defines) = defines {
+            // The defines are read as a file of their own before the
+            // environment, so that its line numbers are unchanged.
+            let buffer = defines::build_define_buffer(defines)?;
+            let file = std::path::PathBuf::from(defines::DEFINES_FILE);
+            if let Err(e) = pp.push_file(file, std::io::Cursor::new(buffer.into_bytes())) {
                 return Err(PyOSError::new_err(format!(
-                    "error opening {:?}: {}",
-                    path, e
+                    "error reading defines: {}",
+                    e
                 )));
             }
-        };
-        let indents = dreammaker::indents::IndentProcessor::new(&ctx, pp);
-        let mut parser = dreammaker::parser::Parser::new(&ctx, indents);
-        if parse_procs {
-            parser.enable_procs();
         }
 
-        let (fatal_errored, tree) = parser.parse_object_tree_2();
+        let (fatal_errored, tree) = {
+            let indents = dreammaker::indents::IndentProcessor::new(&ctx, &mut pp);
+            let mut parser = dreammaker::parser::Parser::new(&ctx, indents);
+            if parse_procs {
+                parser.enable_procs();
+            }
+            parser.parse_object_tree_2()
+        };
         if fatal_errored {
             return Err(PyRuntimeError::new_err(format!(
                 "failed to parse DME environment {}",
                 filename
             )));
         }
+        let define_history = pp.finalize();
 
         let pathlib_path = pathlib.call_method1(pyo3::intern!(py, "Path"), (path,))?;
         let dme = Dme {
+            type_index: type_index::TypeIndex::build(&tree),
             objtree: tree,
             filepath: pathlib_path.into(),
             procs_parsed: parse_procs,
             file_data: Py::new(py, FileData::from_file_list(ctx.file_list(), py))
                 .expect("passing file list"),
+            defines: defines::collect_defines(&define_history),
+            source_texts: Default::default(),
+            define_exprs: Default::default(),
         };
         Ok(dme)
     }
 
+    #[getter]
+    fn get_defines(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let out = PyDict::new(py);
+        for define in self.defines.iter() {
+            let osl = Some(OriginalSourceLocation::from_location(&define.location));
+            out.set_item(
+                define.name.as_str(),
+                DefineDecl {
+                    name: define.name.clone(),
+                    value: define.value.clone(),
+                    args: define.args.clone(),
+                    is_variadic: define.variadic,
+                    is_undefined: define.undefined,
+                    source_loc: self.populate_source_loc(&osl, py),
+                },
+            )?;
+        }
+
+        Ok(out.unbind())
+    }
+
+    fn expand_macro(&self, text: &str, py: Python<'_>) -> PyResult<String> {
+        let env_file = self
+            .filepath
+            .extract::<std::path::PathBuf>(py)
+            .unwrap_or_default();
+        Ok(defines::expand_macro(&self.defines, env_file, text))
+    }
+
     #[getter]
     fn get_types(self_: PyRef<'_, Self>, py: Python<'_>) -> PyResult<Py<DmeTypeAccessor>> {
         Py::new(
@@ -448,6 +589,90 @@ impl Dme {
         Ok(PyList::new(py, out)?.unbind().clone_ref(py))
     }
 
+    /// Build the static call graph of every proc with a body.
+    fn call_graph(slf: &Bound<'_, Self>) -> PyResult<callgraph::CallGraph> {
+        callgraph::CallGraph::build(slf)
+    }
+
+    /// Find every location referring to a type, proc or var.
+    fn find_references(&self, target: &Bound<PyAny>, py: Python<'_>) -> PyResult<Vec<Py<PyAny>>> {
+        references::find_references_py(self, target, py)
+    }
+
+    /// Walk the body of every proc definition on `filter` and its subtypes
+    /// with `walker`, which may also be a list of walkers sharing the walk.
+    #[pyo3(signature = (walker, filter=None, include_builtins=false))]
+    fn walk_procs(
+        slf: &Bound<'_, Self>,
+        walker: &Bound<PyAny>,
+        filter: Option<&Bound<PyAny>>,
+        include_builtins: bool,
+        py: Python<'_>,
+    ) -> PyResult<()> {
+        proc_walk::walk_procs(slf, walker, filter, include_builtins, py)
+    }
+
+    /// Find every node in the proc bodies on `filter` and its subtypes which
+    /// matches the `ast.Pattern` `pattern`, optionally only in procs named
+    /// `proc`.
+    #[pyo3(signature = (pattern, filter=None, proc=None, include_builtins=false))]
+    fn query<'py>(
+        slf: &Bound<'py, Self>,
+        pattern: &Bound<'py, PyAny>,
+        filter: Option<&Bound<'py, PyAny>>,
+        proc: Option<&str>,
+        include_builtins: bool,
+        py: Python<'py>,
+    ) -> PyResult<Bound<'py, PyList>> {
+        query::query(slf, pattern, filter, proc, include_builtins, py)
+    }
+
+    /// Serialise `prefix` and its subtypes, with their vars and procs, as
+    /// JSON or as a Graphviz DOT graph of the type tree.
+    #[pyo3(signature = (format="json", prefix=None, include_vars=true, include_procs=true))]
+    fn export_types(
+        slf: &Bound<'_, Self>,
+        format: &str,
+        prefix: Option<&Bound<PyAny>>,
+        include_vars: bool,
+        include_procs: bool,
+        py: Python<'_>,
+    ) -> PyResult<String> {
+        export::export_types(slf, format, prefix, include_vars, include_procs, py)
+    }
+
+    /// Find the text `expr` was parsed from, as the location of its start
+    /// and the byte offsets of its start and end in that file.
+    fn source_span(
+        &self,
+        expr: &Bound<expression::Expression>,
+        py: Python<'_>,
+    ) -> PyResult<Option<(FilledSourceLocation, usize, usize)>> {
+        span::source_span(self, expr, py)
+    }
+
+    /// The location of `node`, or None if it has no location of its own.
+    fn source_loc(&self, node: &Bound<PyAny>, py: Python<'_>) -> PyResult<Option<Py<PyAny>>> {
+        if node.is_instance_of::<SwitchCase>() {
+            return Ok(None);
+        }
+        let loc = node
+            .getattr("source_loc")?
+            .extract::<Option<Py<OriginalSourceLocation>>>()?;
+        Ok(loc.is_some().then(|| self.populate_source_loc(&loc, py)))
+    }
+
+    /// Report the procs, vars and types which are never used. Types placed
+    /// on any of `dmms` count as used.
+    #[pyo3(signature = (dmms=None))]
+    fn unused(
+        slf: &Bound<'_, Self>,
+        dmms: Option<Vec<PyRef<'_, Dmm>>>,
+        py: Python<'_>,
+    ) -> PyResult<unused::UnusedReport> {
+        unused::find_unused(slf, dmms, py)
+    }
+
     fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
         Ok(format!(
             "<DME {}>",
@@ -0,0 +1,491 @@
+use std::collections::{HashMap, HashSet};
+
+use dreammaker::{
+    Location,
+    ast::{Follow, Spanned, Term},
+    objtree::{NodeIndex, ObjectTree},
+};
+use pyo3::{
+    Bound, Py, PyAny, PyRef, PyResult, Python, exceptions::PyRuntimeError, pyclass, pymethods,
+    types::PyAnyMethods,
+};
+
+use crate::typedecl::ProcDecl;
+
+use super::{
+    Dme,
+    nodes::OriginalSourceLocation,
+    resolve::{self, PrefabRef, ProcKey, Scope, Visitor},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallKind {
+    /// A call by name, either on `src` or on another object.
+    Call,
+    /// A call to the current proc with `.()`.
+    SelfCall,
+    /// A call to the overridden proc with `..()`.
+    ParentCall,
+    /// A reference to a proc without calling it, such as `.proc/foo` or
+    /// `/obj::foo()`, as used for callbacks and signals.
+    ProcReference,
+    /// A call through `call()`.
+    Dynamic,
+}
+
+impl CallKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallKind::Call => "call",
+            CallKind::SelfCall => "self_call",
+            CallKind::ParentCall => "parent_call",
+            CallKind::ProcReference => "proc_reference",
+            CallKind::Dynamic => "dynamic",
+        }
+    }
+}
+
+pub struct CallEdge {
+    pub caller: ProcKey,
+    /// The definition which is statically called, or `None` if it could not
+    /// be resolved.
+    pub callee: Option<ProcKey>,
+    pub name: String,
+    pub kind: CallKind,
+    pub location: Location,
+}
+
+pub struct CallCollector<'a> {
+    caller: &'a ProcKey,
+    edges: &'a mut Vec<CallEdge>,
+}
+
+impl<'a> CallCollector<'a> {
+    pub fn new(caller: &'a ProcKey, edges: &'a mut Vec<CallEdge>) -> Self {
+        CallCollector { caller, edges }
+    }
+
+    pub fn push(&mut self, callee: Option<ProcKey>, name: &str, kind: CallKind, location: Location) {
+        self.edges.push(CallEdge {
+            caller: self.caller.clone(),
+            callee,
+            name: name.to_string(),
+            kind,
+            location,
+        });
+    }
+}
+
+impl Visitor for CallCollector<'_> {
+    fn visit_term(&mut self, scope: &Scope, term: &Spanned<Term>) {
+        let objtree = scope.objtree;
+        match &term.elem {
+            Term::Call(name, _) => {
+                let callee = resolve::find_proc(objtree, scope.type_index, name.as_str());
+                self.push(callee, name.as_str(), CallKind::Call, term.location);
+            }
+            Term::GlobalCall(name, _) => {
+                let callee = resolve::find_proc(objtree, objtree.root().index(), name.as_str());
+                self.push(callee, name.as_str(), CallKind::Call, term.location);
+            }
+            Term::SelfCall(_) => {
+                let callee = Some(self.caller.clone());
+                let name = self.caller.name.clone();
+                self.push(callee, &name, CallKind::SelfCall, term.location);
+            }
+            Term::ParentCall(_) => {
+                let callee = resolve::parent_proc(objtree, self.caller);
+                let name = self.caller.name.clone();
+                self.push(callee, &name, CallKind::ParentCall, term.location);
+            }
+            Term::Prefab(prefab) => {
+                if let PrefabRef::Proc(type_index, name) =
+                    resolve::resolve_prefab(objtree, prefab, scope.type_index)
+                {
+                    let callee = resolve::find_proc(objtree, type_index, &name);
+                    self.push(callee, &name, CallKind::ProcReference, term.location);
+                }
+            }
+            Term::DynamicCall(..) => {
+                self.push(None, "call", CallKind::Dynamic, term.location);
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_follow(&mut self, scope: &Scope, base: Option<NodeIndex>, follow: &Spanned<Follow>) {
+        let (name, kind) = match &follow.elem {
+            Follow::Call(_, name, _) => (name.as_str(), CallKind::Call),
+            Follow::ProcReference(name) => (name.as_str(), CallKind::ProcReference),
+            _ => return,
+        };
+        let callee = base.and_then(|base| resolve::find_proc(scope.objtree, base, name));
+        self.push(callee, name, kind, follow.location);
+    }
+}
+
+/// Collects the calls made in the body of the proc definition `key`.
+pub fn collect_calls(objtree: &ObjectTree, key: &ProcKey, edges: &mut Vec<CallEdge>) {
+    resolve::walk_proc(objtree, key, &mut CallCollector::new(key, edges));
+}
+
+/// Returns every proc definition with a body, in object tree order.
+pub fn procs_with_code(objtree: &ObjectTree) -> Vec<ProcKey> {
+    let mut out = Vec::new();
+    for ty in objtree.iter_types() {
+        let mut names: Vec<&String> = ty.procs.keys().collect();
+        names.sort();
+        for name in names {
+            for (proc_index, proc_value) in ty.procs[name].value.iter().enumerate() {
+                if proc_value.code.is_some() && !proc_value.location.is_builtins() {
+                    out.push(ProcKey {
+                        type_index: ty.index(),
+                        name: name.clone(),
+                        proc_index,
+                    });
+                }
+            }
+        }
+    }
+    out
+}
+
+/// A name for a proc definition which is unique within the object tree,
+/// such as `/obj/item/proc/attack` or `/mob/verb/say`. Later definitions on
+/// the same type are suffixed with their index, e.g. `/obj/item/proc/attack#1`.
+pub fn proc_label(objtree: &ObjectTree, key: &ProcKey) -> String {
+    let type_def = &objtree[key.type_index];
+    let type_path = if type_def.is_root() {
+        ""
+    } else {
+        type_def.path.as_str()
+    };
+    let kind = if resolve::is_verb(objtree, key) {
+        "verb"
+    } else {
+        "proc"
+    };
+    if key.proc_index == 0 {
+        format!("{}/{}/{}", type_path, kind, key.name)
+    } else {
+        format!("{}/{}/{}#{}", type_path, kind, key.name, key.proc_index)
+    }
+}
+
+/// Escapes `text` for use inside a quoted DOT string.
+pub fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn quote_dot(text: &str) -> String {
+    format!("\"{}\"", escape_dot(text))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Builds the `ProcDecl` for `key`, reusing one already built for the same
+/// definition.
+fn proc_decl(
+    dme: &Bound<Dme>,
+    key: &ProcKey,
+    cache: &mut HashMap<ProcKey, Py<ProcDecl>>,
+    py: Python<'_>,
+) -> PyResult<Py<ProcDecl>> {
+    if let Some(decl) = cache.get(key) {
+        return Ok(decl.clone_ref(py));
+    }
+    let decl = Py::new(
+        py,
+        ProcDecl::build(dme, key.type_index, &key.name, key.proc_index, py)?,
+    )?;
+    cache.insert(key.clone(), decl.clone_ref(py));
+    Ok(decl)
+}
+
+/// Builds the Python-facing `CallSite` for `edge`.
+pub fn call_site(
+    dme: &Bound<Dme>,
+    edge: &CallEdge,
+    cache: &mut HashMap<ProcKey, Py<ProcDecl>>,
+    py: Python<'_>,
+) -> PyResult<CallSite> {
+    let osl = Some(OriginalSourceLocation::from_location(&edge.location));
+    Ok(CallSite {
+        caller: proc_decl(dme, &edge.caller, cache, py)?,
+        callee: match &edge.callee {
+            Some(callee) => Some(proc_decl(dme, callee, cache, py)?),
+            None => None,
+        },
+        name: edge.name.clone(),
+        kind: edge.kind.as_str().to_string(),
+        source_loc: dme.borrow().populate_source_loc(&osl, py),
+    })
+}
+
+#[pyclass(module = "avulto")]
+pub struct CallSite {
+    #[pyo3(get)]
+    pub caller: Py<ProcDecl>,
+    #[pyo3(get)]
+    pub callee: Option<Py<ProcDecl>>,
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub kind: String,
+    #[pyo3(get)]
+    pub source_loc: Py<PyAny>,
+}
+
+#[pymethods]
+impl CallSite {
+    #[getter]
+    fn is_resolved(&self) -> bool {
+        self.callee.is_some()
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let callee = match &self.callee {
+            Some(callee) => callee.bind(py).repr()?.to_string(),
+            None => format!("<unresolved {}>", self.name),
+        };
+        Ok(format!(
+            "<CallSite {} -> {} at {}>",
+            self.caller.bind(py).repr()?,
+            callee,
+            self.source_loc.bind(py).str()?
+        ))
+    }
+}
+
+#[pyclass(module = "avulto")]
+pub struct CallGraph {
+    dme: Py<Dme>,
+    procs: Vec<ProcKey>,
+    edges: Vec<CallEdge>,
+    by_caller: HashMap<ProcKey, Vec<usize>>,
+    by_callee: HashMap<ProcKey, Vec<usize>>,
+}
+
+impl CallGraph {
+    pub fn build(dme: &Bound<Dme>) -> PyResult<CallGraph> {
+        let dme_ref = dme.borrow();
+        if !dme_ref.procs_parsed {
+            return Err(PyRuntimeError::new_err(
+                "parse_procs=True was not included in DME's constructor",
+            ));
+        }
+
+        let objtree = &dme_ref.objtree;
+        let procs = procs_with_code(objtree);
+        let mut edges = Vec::new();
+        for key in procs.iter() {
+            collect_calls(objtree, key, &mut edges);
+        }
+
+        let mut by_caller: HashMap<ProcKey, Vec<usize>> = HashMap::new();
+        let mut by_callee: HashMap<ProcKey, Vec<usize>> = HashMap::new();
+        for (i, edge) in edges.iter().enumerate() {
+            by_caller.entry(edge.caller.clone()).or_default().push(i);
+            if let Some(callee) = &edge.callee {
+                by_callee.entry(callee.clone()).or_default().push(i);
+            }
+        }
+
+        Ok(CallGraph {
+            dme: dme.clone().unbind(),
+            procs,
+            edges,
+            by_caller,
+            by_callee,
+        })
+    }
+
+    pub fn edges(&self) -> &[CallEdge] {
+        &self.edges
+    }
+
+    pub fn calls_from_key(&self, key: &ProcKey) -> impl Iterator<Item = &CallEdge> {
+        self.by_caller
+            .get(key)
+            .into_iter()
+            .flatten()
+            .map(|i| &self.edges[*i])
+    }
+
+    pub fn calls_to_key(&self, key: &ProcKey) -> impl Iterator<Item = &CallEdge> {
+        self.by_callee
+            .get(key)
+            .into_iter()
+            .flatten()
+            .map(|i| &self.edges[*i])
+    }
+
+    fn call_sites<'a>(
+        &self,
+        edges: impl Iterator<Item = &'a CallEdge>,
+        py: Python<'_>,
+    ) -> PyResult<Vec<CallSite>> {
+        let dme = self.dme.bind(py);
+        let mut cache = HashMap::new();
+        edges
+            .map(|edge| call_site(dme, edge, &mut cache, py))
+            .collect()
+    }
+
+    fn proc_decls<'a>(
+        &self,
+        keys: impl Iterator<Item = &'a ProcKey>,
+        py: Python<'_>,
+    ) -> PyResult<Vec<Py<ProcDecl>>> {
+        let mut seen = HashSet::new();
+        let mut cache = HashMap::new();
+        let mut out = Vec::new();
+        for key in keys {
+            if seen.insert(key) {
+                out.push(proc_decl(self.dme.bind(py), key, &mut cache, py)?);
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[pymethods]
+impl CallGraph {
+    /// Returns every call site in the graph. If `resolved` is set, only
+    /// resolved or unresolved calls are returned.
+    #[pyo3(signature = (resolved=None))]
+    fn calls(&self, resolved: Option<bool>, py: Python<'_>) -> PyResult<Vec<CallSite>> {
+        self.call_sites(
+            self.edges
+                .iter()
+                .filter(|edge| resolved.is_none_or(|r| edge.callee.is_some() == r)),
+            py,
+        )
+    }
+
+    /// Returns the call sites in the body of `proc`.
+    fn calls_from(&self, proc: PyRef<'_, ProcDecl>, py: Python<'_>) -> PyResult<Vec<CallSite>> {
+        self.call_sites(self.calls_from_key(&proc.key()), py)
+    }
+
+    /// Returns the call sites which resolve to `proc`.
+    fn calls_to(&self, proc: PyRef<'_, ProcDecl>, py: Python<'_>) -> PyResult<Vec<CallSite>> {
+        self.call_sites(self.calls_to_key(&proc.key()), py)
+    }
+
+    /// Returns the procs which call `proc`.
+    fn callers_of(&self, proc: PyRef<'_, ProcDecl>, py: Python<'_>) -> PyResult<Vec<Py<ProcDecl>>> {
+        let key = proc.key();
+        self.proc_decls(self.calls_to_key(&key).map(|edge| &edge.caller), py)
+    }
+
+    /// Returns the procs which `proc` resolvably calls.
+    fn callees_of(&self, proc: PyRef<'_, ProcDecl>, py: Python<'_>) -> PyResult<Vec<Py<ProcDecl>>> {
+        let key = proc.key();
+        self.proc_decls(
+            self.calls_from_key(&key).filter_map(|edge| edge.callee.as_ref()),
+            py,
+        )
+    }
+
+    fn to_dot(&self, py: Python<'_>) -> String {
+        let dme = self.dme.borrow(py);
+        let objtree = &dme.objtree;
+        let mut out = String::from("digraph calls {\n");
+        for key in self.procs.iter() {
+            out.push_str(&format!("  {};\n", quote_dot(&proc_label(objtree, key))));
+        }
+        let mut seen = HashSet::new();
+        for edge in self.edges.iter() {
+            let caller = proc_label(objtree, &edge.caller);
+            let callee = match &edge.callee {
+                Some(callee) => proc_label(objtree, callee),
+                None => format!("?{}", edge.name),
+            };
+            if !seen.insert((caller.clone(), callee.clone(), edge.kind)) {
+                continue;
+            }
+            let mut attrs = Vec::new();
+            if edge.callee.is_none() {
+                attrs.push("style=dashed");
+            }
+            if edge.kind == CallKind::ProcReference {
+                attrs.push("arrowhead=empty");
+            }
+            out.push_str(&format!("  {} -> {}", quote_dot(&caller), quote_dot(&callee)));
+            if !attrs.is_empty() {
+                out.push_str(&format!(" [{}]", attrs.join(", ")));
+            }
+            out.push_str(";\n");
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn to_graphml(&self, py: Python<'_>) -> String {
+        let dme = self.dme.borrow(py);
+        let objtree = &dme.objtree;
+
+        let mut nodes: Vec<(String, bool)> = Vec::new();
+        let mut node_ids = HashSet::new();
+        for key in self.procs.iter() {
+            let label = proc_label(objtree, key);
+            if node_ids.insert(label.clone()) {
+                nodes.push((label, true));
+            }
+        }
+        let mut edges = Vec::new();
+        let mut seen = HashSet::new();
+        for edge in self.edges.iter() {
+            let caller = proc_label(objtree, &edge.caller);
+            let callee = match &edge.callee {
+                Some(callee) => proc_label(objtree, callee),
+                None => format!("?{}", edge.name),
+            };
+            if node_ids.insert(callee.clone()) {
+                nodes.push((callee.clone(), edge.callee.is_some()));
+            }
+            if seen.insert((caller.clone(), callee.clone(), edge.kind)) {
+                edges.push((caller, callee, edge.kind));
+            }
+        }
+
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"resolved\" for=\"node\" attr.name=\"resolved\" attr.type=\"boolean\"/>\n",
+            "  <key id=\"kind\" for=\"edge\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <graph id=\"calls\" edgedefault=\"directed\">\n",
+        ));
+        for (id, resolved) in nodes.iter() {
+            out.push_str(&format!(
+                "    <node id=\"{}\"><data key=\"resolved\">{}</data></node>\n",
+                escape_xml(id),
+                resolved
+            ));
+        }
+        for (caller, callee, kind) in edges.iter() {
+            out.push_str(&format!(
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"kind\">{}</data></edge>\n",
+                escape_xml(caller),
+                escape_xml(callee),
+                kind.as_str()
+            ));
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+
+    fn __len__(&self) -> usize {
+        self.edges.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<CallGraph {} procs, {} calls>", self.procs.len(), self.edges.len())
+    }
+}
@@ -0,0 +1,569 @@
+use std::collections::HashMap;
+
+use pyo3::{
+    Bound, Py, PyResult, Python, exceptions::PyRuntimeError, pyclass, pymethods,
+    types::{PyAnyMethods, PyList, PyListMethods},
+};
+
+use super::{
+    Dme, EmptyProcError,
+    expression::Expression,
+    nodes::{Node, PyExpr},
+    resolve::ProcKey,
+};
+
+struct Block {
+    nodes: Vec<Py<Node>>,
+    condition: Option<PyExpr>,
+}
+
+/// The blocks which `break` and `continue` in a loop jump from.
+struct Loop {
+    label: Option<String>,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// The blocks which explicitly throw inside a `try` block.
+struct Try {
+    first_block: usize,
+    throws: Vec<usize>,
+}
+
+/// Builds a control-flow graph from a proc body.
+///
+/// Blocks are created lazily: `current` is the block statements are being
+/// appended to, and `pending` holds the edges which will lead into the next
+/// block created. A statement which follows a `return` or other jump starts
+/// a block with no predecessors.
+struct Builder<'py> {
+    py: Python<'py>,
+    blocks: Vec<Block>,
+    edges: Vec<(usize, usize, &'static str)>,
+    current: Option<usize>,
+    pending: Vec<(usize, &'static str)>,
+    to_exit: Vec<(usize, &'static str)>,
+    loops: Vec<Loop>,
+    tries: Vec<Try>,
+    labels: HashMap<String, usize>,
+    gotos: Vec<(usize, String)>,
+    /// The label of a `Label` statement, for the loop which starts it.
+    label: Option<String>,
+}
+
+fn ident_name(expr: &PyExpr, py: Python<'_>) -> Option<String> {
+    match &*expr.borrow(py) {
+        Expression::Identifier { name, .. } => Some(name.clone()),
+        _ => None,
+    }
+}
+
+impl<'py> Builder<'py> {
+    fn new(py: Python<'py>) -> Self {
+        let mut builder = Builder {
+            py,
+            blocks: Vec::new(),
+            edges: Vec::new(),
+            current: None,
+            pending: Vec::new(),
+            to_exit: Vec::new(),
+            loops: Vec::new(),
+            tries: Vec::new(),
+            labels: HashMap::new(),
+            gotos: Vec::new(),
+            label: None,
+        };
+        builder.start_block();
+        builder
+    }
+
+    fn new_block(&mut self) -> usize {
+        self.blocks.push(Block {
+            nodes: Vec::new(),
+            condition: None,
+        });
+        self.blocks.len() - 1
+    }
+
+    fn connect_pending(&mut self, target: usize, kind: Option<&'static str>) {
+        for (source, pending_kind) in std::mem::take(&mut self.pending) {
+            self.edges.push((source, target, kind.unwrap_or(pending_kind)));
+        }
+    }
+
+    /// Ends the current block, falling through to whatever comes next.
+    fn fall_through(&mut self) {
+        if let Some(block) = self.current.take() {
+            self.pending.push((block, "next"));
+        }
+    }
+
+    /// Starts a new block, which the current block falls through to.
+    fn start_block(&mut self) -> usize {
+        self.fall_through();
+        let block = self.new_block();
+        self.connect_pending(block, None);
+        self.current = Some(block);
+        block
+    }
+
+    /// Appends `node` to the current block and ends it.
+    fn end_with(&mut self, node: &Bound<Node>) -> usize {
+        let block = match self.current {
+            Some(block) => block,
+            None => self.start_block(),
+        };
+        self.blocks[block].nodes.push(node.clone().unbind());
+        self.current = None;
+        block
+    }
+
+    fn append(&mut self, node: &Bound<Node>) {
+        let block = self.end_with(node);
+        self.current = Some(block);
+    }
+
+    fn find_loop(&mut self, label: Option<&PyExpr>) -> Option<&mut Loop> {
+        let name = label.and_then(|label| ident_name(label, self.py));
+        match name {
+            Some(name) => self
+                .loops
+                .iter_mut()
+                .rev()
+                .find(|l| l.label.as_deref() == Some(name.as_str())),
+            None => self.loops.last_mut(),
+        }
+    }
+
+    fn build_block(&mut self, block: &[Py<Node>]) {
+        for node in block.iter() {
+            self.build(node.bind(self.py));
+        }
+    }
+
+    /// Builds the body of a loop which is entered from `header` along an
+    /// edge of kind `enter`.
+    fn build_loop_body(
+        &mut self,
+        header: usize,
+        enter: &'static str,
+        block: &[Py<Node>],
+        label: Option<String>,
+    ) -> Loop {
+        self.pending = vec![(header, enter)];
+        self.loops.push(Loop {
+            label,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+        self.build_block(block);
+        self.fall_through();
+        self.loops.pop().unwrap()
+    }
+
+    /// Starts a loop header block containing `node`.
+    fn loop_header(&mut self, node: &Bound<Node>, condition: Option<&PyExpr>) -> usize {
+        let header = self.start_block();
+        self.blocks[header].nodes.push(node.clone().unbind());
+        self.blocks[header].condition = condition.map(|c| c.clone_ref(self.py));
+        self.current = None;
+        header
+    }
+
+    /// Finishes a loop whose body ends in `pending`, jumping back to `target`.
+    fn close_loop(&mut self, body: Loop, target: usize, exits: Vec<(usize, &'static str)>) {
+        self.connect_pending(target, Some("loop"));
+        for block in body.continues {
+            self.edges.push((block, target, "continue"));
+        }
+        self.pending = exits;
+        self.pending
+            .extend(body.breaks.into_iter().map(|block| (block, "break")));
+    }
+
+    fn throw_from(&mut self, block: usize) {
+        match self.tries.last_mut() {
+            Some(try_) => try_.throws.push(block),
+            None => self.to_exit.push((block, "throw")),
+        }
+    }
+
+    fn build(&mut self, node: &Bound<Node>) {
+        let py = self.py;
+        let label = self.label.take();
+        match node.get() {
+            Node::Return { .. } => {
+                let block = self.end_with(node);
+                self.to_exit.push((block, "return"));
+            }
+            Node::Throw { .. } | Node::Crash { .. } => {
+                let block = self.end_with(node);
+                self.throw_from(block);
+            }
+            Node::Break { label, .. } => {
+                let block = self.end_with(node);
+                if let Some(target) = self.find_loop(label.as_ref()) {
+                    target.breaks.push(block);
+                }
+            }
+            Node::Continue { name, .. } => {
+                let block = self.end_with(node);
+                if let Some(target) = self.find_loop(name.as_ref()) {
+                    target.continues.push(block);
+                }
+            }
+            Node::Goto { label, .. } => {
+                let block = self.end_with(node);
+                if let Some(name) = ident_name(label, py) {
+                    self.gotos.push((block, name));
+                }
+            }
+            Node::Label { name, block, .. } => {
+                let start = self.start_block();
+                self.blocks[start].nodes.push(node.clone().unbind());
+                if let Some(name) = ident_name(name, py) {
+                    self.labels.insert(name.clone(), start);
+                    self.label = Some(name);
+                }
+                self.build_block(block);
+            }
+            Node::If {
+                if_arms, else_arm, ..
+            } => {
+                let mut test = self.end_with(node);
+                let mut exits = Vec::new();
+                for (i, (condition, block)) in if_arms.iter().enumerate() {
+                    if i > 0 {
+                        let next = self.new_block();
+                        self.edges.push((test, next, "false"));
+                        test = next;
+                    }
+                    self.blocks[test].condition = Some(condition.clone_ref(py));
+                    self.pending = vec![(test, "true")];
+                    self.build_block(block);
+                    self.fall_through();
+                    exits.append(&mut self.pending);
+                }
+                match else_arm {
+                    Some(block) => {
+                        self.pending = vec![(test, "false")];
+                        self.build_block(block);
+                        self.fall_through();
+                        exits.append(&mut self.pending);
+                    }
+                    None => exits.push((test, "false")),
+                }
+                self.pending = exits;
+            }
+            Node::While {
+                condition, block, ..
+            } => {
+                let header = self.loop_header(node, Some(condition));
+                let body = self.build_loop_body(header, "true", block, label);
+                self.close_loop(body, header, vec![(header, "false")]);
+            }
+            Node::DoWhile {
+                condition, block, ..
+            } => {
+                let start = self.start_block();
+                self.loops.push(Loop {
+                    label,
+                    breaks: Vec::new(),
+                    continues: Vec::new(),
+                });
+                self.build_block(block);
+                self.fall_through();
+                let body = self.loops.pop().unwrap();
+                let test = self.loop_header(node, Some(condition));
+                self.edges.push((test, start, "loop"));
+                for block in body.continues {
+                    self.edges.push((block, test, "continue"));
+                }
+                self.pending = vec![(test, "false")];
+                self.pending
+                    .extend(body.breaks.into_iter().map(|block| (block, "break")));
+            }
+            Node::ForInfinite { block, .. } => {
+                let header = self.loop_header(node, None);
+                let body = self.build_loop_body(header, "next", block, label);
+                self.close_loop(body, header, Vec::new());
+            }
+            Node::ForList { block, .. }
+            | Node::ForKeyValue { block, .. }
+            | Node::ForRange { block, .. } => {
+                let header = self.loop_header(node, None);
+                let body = self.build_loop_body(header, "true", block, label);
+                self.close_loop(body, header, vec![(header, "false")]);
+            }
+            Node::ForLoop {
+                init,
+                test,
+                inc,
+                block,
+                ..
+            } => {
+                if let Some(init) = init {
+                    self.append(init.bind(py));
+                }
+                let header = self.loop_header(node, test.as_ref());
+                let mut body = self.build_loop_body(header, "true", block, label);
+                let exits = if test.is_some() {
+                    vec![(header, "false")]
+                } else {
+                    Vec::new()
+                };
+                match inc {
+                    Some(inc) => {
+                        let step = self.start_block();
+                        self.blocks[step].nodes.push(inc.clone_ref(py));
+                        for block in std::mem::take(&mut body.continues) {
+                            self.edges.push((block, step, "continue"));
+                        }
+                        self.fall_through();
+                        self.close_loop(body, header, exits);
+                    }
+                    None => self.close_loop(body, header, exits),
+                }
+            }
+            Node::Switch {
+                input,
+                cases,
+                default,
+                ..
+            } => {
+                let header = self.end_with(node);
+                self.blocks[header].condition = Some(input.clone_ref(py));
+                let mut exits = Vec::new();
+                for case in cases.iter() {
+                    self.pending = vec![(header, "case")];
+                    self.build_block(&case.borrow(py).block);
+                    self.fall_through();
+                    exits.append(&mut self.pending);
+                }
+                match default {
+                    Some(block) => {
+                        self.pending = vec![(header, "default")];
+                        self.build_block(block);
+                        self.fall_through();
+                        exits.append(&mut self.pending);
+                    }
+                    None => exits.push((header, "default")),
+                }
+                self.pending = exits;
+            }
+            Node::TryCatch {
+                try_block,
+                catch_block,
+                ..
+            } => {
+                self.append(node);
+                self.fall_through();
+                self.tries.push(Try {
+                    first_block: self.blocks.len(),
+                    throws: Vec::new(),
+                });
+                self.build_block(try_block);
+                self.fall_through();
+                let try_ = self.tries.pop().unwrap();
+                let exits = std::mem::take(&mut self.pending);
+
+                for block in try_.first_block..self.blocks.len() {
+                    if !try_.throws.contains(&block) {
+                        self.pending.push((block, "exception"));
+                    }
+                }
+                self.pending
+                    .extend(try_.throws.into_iter().map(|block| (block, "throw")));
+                self.build_block(catch_block);
+                self.fall_through();
+                self.pending.extend(exits);
+            }
+            Node::Spawn { block, .. } => {
+                // The spawned block runs separately, so its jumps out of
+                // loops, throws and returns do not affect the rest of the
+                // proc, and it does not lead to the exit.
+                let spawner = self.end_with(node);
+                let loops = std::mem::take(&mut self.loops);
+                let tries = std::mem::take(&mut self.tries);
+                let to_exit = std::mem::take(&mut self.to_exit);
+                self.pending = vec![(spawner, "spawn")];
+                self.build_block(block);
+                self.current = None;
+                self.loops = loops;
+                self.tries = tries;
+                self.to_exit = to_exit;
+                self.pending = vec![(spawner, "next")];
+            }
+            _ => self.append(node),
+        }
+    }
+
+    fn finish(mut self) -> (Vec<Block>, Vec<(usize, usize, &'static str)>, usize) {
+        self.fall_through();
+        let exit = self.new_block();
+        self.connect_pending(exit, None);
+        for (block, kind) in std::mem::take(&mut self.to_exit) {
+            self.edges.push((block, exit, kind));
+        }
+        for (block, name) in std::mem::take(&mut self.gotos) {
+            if let Some(target) = self.labels.get(&name) {
+                self.edges.push((block, *target, "goto"));
+            }
+        }
+        (self.blocks, self.edges, exit)
+    }
+}
+
+#[pyclass(module = "avulto")]
+pub struct BasicBlock {
+    #[pyo3(get)]
+    id: usize,
+    /// The statements in the block. A statement which branches, such as an
+    /// `If` or a loop, is the last in its block.
+    #[pyo3(get)]
+    nodes: Py<PyList>,
+    /// The expression choosing which successor is taken, if any.
+    #[pyo3(get)]
+    condition: Option<PyExpr>,
+    #[pyo3(get)]
+    successors: Vec<usize>,
+    #[pyo3(get)]
+    predecessors: Vec<usize>,
+}
+
+#[pymethods]
+impl BasicBlock {
+    fn __repr__(&self, py: Python<'_>) -> String {
+        format!(
+            "<BasicBlock {} nodes={} successors={:?}>",
+            self.id,
+            self.nodes.bind(py).len(),
+            self.successors
+        )
+    }
+}
+
+#[pyclass(module = "avulto")]
+pub struct ControlFlowGraph {
+    #[pyo3(get)]
+    blocks: Py<PyList>,
+    /// Each edge as a `(source, target, kind)` tuple.
+    #[pyo3(get)]
+    edges: Vec<(usize, usize, String)>,
+    #[pyo3(get)]
+    entry: usize,
+    #[pyo3(get)]
+    exit: usize,
+    successors: Vec<Vec<usize>>,
+}
+
+impl ControlFlowGraph {
+    /// Builds the graph of the proc definition `key`.
+    pub fn for_proc(dme: &Dme, key: &ProcKey, py: Python<'_>) -> PyResult<ControlFlowGraph> {
+        if !dme.procs_parsed {
+            return Err(PyRuntimeError::new_err(
+                "parse_procs=True was not included in DME's constructor",
+            ));
+        }
+        let type_def = &dme.objtree[key.type_index];
+        let Some(code) = &type_def.procs[&key.name].value[key.proc_index].code else {
+            return Err(EmptyProcError::new_err(format!(
+                "no code statements found in proc {} on type {}",
+                key.name, type_def.path
+            )));
+        };
+        let nodes: Vec<Py<Node>> = code
+            .iter()
+            .map(|stmt| Node::from_statement(py, &stmt.elem, Some(stmt.location)))
+            .collect();
+        Self::build(&nodes, py)
+    }
+
+    pub fn build(code: &[Py<Node>], py: Python<'_>) -> PyResult<ControlFlowGraph> {
+        let mut builder = Builder::new(py);
+        builder.build_block(code);
+        let (blocks, edges, exit) = builder.finish();
+
+        let mut successors = vec![Vec::new(); blocks.len()];
+        let mut predecessors = vec![Vec::new(); blocks.len()];
+        for (source, target, _) in edges.iter() {
+            if !successors[*source].contains(target) {
+                successors[*source].push(*target);
+                predecessors[*target].push(*source);
+            }
+        }
+
+        let mut py_blocks = Vec::new();
+        for (id, block) in blocks.into_iter().enumerate() {
+            py_blocks.push(Py::new(
+                py,
+                BasicBlock {
+                    id,
+                    nodes: PyList::new(py, block.nodes)?.unbind(),
+                    condition: block.condition,
+                    successors: successors[id].clone(),
+                    predecessors: predecessors[id].clone(),
+                },
+            )?);
+        }
+
+        Ok(ControlFlowGraph {
+            blocks: PyList::new(py, py_blocks)?.unbind(),
+            edges: edges
+                .into_iter()
+                .map(|(source, target, kind)| (source, target, kind.to_string()))
+                .collect(),
+            entry: 0,
+            exit,
+            successors,
+        })
+    }
+
+    fn reachable(&self) -> Vec<bool> {
+        let mut seen = vec![false; self.successors.len()];
+        let mut stack = vec![self.entry];
+        while let Some(block) = stack.pop() {
+            if !std::mem::replace(&mut seen[block], true) {
+                stack.extend(self.successors[block].iter().copied());
+            }
+        }
+        seen
+    }
+}
+
+#[pymethods]
+impl ControlFlowGraph {
+    /// Returns the blocks containing statements which can never run.
+    fn unreachable_blocks(&self, py: Python<'_>) -> PyResult<Vec<Py<BasicBlock>>> {
+        let blocks = self.blocks.bind(py);
+        let mut out = Vec::new();
+        for (id, reachable) in self.reachable().into_iter().enumerate() {
+            let block = blocks.get_item(id)?.cast_into::<BasicBlock>()?;
+            if !reachable && block.borrow().nodes.bind(py).len() > 0 {
+                out.push(block.unbind());
+            }
+        }
+        Ok(out)
+    }
+
+    /// Whether the end of the proc can be reached without a `return`,
+    /// `throw` or `CRASH()`.
+    fn falls_off_end(&self) -> bool {
+        let reachable = self.reachable();
+        self.edges.iter().any(|(source, target, kind)| {
+            *target == self.exit && kind != "return" && kind != "throw" && reachable[*source]
+        })
+    }
+
+    fn __len__(&self) -> usize {
+        self.successors.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "<ControlFlowGraph blocks={} edges={}>",
+            self.successors.len(),
+            self.edges.len()
+        )
+    }
+}
@@ -0,0 +1,264 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use dreammaker::{
+    Context, Location,
+    lexer::Token,
+    preprocessor::{Define, DefineHistory, Preprocessor},
+};
+use pyo3::{
+    Bound, Py, PyAny, PyResult, Python,
+    exceptions::PyValueError,
+    pyclass, pymethods,
+    types::{PyAnyMethods, PyBool, PyDict, PyDictMethods, PyString},
+};
+use regex::Regex;
+
+lazy_static! {
+    static ref DEFINE_NAME_RE: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+}
+
+const EXPANSION_MARKER: &str = "__avulto_expansion_begin__";
+
+/// A single `#define` as recorded by the preprocessor.
+pub struct DefineRecord {
+    pub name: String,
+    pub value: String,
+    /// The parameter names of a function-like define, or `None` for constants.
+    pub args: Option<Vec<String>>,
+    pub variadic: bool,
+    pub location: Location,
+    /// Whether the define was `#undef`'d before the end of the environment.
+    pub undefined: bool,
+}
+
+impl DefineRecord {
+    fn to_directive(&self) -> String {
+        match &self.args {
+            None => format!("#define {} {}", self.name, self.value),
+            Some(args) => {
+                let mut params = args.join(", ");
+                if self.variadic {
+                    params.push_str("...");
+                }
+                format!("#define {}({}) {}", self.name, params, self.value)
+            }
+        }
+    }
+}
+
+#[pyclass(module = "avulto")]
+pub struct DefineDecl {
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub value: String,
+    #[pyo3(get)]
+    pub args: Option<Vec<String>>,
+    #[pyo3(get)]
+    pub is_variadic: bool,
+    #[pyo3(get)]
+    pub is_undefined: bool,
+    #[pyo3(get)]
+    pub source_loc: Py<PyAny>,
+}
+
+#[pymethods]
+impl DefineDecl {
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        match &self.args {
+            None => Ok(format!("<Define {} {}>", self.name, self.value)),
+            Some(args) => Ok(format!(
+                "<Define {}({}) {}>",
+                self.name,
+                args.join(", "),
+                self.value
+            )),
+        }
+    }
+}
+
+pub fn tokens_to_string(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens.iter() {
+        match token {
+            Token::Ident(ident, whitespace) => {
+                out.push_str(ident);
+                if *whitespace {
+                    out.push(' ');
+                }
+            }
+            other => out.push_str(other.to_string().as_str()),
+        }
+    }
+
+    out.trim_end().to_string()
+}
+
+/// Collect every define the preprocessor saw, in the order they were
+/// defined. Defines which are still in effect at the end of the environment
+/// have a history range reaching the end of the input. A define ending
+/// earlier was either replaced by a later `#define` of the same name, which
+/// starts before the replaced range ends, or `#undef`'d.
+pub fn collect_defines(history: &DefineHistory) -> Vec<DefineRecord> {
+    let end = history.iter().map(|(range, _)| range.end).max();
+    let mut starts: HashMap<&str, Vec<Location>> = HashMap::new();
+    for (range, (name, _)) in history.iter() {
+        starts.entry(name.as_str()).or_default().push(range.start);
+    }
+
+    let mut out: Vec<DefineRecord> = Vec::new();
+    for (range, (name, define)) in history.iter() {
+        let redefined = starts[name.as_str()]
+            .iter()
+            .any(|start| *start > range.start && *start <= range.end);
+        let (args, variadic, subst) = match define {
+            Define::Constant { subst, .. } => (None, false, subst),
+            Define::Function {
+                params,
+                subst,
+                variadic,
+                ..
+            } => (
+                Some(params.iter().map(|p| p.to_string()).collect()),
+                *variadic,
+                subst,
+            ),
+        };
+        out.push(DefineRecord {
+            name: name.clone(),
+            value: tokens_to_string(subst),
+            args,
+            variadic,
+            location: range.start,
+            undefined: Some(range.end) != end && !redefined,
+        });
+    }
+    out.sort_by_key(|define| define.location);
+
+    out
+}
+
+/// Runs `text` through a fresh preprocessor which has every define in
+/// `defines` that is still in effect.
+pub fn expand_macro(defines: &[DefineRecord], env_file: PathBuf, text: &str) -> String {
+    let mut buffer = String::new();
+    for define in defines.iter().filter(|d| !d.undefined) {
+        buffer.push_str(define.to_directive().as_str());
+        buffer.push('\n');
+    }
+    buffer.push_str(EXPANSION_MARKER);
+    buffer.push('\n');
+    buffer.push_str(text);
+
+    let ctx = Context::default();
+    let pp = Preprocessor::from_buffer(&ctx, env_file, buffer);
+    let tokens: Vec<Token> = pp
+        .map(|located| located.token)
+        .skip_while(|token| !matches!(token, Token::Ident(ident, _) if ident == EXPANSION_MARKER))
+        .skip(1)
+        .filter(|token| !matches!(token, Token::Eof))
+        .collect();
+
+    tokens_to_string(&tokens).trim().to_string()
+}
+
+/// Turns a glob such as `code/modules/awaymissions/**` into a regex matching
+/// forward-slash separated include paths.
+fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    let normalized = glob.replace('\\', "/");
+    let mut chars = normalized.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    pattern.push_str(".*");
+                } else {
+                    pattern.push_str("[^/]*");
+                }
+            }
+            '?' => pattern.push_str("[^/]"),
+            _ => pattern.push_str(regex::escape(c.to_string().as_str()).as_str()),
+        }
+    }
+    pattern.push('$');
+    Regex::new(pattern.as_str())
+}
+
+fn define_value_to_string(value: &Bound<PyAny>) -> PyResult<String> {
+    if value.is_none() {
+        Ok(String::new())
+    } else if value.is_instance_of::<PyBool>() {
+        Ok(if value.extract::<bool>()? { "1" } else { "0" }.to_string())
+    } else if let Ok(pystr) = value.cast::<PyString>() {
+        Ok(pystr.to_string())
+    } else {
+        Ok(value.str()?.to_string())
+    }
+}
+
+/// The name of the file the defines passed to `DME.from_file` are read from.
+pub const DEFINES_FILE: &str = "(defines)";
+
+/// Builds the text of a file defining each of `defines`.
+pub fn build_define_buffer(defines: &Bound<PyDict>) -> PyResult<String> {
+    let mut out = String::new();
+    for (key, value) in defines.iter() {
+        let name = key.str()?.to_string();
+        if !DEFINE_NAME_RE.is_match(&name) {
+            return Err(PyValueError::new_err(format!(
+                "invalid define name {:?}",
+                name
+            )));
+        }
+        out.push_str(format!("#define {} {}\n", name, define_value_to_string(&value)?).as_str());
+    }
+    Ok(out)
+}
+
+/// Builds the text of a DME with excluded `#include`s commented out, and any
+/// extra `#include`s appended.
+pub fn build_environment_buffer(
+    contents: &str,
+    extra_includes: &[String],
+    exclude: &[String],
+) -> PyResult<String> {
+    let mut out = String::new();
+
+    let mut exclude_res: Vec<Regex> = Vec::new();
+    for glob in exclude.iter() {
+        match glob_to_regex(glob) {
+            Ok(re) => exclude_res.push(re),
+            Err(e) => {
+                return Err(PyValueError::new_err(format!(
+                    "invalid exclude pattern {:?}: {}",
+                    glob, e
+                )));
+            }
+        }
+    }
+
+    for line in contents.lines() {
+        let trimmed = line.trim_start();
+        if let Some(include) = trimmed.strip_prefix("#include") {
+            let include_path = include.trim().trim_matches('"').replace('\\', "/");
+            if exclude_res.iter().any(|re| re.is_match(&include_path)) {
+                // Comment the line out instead of dropping it to keep line numbers stable.
+                out.push_str("// ");
+            }
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    for include in extra_includes.iter() {
+        out.push_str(format!("#include \"{}\"\n", include).as_str());
+    }
+
+    Ok(out)
+}
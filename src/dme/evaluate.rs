@@ -0,0 +1,550 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use dreammaker::{constants::Constant as DmConstant, objtree::NodeIndex};
+use pyo3::{
+    Bound, IntoPyObject, IntoPyObjectExt, Py, PyAny, PyErr, PyResult, Python, create_exception,
+    exceptions::PyException,
+    types::{PyAnyMethods, PyDict, PyDictMethods, PyList, PyListMethods, PyString},
+};
+
+use crate::{dmlist::DmList, path::Path, typedecl::TypeDecl};
+
+use super::{
+    Dme, MissingTypeError,
+    expression::{Constant, Expression},
+    nodes::PyExpr,
+    operators::{BinaryOperator, UnaryOperator},
+    prefab::Prefab,
+    snippet,
+};
+
+create_exception!(avulto.exceptions, EvaluationError, PyException);
+
+/// How many var and define references may be followed while evaluating a
+/// single expression, which also guards against cyclic references.
+const MAX_DEPTH: usize = 64;
+
+/// The parsed value of each define looked up so far, for `Dme::define_exprs`.
+/// Defines which don't exist or can't be evaluated are cached as `None` or
+/// the error.
+pub type DefineExprs = Mutex<HashMap<String, Option<PyResult<PyExpr>>>>;
+
+/// The result of folding an expression.
+#[derive(Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Num(f32),
+    Str(String),
+    Resource(String),
+    Path(Path),
+    Prefab(Path, Vec<(String, Value)>),
+    List(Vec<(Value, Option<Value>)>),
+}
+
+impl Value {
+    pub fn from_constant(constant: &DmConstant) -> PyResult<Value> {
+        Ok(match constant {
+            DmConstant::Null(_) => Value::Null,
+            DmConstant::Float(f) => Value::Num(*f),
+            DmConstant::String(s) => Value::Str(s.to_string()),
+            DmConstant::Resource(s) => Value::Resource(s.to_string()),
+            DmConstant::List(list) => {
+                let mut out = vec![];
+                for (key, val) in list.iter() {
+                    let val = match val {
+                        Some(val) => Some(Value::from_constant(val)?),
+                        None => None,
+                    };
+                    out.push((Value::from_constant(key)?, val));
+                }
+                Value::List(out)
+            }
+            DmConstant::Prefab(pop) => {
+                let path = Path::from_tree_path(&pop.path);
+                if pop.vars.is_empty() {
+                    Value::Path(path)
+                } else {
+                    let mut vars = vec![];
+                    for (k, v) in pop.vars.iter() {
+                        vars.push((k.to_string(), Value::from_constant(v)?));
+                    }
+                    Value::Prefab(path, vars)
+                }
+            }
+            DmConstant::New { .. } | DmConstant::Call(..) => {
+                return Err(EvaluationError::new_err(format!(
+                    "cannot evaluate non-constant value {}",
+                    constant
+                )));
+            }
+        })
+    }
+
+    pub fn to_python(&self, py: Python<'_>) -> PyResult<Py<PyAny>> {
+        Ok(match self {
+            Value::Null => py.None(),
+            Value::Num(f) => {
+                // `i64::MAX as f32` rounds up to 2^63, so it is excluded.
+                if f.fract() == 0.0 && *f >= i64::MIN as f32 && *f < i64::MAX as f32 {
+                    (*f as i64).into_py_any(py)?
+                } else {
+                    f.into_py_any(py)?
+                }
+            }
+            Value::Str(s) | Value::Resource(s) => s.into_py_any(py)?,
+            Value::Path(p) => p.clone().into_py_any(py)?,
+            Value::Prefab(path, vars) => {
+                let mut out: Vec<Bound<PyDict>> = vec![];
+                for (k, v) in vars.iter() {
+                    let var = PyDict::new(py);
+                    var.set_item(k.as_str(), v.to_python(py)?)?;
+                    out.push(var);
+                }
+                Prefab {
+                    path: path.clone(),
+                    vars: PyList::new(py, out)?.into_any().unbind(),
+                }
+                .into_py_any(py)?
+            }
+            Value::List(items) => {
+                let mut keys: Vec<Py<PyAny>> = vec![];
+                let mut vals: Vec<Py<PyAny>> = vec![];
+                for (k, v) in items.iter() {
+                    keys.push(k.to_python(py)?);
+                    vals.push(match v {
+                        Some(v) => v.to_python(py)?,
+                        None => py.None(),
+                    });
+                }
+                Py::new(py, DmList { keys, vals })?.into_any()
+            }
+        })
+    }
+
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Num(f) => *f != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            _ => true,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Num(_) => "num",
+            Value::Str(_) => "text",
+            Value::Resource(_) => "resource",
+            Value::Path(_) | Value::Prefab(..) => "path",
+            Value::List(_) => "list",
+        }
+    }
+
+    fn num(&self) -> PyResult<f32> {
+        match self {
+            Value::Null => Ok(0.0),
+            Value::Num(f) => Ok(*f),
+            other => Err(EvaluationError::new_err(format!(
+                "expected a number, got {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    /// The text of this value when embedded in a string.
+    fn to_text(&self) -> PyResult<String> {
+        match self {
+            Value::Null => Ok(String::new()),
+            Value::Num(f) => Ok(if f.fract() == 0.0 && f.abs() < 1e7 {
+                format!("{}", *f as i32)
+            } else {
+                format!("{}", f)
+            }),
+            Value::Str(s) => Ok(s.clone()),
+            Value::Path(p) | Value::Prefab(p, _) => Ok(p.rel.clone()),
+            other => Err(EvaluationError::new_err(format!(
+                "cannot embed {} in a string",
+                other.type_name()
+            ))),
+        }
+    }
+}
+
+fn bool_value(b: bool) -> Value {
+    Value::Num(if b { 1.0 } else { 0.0 })
+}
+
+pub struct Evaluator<'a, 'py> {
+    dme: &'a Dme,
+    context: Option<NodeIndex>,
+    py: Python<'py>,
+    depth: usize,
+}
+
+impl<'a, 'py> Evaluator<'a, 'py> {
+    pub fn new(dme: &'a Dme, context: Option<NodeIndex>, py: Python<'py>) -> Self {
+        Evaluator {
+            dme,
+            context,
+            py,
+            depth: 0,
+        }
+    }
+
+    fn non_constant(&self, expr: &PyExpr) -> PyErr {
+        EvaluationError::new_err(format!("cannot evaluate non-constant expression {}", expr))
+    }
+
+    /// Evaluate an expression which was reached through a var or define
+    /// reference, tracking how deep the references go.
+    fn eval_reference(&mut self, expr: &PyExpr, name: &str) -> PyResult<Value> {
+        if self.depth >= MAX_DEPTH {
+            return Err(EvaluationError::new_err(format!(
+                "too many nested references evaluating {}",
+                name
+            )));
+        }
+        self.depth += 1;
+        let result = self.eval(expr);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parse the value of the last definition of `name`, if it is defined.
+    fn parse_define(&self, name: &str) -> Option<PyResult<PyExpr>> {
+        let define = self
+            .dme
+            .defines
+            .iter()
+            .rev()
+            .find(|d| d.name == name && !d.undefined && d.args.is_none())?;
+        if define.value.is_empty() {
+            return Some(Err(EvaluationError::new_err(format!(
+                "define {} has no value",
+                name
+            ))));
+        }
+        Some(snippet::parse_expression(&define.value, self.py))
+    }
+
+    fn lookup_define(&mut self, name: &str) -> Option<PyResult<Value>> {
+        let py = self.py;
+        let expr = {
+            let mut cache = self.dme.define_exprs.lock().unwrap();
+            if !cache.contains_key(name) {
+                cache.insert(name.to_string(), self.parse_define(name));
+            }
+            match cache.get(name)? {
+                Some(Ok(expr)) => expr.clone_ref(py),
+                Some(Err(err)) => return Some(Err(err.clone_ref(py))),
+                None => return None,
+            }
+        };
+        Some(self.eval_reference(&expr, name))
+    }
+
+    fn lookup_var(&mut self, name: &str) -> PyResult<Value> {
+        let Some(mut idx) = self.context else {
+            return Err(EvaluationError::new_err(format!(
+                "cannot evaluate var {} without a context type",
+                name
+            )));
+        };
+        let dme = self.dme;
+        let objtree = &dme.objtree;
+        loop {
+            let type_def = &objtree[idx];
+            if let Some(var) = type_def.vars.get(name) {
+                if let Some(constant) = &var.value.constant {
+                    return Value::from_constant(constant);
+                }
+                if let Some(expr) = &var.value.expression {
+                    let expr: PyExpr = Expression::parse(self.py, expr)
+                        .into_pyobject(self.py)?
+                        .unbind();
+                    return self.eval_reference(&expr, name);
+                }
+                return Ok(Value::Null);
+            }
+            match type_def.parent_type_index() {
+                Some(parent) => idx = parent,
+                None => break,
+            }
+        }
+
+        Err(EvaluationError::new_err(format!(
+            "no var {} on {}",
+            name, objtree[self.context.unwrap()].path
+        )))
+    }
+
+    fn eval_args(&mut self, args: &[PyExpr]) -> PyResult<Vec<Value>> {
+        args.iter().map(|arg| self.eval(arg)).collect()
+    }
+
+    fn eval_prefab(&mut self, prefab: &Prefab) -> PyResult<Value> {
+        let mut vars = vec![];
+        let py = self.py;
+        for item in prefab.vars.bind(py).cast::<PyList>()?.iter() {
+            for (k, v) in item.cast::<PyDict>()?.iter() {
+                let expr: PyExpr = v.cast_into::<Expression>()?.unbind();
+                vars.push((k.str()?.to_string(), self.eval(&expr)?));
+            }
+        }
+        if vars.is_empty() {
+            Ok(Value::Path(prefab.path.clone()))
+        } else {
+            Ok(Value::Prefab(prefab.path.clone(), vars))
+        }
+    }
+
+    fn eval_binary(&mut self, op: &BinaryOperator, lhs: &PyExpr, rhs: &PyExpr) -> PyResult<Value> {
+        let lhs = self.eval(lhs)?;
+        // `&&` and `||` short circuit and produce one of their operands.
+        match op {
+            BinaryOperator::And if !lhs.truthy() => return Ok(lhs),
+            BinaryOperator::Or if lhs.truthy() => return Ok(lhs),
+            BinaryOperator::And | BinaryOperator::Or => return self.eval(rhs),
+            _ => {}
+        }
+        let rhs = self.eval(rhs)?;
+
+        Ok(match op {
+            BinaryOperator::Add => match (&lhs, &rhs) {
+                (Value::Str(a), b) => Value::Str(format!("{}{}", a, b.to_text()?)),
+                (Value::Null, Value::Str(b)) => Value::Str(b.clone()),
+                (Value::List(a), Value::List(b)) => {
+                    Value::List(a.iter().chain(b.iter()).cloned().collect())
+                }
+                _ => Value::Num(lhs.num()? + rhs.num()?),
+            },
+            BinaryOperator::Sub => Value::Num(lhs.num()? - rhs.num()?),
+            BinaryOperator::Mul => Value::Num(lhs.num()? * rhs.num()?),
+            BinaryOperator::Div => {
+                let divisor = rhs.num()?;
+                if divisor == 0.0 {
+                    return Err(EvaluationError::new_err("division by zero"));
+                }
+                Value::Num(lhs.num()? / divisor)
+            }
+            BinaryOperator::Pow => Value::Num(lhs.num()?.powf(rhs.num()?)),
+            BinaryOperator::Mod => {
+                let divisor = rhs.num()? as i32;
+                if divisor == 0 {
+                    return Err(EvaluationError::new_err("modulo by zero"));
+                }
+                Value::Num((lhs.num()? as i32).wrapping_rem(divisor) as f32)
+            }
+            BinaryOperator::FloatMod => {
+                let divisor = rhs.num()?;
+                if divisor == 0.0 {
+                    return Err(EvaluationError::new_err("modulo by zero"));
+                }
+                Value::Num(lhs.num()? % divisor)
+            }
+            BinaryOperator::Eq => bool_value(lhs == rhs),
+            BinaryOperator::NotEq | BinaryOperator::LessOrGreater => bool_value(lhs != rhs),
+            BinaryOperator::Equiv | BinaryOperator::NotEquiv => {
+                let equiv = match (&lhs, &rhs) {
+                    (Value::Str(a), Value::Str(b)) => a.eq_ignore_ascii_case(b),
+                    _ => lhs == rhs,
+                };
+                bool_value(equiv == (*op == BinaryOperator::Equiv))
+            }
+            BinaryOperator::Less
+            | BinaryOperator::Greater
+            | BinaryOperator::LessEq
+            | BinaryOperator::GreaterEq => {
+                let ordering = match (&lhs, &rhs) {
+                    (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+                    _ => lhs.num()?.partial_cmp(&rhs.num()?),
+                };
+                let Some(ordering) = ordering else {
+                    return Ok(bool_value(false));
+                };
+                bool_value(match op {
+                    BinaryOperator::Less => ordering.is_lt(),
+                    BinaryOperator::Greater => ordering.is_gt(),
+                    BinaryOperator::LessEq => ordering.is_le(),
+                    _ => ordering.is_ge(),
+                })
+            }
+            BinaryOperator::BitAnd => Value::Num(((lhs.num()? as i32) & (rhs.num()? as i32)) as f32),
+            BinaryOperator::BitOr => Value::Num(((lhs.num()? as i32) | (rhs.num()? as i32)) as f32),
+            BinaryOperator::BitXor => Value::Num(((lhs.num()? as i32) ^ (rhs.num()? as i32)) as f32),
+            BinaryOperator::LShift => {
+                Value::Num(((lhs.num()? as i32).wrapping_shl(rhs.num()? as u32)) as f32)
+            }
+            BinaryOperator::RShift => {
+                Value::Num(((lhs.num()? as i32).wrapping_shr(rhs.num()? as u32)) as f32)
+            }
+            BinaryOperator::And | BinaryOperator::Or => unreachable!(),
+            BinaryOperator::In | BinaryOperator::To => {
+                return Err(EvaluationError::new_err(format!(
+                    "cannot evaluate operator {:?}",
+                    op
+                )));
+            }
+        })
+    }
+
+    pub fn eval(&mut self, expr: &PyExpr) -> PyResult<Value> {
+        let py = self.py;
+        match expr.get() {
+            Expression::Constant { constant, .. } => match constant {
+                Constant::Null() => Ok(Value::Null),
+                Constant::Int(i) => Ok(Value::Num(*i as f32)),
+                Constant::Float(f) => Ok(Value::Num(*f)),
+                Constant::String(s) => Ok(Value::Str(s.clone())),
+                Constant::Resource(s) => Ok(Value::Resource(s.clone())),
+                Constant::Path(p) => Ok(Value::Path(p.clone())),
+                Constant::ProcMacro() | Constant::TypeMacro() | Constant::ImpliedTypeMacro() => {
+                    Err(self.non_constant(expr))
+                }
+            },
+            Expression::Identifier { name, .. } => match self.lookup_define(name) {
+                Some(result) => result,
+                None => self.lookup_var(name),
+            },
+            Expression::List { list, .. } => {
+                let mut items = vec![];
+                let list = list.borrow(py);
+                for (key, val) in list.keys.iter().zip(list.vals.iter()) {
+                    let key = key.cast_bound::<Expression>(py)?;
+                    let val = val.cast_bound::<Expression>(py)?;
+                    let has_value = !matches!(
+                        val.get(),
+                        Expression::Constant {
+                            constant: Constant::Null(),
+                            source_loc: None
+                        }
+                    );
+                    if !has_value {
+                        items.push((self.eval(&key.clone().unbind())?, None));
+                        continue;
+                    }
+                    // Bare identifiers used as keys are strings, as in `list(a = 1)`.
+                    let key = match key.get() {
+                        Expression::Identifier { name, .. } => Value::Str(name.clone()),
+                        _ => self.eval(&key.clone().unbind())?,
+                    };
+                    items.push((key, Some(self.eval(&val.clone().unbind())?)));
+                }
+                Ok(Value::List(items))
+            }
+            Expression::BinaryOp { op, lhs, rhs, .. } => self.eval_binary(op, lhs, rhs),
+            Expression::TernaryOp {
+                cond,
+                if_expr,
+                else_expr,
+                ..
+            } => {
+                if self.eval(cond)?.truthy() {
+                    self.eval(if_expr)
+                } else {
+                    self.eval(else_expr)
+                }
+            }
+            Expression::UnaryOp { expr: inner, unary_op, .. } => match unary_op {
+                UnaryOperator::Neg => Ok(Value::Num(-self.eval(inner)?.num()?)),
+                UnaryOperator::Not => Ok(bool_value(!self.eval(inner)?.truthy())),
+                UnaryOperator::BitNot => Ok(Value::Num(!(self.eval(inner)?.num()? as i32) as f32)),
+                _ => Err(self.non_constant(expr)),
+            },
+            Expression::InterpString {
+                first, token_pairs, ..
+            } => {
+                let mut out = String::new();
+                if let Constant::String(s) = first {
+                    out.push_str(s);
+                }
+                for (token_expr, token_str) in token_pairs.iter() {
+                    if let Some(token_expr) = token_expr {
+                        out.push_str(self.eval(token_expr)?.to_text()?.as_str());
+                    }
+                    if let Constant::String(s) = token_str.get() {
+                        out.push_str(s);
+                    }
+                }
+                Ok(Value::Str(out))
+            }
+            Expression::Prefab { prefab, .. } => self.eval_prefab(&prefab.borrow(py)),
+            Expression::Call { name, args, .. } => {
+                // `initial(foo)` and `initial(src.foo)` refer to the type's value for `foo`.
+                let is_initial = matches!(name.get(), Expression::Identifier { name, .. } if name == "initial");
+                if is_initial && args.len() == 1 {
+                    let var_name = match args[0].get() {
+                        Expression::Identifier { name, .. } => Some(name.clone()),
+                        Expression::Field { expr: field_expr, field, .. } => {
+                            let on_src = match field_expr {
+                                None => true,
+                                Some(e) => matches!(e.get(), Expression::Identifier { name, .. } if name == "src"),
+                            };
+                            match field.get() {
+                                Expression::Identifier { name, .. } if on_src => Some(name.clone()),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    };
+                    if let Some(var_name) = var_name {
+                        return self.lookup_var(&var_name);
+                    }
+                }
+                Err(self.non_constant(expr))
+            }
+            Expression::Pick { .. }
+            | Expression::AssignOp { .. }
+            | Expression::Locate { .. }
+            | Expression::Index { .. }
+            | Expression::Field { .. }
+            | Expression::StaticField { .. }
+            | Expression::SelfCall { .. }
+            | Expression::ParentCall { .. }
+            | Expression::ProcReference { .. }
+            | Expression::ExternalCall { .. }
+            | Expression::NewMiniExpr { .. }
+            | Expression::NewImplicit { .. }
+            | Expression::NewPrefab { .. }
+            | Expression::DynamicCall { .. }
+            | Expression::Input { .. }
+            | Expression::GlobalIdentifier { .. }
+            | Expression::GlobalCall { .. }
+            | Expression::As { .. } => Err(self.non_constant(expr)),
+        }
+    }
+}
+
+/// Resolve the `context_type` argument of `Expression.evaluate` to a type in
+/// the object tree.
+pub fn resolve_context(dme: &Dme, context_type: Option<&Bound<PyAny>>) -> PyResult<Option<NodeIndex>> {
+    let Some(context_type) = context_type else {
+        return Ok(None);
+    };
+    if context_type.is_none() {
+        return Ok(None);
+    }
+    if let Ok(typedecl) = context_type.cast::<TypeDecl>() {
+        return Ok(Some(typedecl.borrow().node_index));
+    }
+
+    let path = if let Ok(path) = context_type.extract::<Path>() {
+        path
+    } else if let Ok(pystr) = context_type.cast::<PyString>() {
+        Path::make_untrusted(pystr.to_str()?).map_err(MissingTypeError::new_err)?
+    } else {
+        return Err(MissingTypeError::new_err(format!(
+            "invalid context type {}",
+            context_type
+        )));
+    };
+    let search = if path.rel == "/" { "" } else { path.rel.as_str() };
+    match dme.objtree.find(search) {
+        Some(type_ref) => Ok(Some(type_ref.index())),
+        None => Err(MissingTypeError::new_err(format!(
+            "unrecognized path {}",
+            path.rel
+        ))),
+    }
+}
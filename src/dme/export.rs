@@ -0,0 +1,375 @@
+//! Serialises the object tree for `DME.export_types`.
+
+use std::collections::HashSet;
+
+use dreammaker::{
+    Location,
+    objtree::{NodeIndex, ObjectTree, Type},
+};
+use pyo3::{
+    Bound, IntoPyObject, PyAny, PyResult, Python, exceptions::PyValueError, types::PyAnyMethods,
+};
+
+use crate::{path::Path, typedecl::ProcDecl};
+
+use super::{
+    Dme,
+    callgraph::{escape_dot, quote_dot},
+    evaluate::{Evaluator, Value},
+    expression::Expression,
+    proc_walk, resolve, unparse,
+};
+
+struct SourcePos {
+    file: String,
+    line: u32,
+    column: u16,
+}
+
+struct VarEntry {
+    name: String,
+    /// Whether the var is declared on the type, rather than overridden.
+    declared: bool,
+    declared_type: Option<String>,
+    /// The DM source of the var's value, if it can be folded to a constant.
+    value: Option<String>,
+    source_loc: Option<SourcePos>,
+}
+
+struct ProcEntry {
+    name: String,
+    signature: String,
+    is_verb: bool,
+    is_override: bool,
+    source_loc: Option<SourcePos>,
+}
+
+struct TypeEntry {
+    path: String,
+    parent: Option<String>,
+    source_loc: Option<SourcePos>,
+    vars: Vec<VarEntry>,
+    procs: Vec<ProcEntry>,
+}
+
+fn type_path(type_def: &Type) -> String {
+    if type_def.is_root() {
+        "/".to_string()
+    } else {
+        Path::make_trusted(&type_def.path).rel
+    }
+}
+
+fn source_pos(dme: &Dme, location: Location, py: Python<'_>) -> PyResult<Option<SourcePos>> {
+    if location.is_builtins() {
+        return Ok(None);
+    }
+    let file_data = dme.file_data.borrow(py);
+    let Some(file) = file_data.file_ids.get(&location.file) else {
+        return Ok(None);
+    };
+    Ok(Some(SourcePos {
+        file: file.bind(py).str()?.to_string(),
+        line: location.line,
+        column: location.column,
+    }))
+}
+
+/// Renders `value` as DM source.
+fn render_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Num(f) => {
+            if f.fract() == 0.0 && f.abs() < 1e7 {
+                format!("{}", *f as i32)
+            } else {
+                unparse::float_to_string(*f)
+            }
+        }
+        Value::Str(s) => format!("\"{}\"", unparse::escape_string(s)),
+        Value::Resource(s) => format!("'{}'", s),
+        Value::Path(p) => p.rel.clone(),
+        Value::Prefab(p, vars) => {
+            let vars: Vec<String> = vars
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, render_value(value)))
+                .collect();
+            format!("{}{{{}}}", p.rel, vars.join("; "))
+        }
+        Value::List(items) => {
+            let items: Vec<String> = items
+                .iter()
+                .map(|(key, value)| match value {
+                    Some(value) => format!("{} = {}", render_value(key), render_value(value)),
+                    None => render_value(key),
+                })
+                .collect();
+            format!("list({})", items.join(", "))
+        }
+    }
+}
+
+fn declared_type(objtree: &ObjectTree, index: NodeIndex, name: &str) -> Option<String> {
+    let declaring = resolve::find_var_declaration(objtree, index, name)?;
+    let decl = objtree[declaring].vars[name].declaration.as_ref()?;
+    if decl.var_type.type_path.is_empty() {
+        None
+    } else {
+        Some(Path::from_tree_path(&decl.var_type.type_path).rel)
+    }
+}
+
+fn collect_vars(dme: &Dme, index: NodeIndex, py: Python<'_>) -> PyResult<Vec<VarEntry>> {
+    let objtree = &dme.objtree;
+    let type_def = &objtree[index];
+    let mut names: Vec<&String> = type_def.vars.keys().collect();
+    names.sort();
+
+    let mut out = Vec::new();
+    for name in names {
+        let var = &type_def.vars[name];
+        let location = match &var.declaration {
+            Some(decl) if var.value.location.is_builtins() => decl.location,
+            _ => var.value.location,
+        };
+        if location.is_builtins() {
+            continue;
+        }
+        let value = match (&var.value.constant, &var.value.expression) {
+            (Some(constant), _) => Value::from_constant(constant).ok(),
+            (None, Some(expr)) => {
+                let expr = Expression::parse(py, expr).into_pyobject(py)?.unbind();
+                Evaluator::new(dme, Some(index), py).eval(&expr).ok()
+            }
+            (None, None) => None,
+        };
+        out.push(VarEntry {
+            name: name.clone(),
+            declared: var.declaration.is_some(),
+            declared_type: declared_type(objtree, index, name),
+            value: value.as_ref().map(render_value),
+            source_loc: source_pos(dme, location, py)?,
+        });
+    }
+    Ok(out)
+}
+
+fn collect_procs(dme: &Bound<Dme>, index: NodeIndex, py: Python<'_>) -> PyResult<Vec<ProcEntry>> {
+    let definitions: Vec<(String, usize, Location)> = {
+        let objtree = &dme.borrow().objtree;
+        let type_def = &objtree[index];
+        let mut names: Vec<&String> = type_def.procs.keys().collect();
+        names.sort();
+        let mut definitions = Vec::new();
+        for name in names {
+            for (proc_index, proc_value) in type_def.procs[name].value.iter().enumerate() {
+                if !proc_value.location.is_builtins() {
+                    definitions.push((name.clone(), proc_index, proc_value.location));
+                }
+            }
+        }
+        definitions
+    };
+
+    let mut out = Vec::new();
+    for (name, proc_index, location) in definitions {
+        let decl = ProcDecl::build(dme, index, &name, proc_index, py)?;
+        out.push(ProcEntry {
+            signature: decl.signature(py)?,
+            is_verb: decl.is_verb,
+            is_override: decl.is_override,
+            source_loc: source_pos(&dme.borrow(), location, py)?,
+            name,
+        });
+    }
+    Ok(out)
+}
+
+fn collect_types(
+    dme: &Bound<Dme>,
+    prefix: &Path,
+    include_vars: bool,
+    include_procs: bool,
+    py: Python<'_>,
+) -> PyResult<Vec<TypeEntry>> {
+    let mut indexes: Vec<NodeIndex> = Vec::new();
+    {
+        let dme_ref = dme.borrow();
+        let mut paths: Vec<Path> = Vec::new();
+        dme_ref.collect_child_paths(prefix, false, &mut paths);
+        for path in paths {
+            let search_string = if path.rel.eq("/") { "" } else { path.rel.as_str() };
+            if let Some(ty) = dme_ref.objtree.find(search_string) {
+                indexes.push(ty.index());
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    for index in indexes {
+        let dme_ref = dme.borrow();
+        let objtree = &dme_ref.objtree;
+        let type_def = &objtree[index];
+        out.push(TypeEntry {
+            path: type_path(type_def),
+            parent: type_def
+                .parent_type_index()
+                .map(|parent| type_path(&objtree[parent])),
+            source_loc: source_pos(&dme_ref, type_def.location, py)?,
+            vars: if include_vars {
+                collect_vars(&dme_ref, index, py)?
+            } else {
+                Vec::new()
+            },
+            procs: if include_procs {
+                collect_procs(dme, index, py)?
+            } else {
+                Vec::new()
+            },
+        });
+    }
+    Ok(out)
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_opt_string(text: &Option<String>) -> String {
+    text.as_deref().map_or("null".to_string(), json_string)
+}
+
+fn json_source_loc(pos: &Option<SourcePos>) -> String {
+    match pos {
+        Some(pos) => format!(
+            "{{\"file\": {}, \"line\": {}, \"column\": {}}}",
+            json_string(&pos.file),
+            pos.line,
+            pos.column
+        ),
+        None => "null".to_string(),
+    }
+}
+
+fn to_json(types: &[TypeEntry], include_vars: bool, include_procs: bool) -> String {
+    let mut out = String::from("{\"types\": [\n");
+    for (i, ty) in types.iter().enumerate() {
+        out.push_str(&format!(
+            "{{\"path\": {}, \"parent\": {}, \"source_loc\": {}",
+            json_string(&ty.path),
+            json_opt_string(&ty.parent),
+            json_source_loc(&ty.source_loc)
+        ));
+        if include_vars {
+            let vars: Vec<String> = ty
+                .vars
+                .iter()
+                .map(|var| {
+                    format!(
+                        "{{\"name\": {}, \"declared\": {}, \"declared_type\": {}, \"value\": {}, \"source_loc\": {}}}",
+                        json_string(&var.name),
+                        var.declared,
+                        json_opt_string(&var.declared_type),
+                        json_opt_string(&var.value),
+                        json_source_loc(&var.source_loc)
+                    )
+                })
+                .collect();
+            out.push_str(&format!(", \"vars\": [{}]", vars.join(", ")));
+        }
+        if include_procs {
+            let procs: Vec<String> = ty
+                .procs
+                .iter()
+                .map(|proc| {
+                    format!(
+                        "{{\"name\": {}, \"signature\": {}, \"is_verb\": {}, \"is_override\": {}, \"source_loc\": {}}}",
+                        json_string(&proc.name),
+                        json_string(&proc.signature),
+                        proc.is_verb,
+                        proc.is_override,
+                        json_source_loc(&proc.source_loc)
+                    )
+                })
+                .collect();
+            out.push_str(&format!(", \"procs\": [{}]", procs.join(", ")));
+        }
+        out.push('}');
+        if i + 1 < types.len() {
+            out.push(',');
+        }
+        out.push('\n');
+    }
+    out.push_str("]}\n");
+    out
+}
+
+fn to_dot(types: &[TypeEntry]) -> String {
+    let mut out = String::from("digraph types {\n  node [shape=box];\n");
+    for ty in types.iter() {
+        let mut lines = vec![ty.path.clone()];
+        for var in ty.vars.iter() {
+            lines.push(match &var.value {
+                Some(value) => format!("var {} = {}", var.name, value),
+                None => format!("var {}", var.name),
+            });
+        }
+        for proc in ty.procs.iter() {
+            lines.push(format!("{}()", proc.name));
+        }
+        if lines.len() == 1 {
+            out.push_str(&format!("  {};\n", quote_dot(&ty.path)));
+        } else {
+            let label: String = lines
+                .iter()
+                .map(|line| format!("{}\\l", escape_dot(line)))
+                .collect();
+            out.push_str(&format!("  {} [label=\"{}\"];\n", quote_dot(&ty.path), label));
+        }
+    }
+    let exported: HashSet<&str> = types.iter().map(|ty| ty.path.as_str()).collect();
+    for ty in types.iter() {
+        if let Some(parent) = ty.parent.as_deref().filter(|parent| exported.contains(parent)) {
+            out.push_str(&format!("  {} -> {};\n", quote_dot(parent), quote_dot(&ty.path)));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// The implementation of `DME.export_types`.
+pub fn export_types(
+    dme: &Bound<Dme>,
+    format: &str,
+    prefix: Option<&Bound<PyAny>>,
+    include_vars: bool,
+    include_procs: bool,
+    py: Python<'_>,
+) -> PyResult<String> {
+    if format != "json" && format != "dot" {
+        return Err(PyValueError::new_err(format!(
+            "unknown export format {:?}, expected \"json\" or \"dot\"",
+            format
+        )));
+    }
+    let prefix = proc_walk::type_filter(prefix)?;
+    let types = collect_types(dme, &prefix, include_vars, include_procs, py)?;
+    Ok(match format {
+        "json" => to_json(&types, include_vars, include_procs),
+        _ => to_dot(&types),
+    })
+}
@@ -39,13 +39,22 @@ impl Expression {
                         constant: Constant::Resource(s.clone()),
                         source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
                     },
-                    dreammaker::ast::Term::As(_) => todo!(),
+                    dreammaker::ast::Term::As(input_type) => Self::As {
+                        input_type: input_type.bits(),
+                        source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
+                    },
                     dreammaker::ast::Term::__PROC__ => Self::Constant {
                         constant: Constant::ProcMacro(),
                         source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
                     },
-                    dreammaker::ast::Term::__TYPE__ => todo!(),
-                    dreammaker::ast::Term::__IMPLIED_TYPE__ => todo!(),
+                    dreammaker::ast::Term::__TYPE__ => Self::Constant {
+                        constant: Constant::TypeMacro(),
+                        source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
+                    },
+                    dreammaker::ast::Term::__IMPLIED_TYPE__ => Self::Constant {
+                        constant: Constant::ImpliedTypeMacro(),
+                        source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
+                    },
                     dreammaker::ast::Term::Expr(expression) => Expression::parse(py, expression),
                     dreammaker::ast::Term::Prefab(prefab) => Self::Prefab {
                         prefab: Prefab::make(py, prefab)
@@ -143,7 +152,7 @@ impl Expression {
                         }),
                         source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
                     },
-                    dreammaker::ast::Term::NewMiniExpr { expr, args: _ } => Self::NewMiniExpr {
+                    dreammaker::ast::Term::NewMiniExpr { expr, args } => Self::NewMiniExpr {
                         name: Expression::ident(expr.ident.to_string(), None, py),
                         fields: expr
                             .fields
@@ -159,6 +168,16 @@ impl Expression {
                                 .into()
                             })
                             .collect(),
+                        args: args.as_ref().map(|args| {
+                            args.iter()
+                                .map(|expr| {
+                                    Expression::parse(py, expr)
+                                        .into_pyobject(py)
+                                        .expect("parsing newminiexpr arg")
+                                        .into()
+                                })
+                                .collect()
+                        }),
                         source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
                     },
                     dreammaker::ast::Term::List(l) => {
@@ -322,10 +341,12 @@ impl Expression {
                         function,
                         args,
                     } => Self::ExternalCall {
-                        library_name: Expression::parse(py, library.as_ref().unwrap().as_ref())
-                            .into_pyobject(py)
-                            .expect("parsing external call lib name")
-                            .into(),
+                        library_name: library.as_ref().map(|library| {
+                            Expression::parse(py, library)
+                                .into_pyobject(py)
+                                .expect("parsing external call lib name")
+                                .into()
+                        }),
                         function_name: Expression::parse(py, function)
                             .into_pyobject(py)
                             .expect("parsing external call func name")
@@ -341,8 +362,23 @@ impl Expression {
                             .collect(),
                         source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
                     },
-                    dreammaker::ast::Term::GlobalIdent(_) => todo!(),
-                    dreammaker::ast::Term::GlobalCall(_, _) => todo!(),
+                    dreammaker::ast::Term::GlobalIdent(ident2) => Self::GlobalIdentifier {
+                        name: ident2.to_string(),
+                        source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
+                    },
+                    dreammaker::ast::Term::GlobalCall(ident2, args) => Self::GlobalCall {
+                        name: Expression::ident(ident2.to_string(), None, py),
+                        args: args
+                            .iter()
+                            .map(|e| {
+                                Expression::parse(py, e)
+                                    .into_pyobject(py)
+                                    .expect("parsing global call args")
+                                    .into()
+                            })
+                            .collect(),
+                        source_loc: Some(OriginalSourceLocation::from_location(&term.location)),
+                    },
                 };
 
                 for f in follow.iter() {
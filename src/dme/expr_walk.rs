@@ -16,6 +16,7 @@ impl Expression {
             let source_loc = match self_expr {
                 Expression::Constant { source_loc, .. } => source_loc,
                 Expression::Identifier { source_loc, .. } => source_loc,
+                Expression::GlobalIdentifier { source_loc, .. } => source_loc,
                 Expression::List { source_loc, .. } => source_loc,
                 Expression::BinaryOp { source_loc, .. } => source_loc,
                 Expression::AssignOp { source_loc, .. } => source_loc,
@@ -27,6 +28,7 @@ impl Expression {
                 Expression::Field { source_loc, .. } => source_loc,
                 Expression::StaticField { source_loc, .. } => source_loc,
                 Expression::Call { source_loc, .. } => source_loc,
+                Expression::GlobalCall { source_loc, .. } => source_loc,
                 Expression::SelfCall { source_loc, .. } => source_loc,
                 Expression::ParentCall { source_loc, .. } => source_loc,
                 Expression::UnaryOp { source_loc, .. } => source_loc,
@@ -38,6 +40,7 @@ impl Expression {
                 Expression::DynamicCall { source_loc, .. } => source_loc,
                 Expression::Input { source_loc, .. } => source_loc,
                 Expression::Pick { source_loc, .. } => source_loc,
+                Expression::As { source_loc, .. } => source_loc,
             };
             walker.call_method1(
                 "visit_Expr",
@@ -65,6 +68,18 @@ impl Expression {
                 }
                 Ok(())
             }
+            Expression::GlobalIdentifier {
+                name: _,
+                source_loc,
+            } => {
+                if walker.hasattr("visit_GlobalIdentifier").unwrap() {
+                    walker.call_method1(
+                        "visit_GlobalIdentifier",
+                        (self_, dme.borrow().populate_source_loc(source_loc, py)),
+                    )?;
+                }
+                Ok(())
+            }
             Expression::BinaryOp {
                 op: _,
                 lhs,
@@ -245,6 +260,24 @@ impl Expression {
 
                 Ok(())
             }
+            Expression::GlobalCall {
+                name: _,
+                args,
+                source_loc,
+            } => {
+                if walker.hasattr("visit_GlobalCall").unwrap() {
+                    walker.call_method1(
+                        "visit_GlobalCall",
+                        (self_, dme.borrow().populate_source_loc(source_loc, py)),
+                    )?;
+                } else {
+                    for arg in args.iter() {
+                        Expression::walk(arg.bind(py), dme, walker, py)?;
+                    }
+                }
+
+                Ok(())
+            }
             Expression::SelfCall { args, source_loc } => {
                 if walker.hasattr("visit_SelfCall").unwrap() {
                     walker.call_method1(
@@ -339,7 +372,9 @@ impl Expression {
                         (self_, dme.borrow().populate_source_loc(source_loc, py)),
                     )?;
                 } else {
-                    Expression::walk(library_name.bind(py), dme, walker, py)?;
+                    if let Some(library_name) = library_name {
+                        Expression::walk(library_name.bind(py), dme, walker, py)?;
+                    }
                     Expression::walk(function_name.bind(py), dme, walker, py)?;
                     for arg in args.iter() {
                         Expression::walk(arg.bind(py), dme, walker, py)?;
@@ -350,6 +385,7 @@ impl Expression {
             Expression::NewMiniExpr {
                 name,
                 fields,
+                args,
                 source_loc,
             } => {
                 if walker.hasattr("visit_NewMiniExpr").unwrap() {
@@ -362,6 +398,11 @@ impl Expression {
                     for field in fields.iter() {
                         Expression::walk(field.bind(py), dme, walker, py)?;
                     }
+                    if let Some(args) = args {
+                        for arg in args.iter() {
+                            Expression::walk(arg.bind(py), dme, walker, py)?;
+                        }
+                    }
                 }
 
                 Ok(())
@@ -459,6 +500,18 @@ impl Expression {
 
                 Ok(())
             }
+            Expression::As {
+                input_type: _,
+                source_loc,
+            } => {
+                if walker.hasattr("visit_As").unwrap() {
+                    walker.call_method1(
+                        "visit_As",
+                        (self_, dme.borrow().populate_source_loc(source_loc, py)),
+                    )?;
+                }
+                Ok(())
+            }
         }
     }
 }
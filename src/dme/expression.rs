@@ -1,12 +1,17 @@
 use dreammaker::Location;
-use pyo3::{pyclass, pymethods, IntoPyObject, Py, PyAny, PyResult, Python};
+use pyo3::{
+    pyclass, pymethods, types::PyTuple, Bound, IntoPyObject, Py, PyAny, PyRef, PyResult, Python,
+};
 
 use crate::{dmlist::DmList, path::Path};
 
 use super::{
+    evaluate::{resolve_context, Evaluator},
     nodes::{NodeKind, OriginalSourceLocation, PyExpr},
+    Dme,
     operators::{AssignOperator, BinaryOperator, UnaryOperator},
     prefab::Prefab,
+    visitor,
 };
 
 #[pyclass(frozen)]
@@ -19,6 +24,8 @@ pub enum Constant {
     Resource(String),
     Path(Path),
     ProcMacro(),
+    TypeMacro(),
+    ImpliedTypeMacro(),
 }
 
 #[pymethods]
@@ -33,6 +40,11 @@ impl Constant {
             Constant::Resource(s) => s.into_pyobject(py)?.into_any().unbind(),
             Constant::Path(p) => p.clone().into_pyobject(py)?.into_any().unbind(),
             Constant::ProcMacro() => Constant::ProcMacro().into_pyobject(py)?.into_any().unbind(),
+            Constant::TypeMacro() => Constant::TypeMacro().into_pyobject(py)?.into_any().unbind(),
+            Constant::ImpliedTypeMacro() => Constant::ImpliedTypeMacro()
+                .into_pyobject(py)?
+                .into_any()
+                .unbind(),
         })
     }
 }
@@ -48,6 +60,10 @@ pub enum Expression {
         name: String,
         source_loc: Option<Py<OriginalSourceLocation>>,
     },
+    GlobalIdentifier {
+        name: String,
+        source_loc: Option<Py<OriginalSourceLocation>>,
+    },
     List {
         list: Py<DmList>,
         source_loc: Option<Py<OriginalSourceLocation>>,
@@ -105,6 +121,11 @@ pub enum Expression {
         args: Vec<PyExpr>,
         source_loc: Option<Py<OriginalSourceLocation>>,
     },
+    GlobalCall {
+        name: PyExpr,
+        args: Vec<PyExpr>,
+        source_loc: Option<Py<OriginalSourceLocation>>,
+    },
     SelfCall {
         args: Vec<PyExpr>,
         source_loc: Option<Py<OriginalSourceLocation>>,
@@ -124,7 +145,7 @@ pub enum Expression {
         source_loc: Option<Py<OriginalSourceLocation>>,
     },
     ExternalCall {
-        library_name: PyExpr,
+        library_name: Option<PyExpr>,
         function_name: PyExpr,
         args: Vec<PyExpr>,
         source_loc: Option<Py<OriginalSourceLocation>>,
@@ -132,6 +153,7 @@ pub enum Expression {
     NewMiniExpr {
         name: PyExpr,
         fields: Vec<PyExpr>,
+        args: Option<Vec<PyExpr>>,
         source_loc: Option<Py<OriginalSourceLocation>>,
     },
     NewImplicit {
@@ -158,6 +180,10 @@ pub enum Expression {
         args: Vec<(Option<PyExpr>, PyExpr)>,
         source_loc: Option<Py<OriginalSourceLocation>>,
     },
+    As {
+        input_type: u32,
+        source_loc: Option<Py<OriginalSourceLocation>>,
+    },
 }
 
 impl Expression {
@@ -199,6 +225,7 @@ impl Expression {
         match self {
             Expression::Constant { .. } => NodeKind::Constant,
             Expression::Identifier { .. } => NodeKind::Identifier,
+            Expression::GlobalIdentifier { .. } => NodeKind::GlobalIdentifier,
             Expression::List { .. } => NodeKind::List,
             Expression::BinaryOp { .. } => NodeKind::BinaryOp,
             Expression::AssignOp { .. } => NodeKind::AssignOp,
@@ -210,6 +237,7 @@ impl Expression {
             Expression::Field { .. } => NodeKind::Field,
             Expression::StaticField { .. } => NodeKind::StaticField,
             Expression::Call { .. } => NodeKind::Call,
+            Expression::GlobalCall { .. } => NodeKind::GlobalCall,
             Expression::SelfCall { .. } => NodeKind::SelfCall,
             Expression::ParentCall { .. } => NodeKind::ParentCall,
             Expression::UnaryOp { .. } => NodeKind::UnaryOp,
@@ -221,9 +249,38 @@ impl Expression {
             Expression::DynamicCall { .. } => NodeKind::DynamicCall,
             Expression::Input { .. } => NodeKind::Input,
             Expression::Pick { .. } => NodeKind::Pick,
+            Expression::As { .. } => NodeKind::As,
         }
     }
 
+    /// Fold the expression into a Python value, looking up vars on
+    /// `context_type` and defines in `dme`.
+    #[pyo3(signature = (dme, context_type=None))]
+    fn evaluate(
+        slf: &Bound<'_, Self>,
+        dme: PyRef<'_, Dme>,
+        context_type: Option<&Bound<PyAny>>,
+        py: Python<'_>,
+    ) -> PyResult<Py<PyAny>> {
+        let context = resolve_context(&dme, context_type)?;
+        Evaluator::new(&dme, context, py)
+            .eval(&slf.clone().unbind())?
+            .to_python(py)
+    }
+
+    #[getter(_fields)]
+    fn get_field_names<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyTuple>> {
+        visitor::field_names(slf.as_any())
+    }
+
+    fn iter_fields(slf: &Bound<Self>) -> PyResult<Vec<(&'static str, Py<PyAny>)>> {
+        visitor::iter_fields(slf.as_any())
+    }
+
+    fn iter_child_nodes<'py>(slf: &Bound<'py, Self>) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        visitor::child_nodes(slf.as_any())
+    }
+
     fn __str__(&self, py: Python<'_>) -> PyResult<String> {
         match self {
             Expression::Identifier { name, .. } => Ok(name.clone()),
@@ -237,6 +294,9 @@ impl Expression {
                 Ok(format!("<Constant {}>", constant.get_val(py)?))
             }
             Expression::Identifier { name, .. } => Ok(format!("<Identifier {}>", name)),
+            Expression::GlobalIdentifier { name, .. } => {
+                Ok(format!("<GlobalIdentifier global.{}>", name))
+            }
             Expression::List { list, .. } => Ok(format!("{}", list)),
             Expression::BinaryOp { op, lhs, rhs, .. } => {
                 Ok(format!("<BinaryOp {} {:?} {}>", lhs, op, rhs))
@@ -254,6 +314,7 @@ impl Expression {
                 Ok(format!("<StaticField {}::{}>", expr, field))
             }
             Expression::Call { expr, name, .. } => Ok(format!("<Call {}.{}(...)>", expr, name)),
+            Expression::GlobalCall { .. } => Ok("<GlobalCall ...>".to_string()),
             Expression::SelfCall { .. } => Ok("<SelfCall ...>".to_string()),
             Expression::ParentCall { .. } => Ok("<ParentCall ...>".to_string()),
             Expression::UnaryOp { expr, unary_op, .. } => {
@@ -266,10 +327,13 @@ impl Expression {
                 library_name,
                 function_name,
                 ..
-            } => Ok(format!(
-                "<ExternalCall {},{}(...)>",
-                library_name, function_name
-            )),
+            } => match library_name {
+                Some(library_name) => Ok(format!(
+                    "<ExternalCall {},{}(...)>",
+                    library_name, function_name
+                )),
+                None => Ok(format!("<ExternalCall {}(...)>", function_name)),
+            },
             Expression::NewMiniExpr { name, .. } => Ok(format!("<NewMiniExpr {} ...>", name)),
             Expression::NewImplicit { .. } => Ok("<NewImplicit ...>".to_string()),
             Expression::NewPrefab { prefab, .. } => Ok(format!("<NewPrefab {} ...>", prefab)),
@@ -280,6 +344,7 @@ impl Expression {
             } => Ok(format!("<DynamicCall ({:?})({:?})>", lib_name, proc_name)),
             Expression::Input { input_type, .. } => Ok(format!("<Input {:?} ...>", input_type)),
             Expression::Pick { .. } => Ok("<Pick ...>".to_string()),
+            Expression::As { input_type, .. } => Ok(format!("<As {}>", input_type)),
         }
     }
 }
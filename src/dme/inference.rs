@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use dreammaker::{
+    Location,
+    ast::{Expression, Follow, Spanned, Statement, Term, VarType},
+    objtree::{NodeIndex, ObjectTree},
+};
+use pyo3::{
+    Bound, PyAny, PyResult, exceptions::PyRuntimeError, pyclass, pymethods,
+    types::PyAnyMethods,
+};
+
+use crate::path::Path;
+
+use super::{
+    Dme,
+    resolve::{self, ProcKey, Scope, Visitor},
+};
+
+/// Records the static type of each term and follow, keyed by its line and
+/// column.
+struct TypeRecorder<'a> {
+    types: &'a mut HashMap<(u32, u16), NodeIndex>,
+}
+
+impl TypeRecorder<'_> {
+    fn record(&mut self, location: Location, type_index: Option<NodeIndex>) {
+        if let Some(type_index) = type_index {
+            self.types
+                .entry((location.line, location.column))
+                .or_insert(type_index);
+        }
+    }
+
+    /// A bare `new` takes the declared type of the var it initializes.
+    fn record_new_implicit(
+        &mut self,
+        objtree: &ObjectTree,
+        var_type: &VarType,
+        value: &Expression,
+    ) {
+        if let Expression::Base { term, follow } = value {
+            if matches!(term.elem, Term::NewImplicit { .. }) && follow.is_empty() {
+                self.record(
+                    term.location,
+                    resolve::tree_path_type(objtree, &var_type.type_path),
+                );
+            }
+        }
+    }
+}
+
+impl Visitor for TypeRecorder<'_> {
+    fn visit_statement(&mut self, scope: &Scope, stmt: &Statement, _location: Location) {
+        match stmt {
+            Statement::Var(var) => {
+                if let Some(value) = &var.value {
+                    self.record_new_implicit(scope.objtree, &var.var_type, value);
+                }
+            }
+            Statement::Vars(vars) => {
+                for var in vars.iter() {
+                    if let Some(value) = &var.value {
+                        self.record_new_implicit(scope.objtree, &var.var_type, value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_term(&mut self, scope: &Scope, term: &Spanned<Term>) {
+        self.record(term.location, scope.term_type(&term.elem));
+    }
+
+    fn visit_follow(&mut self, scope: &Scope, base: Option<NodeIndex>, follow: &Spanned<Follow>) {
+        self.record(follow.location, scope.follow_type(base, &follow.elem));
+    }
+}
+
+/// Infers the static type of the identifiers, fields and calls in the proc
+/// definition `key`.
+pub fn infer_types(objtree: &ObjectTree, key: &ProcKey) -> HashMap<(u32, u16), NodeIndex> {
+    let mut types = HashMap::new();
+    resolve::walk_proc(objtree, key, &mut TypeRecorder { types: &mut types });
+    types
+}
+
+#[pyclass(module = "avulto")]
+pub struct InferredTypes {
+    types: HashMap<(u32, u16), Path>,
+}
+
+impl InferredTypes {
+    pub fn build(dme: &Dme, key: &ProcKey) -> PyResult<InferredTypes> {
+        if !dme.procs_parsed {
+            return Err(PyRuntimeError::new_err(
+                "parse_procs=True was not included in DME's constructor",
+            ));
+        }
+        let objtree = &dme.objtree;
+        Ok(InferredTypes {
+            types: infer_types(objtree, key)
+                .into_iter()
+                .map(|(pos, type_index)| (pos, Path::make_trusted(&objtree[type_index].path)))
+                .collect(),
+        })
+    }
+}
+
+#[pymethods]
+impl InferredTypes {
+    /// Returns the static type of the expression at `target`, which may be
+    /// an expression node or a source location.
+    fn type_of(&self, target: &Bound<PyAny>) -> PyResult<Option<Path>> {
+        let source_loc = if target.hasattr("source_loc")? {
+            target.getattr("source_loc")?
+        } else {
+            target.clone()
+        };
+        if source_loc.is_none() {
+            return Ok(None);
+        }
+        let line: u32 = source_loc.getattr("line")?.extract()?;
+        let column: u16 = source_loc.getattr("column")?.extract()?;
+        Ok(self.types.get(&(line, column)).cloned())
+    }
+
+    fn __len__(&self) -> usize {
+        self.types.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<InferredTypes {} expressions>", self.types.len())
+    }
+}
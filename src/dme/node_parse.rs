@@ -209,6 +209,7 @@ impl Node {
                     }
                 }
                 Self::ForList {
+                    declared: for_list_statement.var_type.is_some(),
                     name: Expression::ident(for_list_statement.name.to_string(), None, py),
                     in_list: for_list_statement.in_list.as_ref().map(|expr| {
                         Expression::parse(py, expr)
@@ -234,6 +235,7 @@ impl Node {
                 .into()
             }
             Statement::ForRange(for_range_statement) => Self::ForRange {
+                declared: for_range_statement.var_type.is_some(),
                 name: Expression::ident(for_range_statement.name.to_string(), None, py),
                 start: Expression::parse(py, &for_range_statement.start)
                     .into_pyobject(py)
@@ -524,6 +526,7 @@ impl Node {
                     }
                 }
                 Self::ForKeyValue {
+                    declared: stmt.var_type.is_some(),
                     key: Expression::ident(stmt.key.to_string(), None, py),
                     value: Expression::ident(stmt.value.to_string(), None, py),
                     in_list: stmt.in_list.as_ref().map(|expr| {
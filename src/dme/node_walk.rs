@@ -11,7 +11,6 @@ impl Node {
     ) -> PyResult<()> {
         let node = self_.get();
         match node {
-            Node::Unknown() => todo!(),
             Node::Expression { expr, .. } => {
                 Expression::walk(expr.bind(py), dme, walker, py)?;
             }
@@ -202,6 +201,7 @@ impl Node {
                 block,
                 source_loc,
                 var_type,
+                ..
             } => {
                 if walker.hasattr("visit_ForList").unwrap() {
                     walker.call_method1(
@@ -238,6 +238,7 @@ impl Node {
                 step,
                 block,
                 source_loc,
+                ..
             } => {
                 if walker.hasattr("visit_ForRange").unwrap() {
                     walker.call_method1(
@@ -434,7 +435,7 @@ impl Node {
                     }
                 }
             },
-            Node::ForKeyValue { var_type, key, value, in_list, block, source_loc } => {
+            Node::ForKeyValue { var_type, key, value, in_list, block, source_loc, .. } => {
                 if walker.hasattr("visit_ForKeyValue").unwrap() {
                     walker.call_method1("visit_ForKeyValue", (self_, dme.borrow().populate_source_loc(source_loc, py)))?;
                 } else {
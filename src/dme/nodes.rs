@@ -4,8 +4,8 @@ use std::hash::Hash;
 use dreammaker::{FileId, Location};
 use pyo3::{
     pyclass, pymethods, pymodule,
-    types::{PyAnyMethods, PyList, PyModule, PyModuleMethods},
-    Bound, IntoPyObject, Py, PyAny, PyResult, Python,
+    types::{PyAnyMethods, PyList, PyModule, PyModuleMethods, PyTuple},
+    wrap_pyfunction, Bound, IntoPyObject, Py, PyAny, PyResult, Python,
 };
 
 use crate::{
@@ -19,7 +19,7 @@ use crate::{
 use super::{
     expression::{Constant, Expression},
     operators::SettingMode,
-    Dme,
+    query, snippet, unparse, visitor, Dme,
 };
 
 extern crate dreammaker;
@@ -39,6 +39,17 @@ pub fn ast(_py: Python, m: &Bound<PyModule>) -> PyResult<()> {
     m.add_class::<Node>()?;
     m.add_class::<NodeKind>()?;
     m.add_class::<Prefab>()?;
+    m.add_class::<SwitchCase>()?;
+    m.add_class::<query::AnyPattern>()?;
+    m.add_class::<query::Pattern>()?;
+    m.add_class::<visitor::NodeVisitor>()?;
+    m.add_class::<visitor::NodeTransformer>()?;
+    m.add_class::<visitor::WalkContext>()?;
+
+    m.add_function(wrap_pyfunction!(snippet::parse_expression, m)?)?;
+    m.add_function(wrap_pyfunction!(snippet::parse_statements, m)?)?;
+    m.add_function(wrap_pyfunction!(unparse::unparse, m)?)?;
+    m.add_function(wrap_pyfunction!(visitor::kind_name, m)?)?;
     Ok(())
 }
 
@@ -51,6 +62,7 @@ pub fn ast(_py: Python, m: &Bound<PyModule>) -> PyResult<()> {
 )]
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum NodeKind {
+    As,
     AssignOp,
     Attribute,
     BinaryOp,
@@ -70,6 +82,8 @@ pub enum NodeKind {
     ForLoop,
     ForRange,
     ForKeyValue,
+    GlobalCall,
+    GlobalIdentifier,
     Goto,
     Identifier,
     If,
@@ -101,7 +115,6 @@ pub enum NodeKind {
     Throw,
     TryCatch,
     UnaryOp,
-    Unknown,
     Var,
     Vars,
     While,
@@ -143,7 +156,6 @@ impl OriginalSourceLocation {
 
 #[pyclass(frozen)]
 pub enum Node {
-    Unknown(),
     Expression {
         expr: PyExpr,
         source_loc: Option<Py<OriginalSourceLocation>>,
@@ -189,6 +201,7 @@ pub enum Node {
     },
     ForList {
         var_type: Option<Path>,
+        declared: bool,
         name: PyExpr,
         in_list: Option<PyExpr>,
         block: PyCodeBlock,
@@ -202,6 +215,7 @@ pub enum Node {
         source_loc: Option<Py<OriginalSourceLocation>>,
     },
     ForRange {
+        declared: bool,
         name: PyExpr,
         start: PyExpr,
         end: PyExpr,
@@ -257,6 +271,7 @@ pub enum Node {
     },
     ForKeyValue {
         var_type: Option<Path>,
+        declared: bool,
         key: PyExpr,
         value: PyExpr,
         in_list: Option<PyExpr>,
@@ -278,7 +293,6 @@ impl Node {
     #[getter]
     fn get_kind(&self, py: Python<'_>) -> PyResult<Py<PyAny>> {
         match self {
-            Node::Unknown() => Ok(Py::new(py, NodeKind::Unknown).unwrap().into_any()),
             Node::Expression { expr, .. } => expr.call_method0(py, "kind"),
             Node::Crash { .. } => Ok(Py::new(py, NodeKind::Crash).unwrap().into_any()),
             Node::Return { .. } => Ok(Py::new(py, NodeKind::Return).unwrap().into_any()),
@@ -305,13 +319,25 @@ impl Node {
         }
     }
 
+    #[getter(_fields)]
+    fn get_field_names<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyTuple>> {
+        visitor::field_names(slf.as_any())
+    }
+
+    fn iter_fields(slf: &Bound<Self>) -> PyResult<Vec<(&'static str, Py<PyAny>)>> {
+        visitor::iter_fields(slf.as_any())
+    }
+
+    fn iter_child_nodes<'py>(slf: &Bound<'py, Self>) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        visitor::child_nodes(slf.as_any())
+    }
+
     fn __str__(&self, py: Python<'_>) -> PyResult<String> {
         self.__repr__(py)
     }
 
     fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
         match self {
-            Node::Unknown() => todo!(),
             Node::Expression { expr, .. } => Ok(format!("{}", expr)),
             Node::Crash { expr, .. } => Ok(format!("<Crash {:?}>", expr)),
             Node::Return { retval, .. } => Ok(format!(
@@ -363,6 +389,22 @@ pub struct SwitchCase {
     pub(crate) block: PyCodeBlock,
 }
 
+#[pymethods]
+impl SwitchCase {
+    #[getter(_fields)]
+    fn get_field_names<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyTuple>> {
+        visitor::field_names(slf.as_any())
+    }
+
+    fn iter_fields(slf: &Bound<Self>) -> PyResult<Vec<(&'static str, Py<PyAny>)>> {
+        visitor::iter_fields(slf.as_any())
+    }
+
+    fn iter_child_nodes<'py>(slf: &Bound<'py, Self>) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        visitor::child_nodes(slf.as_any())
+    }
+}
+
 impl SwitchCase {
     pub fn walk_parts(
         &self,
@@ -0,0 +1,279 @@
+use dreammaker::objtree::{NodeIndex, ProcValue};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    types::{PyAnyMethods, PyList, PyString, PyStringMethods, PyTuple},
+    Bound, Py, PyAny, PyResult, Python,
+};
+
+use crate::{
+    path::Path,
+    typedecl::{ProcDecl, TypeDecl},
+};
+
+use super::{
+    expression::Expression,
+    nodes::Node,
+    visitor::{self, NodeVisitor},
+    Dme,
+};
+
+/// Calls `method` on each walker defining it.
+fn notify(walkers: &[Bound<PyAny>], method: &str, decl: &Bound<PyAny>) -> PyResult<()> {
+    for walker in walkers {
+        if walker.hasattr(method)? {
+            walker.call_method1(method, (decl,))?;
+        }
+    }
+    Ok(())
+}
+
+/// The default values of a proc definition's parameters.
+pub fn param_defaults<'py>(
+    proc_value: &ProcValue,
+    py: Python<'py>,
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    proc_value
+        .parameters
+        .iter()
+        .filter_map(|param| param.default.as_ref())
+        .map(|default| Ok(Bound::new(py, Expression::parse(py, default))?.into_any()))
+        .collect()
+}
+
+/// Walks a statement or expression with `walker`, which may be an
+/// `ast.NodeVisitor`.
+pub fn walk_ast(dme: &Bound<Dme>, walker: &Bound<PyAny>, item: &Bound<PyAny>) -> PyResult<()> {
+    let py = item.py();
+    if walker.is_instance_of::<NodeVisitor>() {
+        walker.call_method1("visit", (item,))?;
+    } else if let Ok(node) = item.cast::<Node>() {
+        Node::walk(node, dme, walker, py)?;
+    } else if let Ok(expr) = item.cast::<Expression>() {
+        Expression::walk(expr, dme, walker, py)?;
+    }
+    Ok(())
+}
+
+/// Resolves the type path filter taken by `DME.walk_procs` and `DME.query`.
+pub fn type_filter(filter: Option<&Bound<PyAny>>) -> PyResult<Path> {
+    match filter {
+        None => Ok(Path::root()),
+        Some(prefix) => {
+            if let Ok(path) = prefix.extract::<Path>() {
+                Ok(path)
+            } else if let Ok(pystr) = prefix.cast::<PyString>() {
+                Path::make_untrusted(pystr.to_str()?).map_err(PyRuntimeError::new_err)
+            } else {
+                Err(PyValueError::new_err(format!("invalid path {:?}", prefix)))
+            }
+        }
+    }
+}
+
+/// A proc definition visited by [each_proc]. Its [ProcDecl] and [TypeDecl]
+/// are only built when asked for.
+pub struct ProcEntry<'py> {
+    dme: Bound<'py, Dme>,
+    type_index: NodeIndex,
+    pub name: String,
+    proc_index: usize,
+    proc_decl: Option<Py<ProcDecl>>,
+    type_decl: Option<Py<TypeDecl>>,
+}
+
+impl ProcEntry<'_> {
+    pub fn proc_decl(&mut self) -> PyResult<Py<ProcDecl>> {
+        let py = self.dme.py();
+        if let Some(proc_decl) = &self.proc_decl {
+            return Ok(proc_decl.clone_ref(py));
+        }
+        let proc_decl = Py::new(
+            py,
+            ProcDecl::build(&self.dme, self.type_index, &self.name, self.proc_index, py)?,
+        )?;
+        Ok(self.proc_decl.insert(proc_decl).clone_ref(py))
+    }
+
+    pub fn type_decl(&mut self) -> PyResult<Py<TypeDecl>> {
+        let py = self.dme.py();
+        if let Some(type_decl) = &self.type_decl {
+            return Ok(type_decl.clone_ref(py));
+        }
+        let type_decl = Py::new(py, TypeDecl::build(&self.dme, self.type_index, py)?)?;
+        Ok(self.type_decl.insert(type_decl).clone_ref(py))
+    }
+}
+
+/// Calls `f` with each proc definition on `prefix` and its subtypes, along
+/// with the arg defaults and statements of its body. Types are visited in
+/// path order, and procs on a type in name order. The DME is not borrowed
+/// while `f` runs.
+pub fn each_proc<'py, F>(
+    dme: &Bound<'py, Dme>,
+    prefix: &Path,
+    include_builtins: bool,
+    py: Python<'py>,
+    mut f: F,
+) -> PyResult<()>
+where
+    F: FnMut(&mut ProcEntry<'py>, Vec<Bound<'py, PyAny>>) -> PyResult<()>,
+{
+    let mut keys: Vec<(NodeIndex, String, usize)> = Vec::new();
+    {
+        let dme_ref = dme.borrow();
+        if !dme_ref.procs_parsed {
+            return Err(PyRuntimeError::new_err(
+                "parse_procs=True was not included in DME's constructor",
+            ));
+        }
+        let objtree = &dme_ref.objtree;
+
+        let mut paths: Vec<Path> = Vec::new();
+        dme_ref.collect_child_paths(prefix, false, &mut paths);
+
+        for path in paths {
+            let search_string = if path.rel.eq("/") { "" } else { path.rel.as_str() };
+            let Some(ty) = objtree.find(search_string) else {
+                continue;
+            };
+            let mut proc_names: Vec<&String> = ty.procs.keys().collect();
+            proc_names.sort();
+            for name in proc_names {
+                for (proc_index, proc_value) in ty.procs[name].value.iter().enumerate() {
+                    if proc_value.location.is_builtins() && !include_builtins {
+                        continue;
+                    }
+                    keys.push((ty.index(), name.clone(), proc_index));
+                }
+            }
+        }
+    }
+
+    for (type_index, name, proc_index) in keys {
+        let items = {
+            let dme_ref = dme.borrow();
+            let proc_value = &dme_ref.objtree[type_index].procs[&name].value[proc_index];
+            let mut items = param_defaults(proc_value, py)?;
+            for stmt in proc_value.code.as_deref().unwrap_or_default() {
+                let node = Node::from_statement(py, &stmt.elem, Some(stmt.location));
+                items.push(node.into_bound(py).into_any());
+            }
+            items
+        };
+        let mut entry = ProcEntry {
+            dme: dme.clone(),
+            type_index,
+            name,
+            proc_index,
+            proc_decl: None,
+            type_decl: None,
+        };
+        f(&mut entry, items)?;
+    }
+
+    Ok(())
+}
+
+/// The implementation of `DME.walk_procs`.
+pub fn walk_procs(
+    dme: &Bound<Dme>,
+    walker: &Bound<PyAny>,
+    filter: Option<&Bound<PyAny>>,
+    include_builtins: bool,
+    py: Python<'_>,
+) -> PyResult<()> {
+    let walkers: Vec<Bound<PyAny>> =
+        if walker.is_instance_of::<PyList>() || walker.is_instance_of::<PyTuple>() {
+            walker.try_iter()?.collect::<PyResult<_>>()?
+        } else {
+            vec![walker.clone()]
+        };
+
+    // Only build the ProcDecl of each proc if a walker can see it.
+    let mut wants_decl = false;
+    for walker in walkers.iter() {
+        wants_decl |= walker.is_instance_of::<NodeVisitor>()
+            || walker.hasattr("enter_proc")?
+            || walker.hasattr("exit_proc")?;
+    }
+
+    let prefix = type_filter(filter)?;
+    each_proc(dme, &prefix, include_builtins, py, |entry, items| {
+        if !wants_decl {
+            return items.iter().try_for_each(|item| {
+                walkers
+                    .iter()
+                    .try_for_each(|walker| walk_ast(dme, walker, item))
+            });
+        }
+        let proc_decl = entry.proc_decl()?;
+        let type_decl = entry.type_decl()?;
+        notify(&walkers, "enter_proc", proc_decl.bind(py).as_any())?;
+        let mut previous = Vec::new();
+        for walker in walkers.iter() {
+            if let Ok(visitor) = walker.cast::<NodeVisitor>() {
+                previous.push(visitor::set_proc(
+                    visitor,
+                    Some(proc_decl.clone_ref(py)),
+                    Some(type_decl.clone_ref(py)),
+                ));
+            }
+        }
+
+        let result = items.iter().try_for_each(|item| {
+            walkers
+                .iter()
+                .try_for_each(|walker| walk_ast(dme, walker, item))
+        });
+
+        let mut previous = previous.into_iter();
+        for walker in walkers.iter() {
+            if let Ok(visitor) = walker.cast::<NodeVisitor>() {
+                let (previous_proc, previous_type) = previous.next().unwrap();
+                visitor::set_proc(visitor, previous_proc, previous_type);
+            }
+        }
+        result?;
+        notify(&walkers, "exit_proc", proc_decl.bind(py).as_any())
+    })
+}
+
+/// The implementation of `TypeDecl.walk_vars`.
+pub fn walk_vars(
+    type_decl: &Bound<TypeDecl>,
+    walker: &Bound<PyAny>,
+    py: Python<'_>,
+) -> PyResult<()> {
+    let this = type_decl.borrow();
+    let dme = this.dme.cast_bound::<Dme>(py).unwrap();
+    let dme_ref = dme.borrow();
+    let ty = &dme_ref.objtree[this.node_index];
+
+    let mut vars: Vec<_> = ty
+        .vars
+        .iter()
+        .filter(|(_, var)| !var.value.location.is_builtins())
+        .filter_map(|(name, var)| Some((name, var.value.location, var.value.expression.as_ref()?)))
+        .collect();
+    vars.sort_by_key(|(_, location, _)| *location);
+
+    let walkers = [walker.clone()];
+    let visitor = walker.cast::<NodeVisitor>().ok();
+    let previous = visitor.map(|visitor| {
+        visitor::set_proc(visitor, None, Some(type_decl.clone().unbind()))
+    });
+    let result = vars.into_iter().try_for_each(|(name, _, expr)| {
+        let var_decl = Bound::new(
+            py,
+            dme_ref.build_var_decl(name, this.node_index, this.node_index, py)?,
+        )?
+        .into_any();
+        notify(&walkers, "enter_var", &var_decl)?;
+        walk_ast(dme, walker, Bound::new(py, Expression::parse(py, expr))?.as_any())?;
+        notify(&walkers, "exit_var", &var_decl)
+    });
+    if let (Some(visitor), Some((previous_proc, previous_type))) = (visitor, previous) {
+        visitor::set_proc(visitor, previous_proc, previous_type);
+    }
+    result
+}
@@ -0,0 +1,228 @@
+use pyo3::{
+    exceptions::PyValueError,
+    pyclass, pymethods,
+    types::{PyAnyMethods, PyDict, PyList, PyListMethods, PyString, PyStringMethods, PyTuple},
+    Bound, Py, PyAny, PyResult, Python,
+};
+
+use crate::typedecl::ProcDecl;
+
+use super::{
+    expression::Expression,
+    nodes::{Node, OriginalSourceLocation, SwitchCase},
+    proc_walk::{self, ProcEntry},
+    visitor, Dme,
+};
+
+/// A wildcard matching any value in a [`Pattern`], including `None`.
+#[pyclass(module = "avulto.ast", name = "Any", frozen)]
+pub struct AnyPattern;
+
+#[pymethods]
+impl AnyPattern {
+    #[new]
+    fn new() -> Self {
+        AnyPattern
+    }
+
+    fn __repr__(&self) -> String {
+        "Any()".to_string()
+    }
+}
+
+/// A structural pattern over AST nodes, such as
+/// `Pattern("Call", name="qdel", args=[Any()])`.
+#[pyclass(module = "avulto.ast", frozen)]
+pub struct Pattern {
+    #[pyo3(get)]
+    kind: String,
+    fields: Vec<(String, Py<PyAny>)>,
+}
+
+#[pymethods]
+impl Pattern {
+    #[new]
+    #[pyo3(signature = (kind, **fields))]
+    fn new(kind: String, fields: Option<&Bound<PyDict>>, py: Python<'_>) -> PyResult<Self> {
+        let is_kind = |ty: &Bound<PyAny>| ty.hasattr(kind.as_str()).unwrap_or(false);
+        if kind != "SwitchCase"
+            && !is_kind(py.get_type::<Node>().as_any())
+            && !is_kind(py.get_type::<Expression>().as_any())
+        {
+            return Err(PyValueError::new_err(format!("unknown node kind {:?}", kind)));
+        }
+
+        let mut pattern_fields = Vec::new();
+        if let Some(fields) = fields {
+            for (name, value) in fields.iter() {
+                let name = name.cast::<PyString>()?.to_str()?.to_string();
+                pattern_fields.push((name, value.unbind()));
+            }
+        }
+        Ok(Pattern {
+            kind,
+            fields: pattern_fields,
+        })
+    }
+
+    /// Whether `node` itself matches the pattern.
+    fn matches(slf: &Bound<Self>, node: &Bound<PyAny>) -> PyResult<bool> {
+        matches(slf.as_any(), node)
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let mut parts = vec![format!("{:?}", self.kind)];
+        for (name, value) in self.fields.iter() {
+            parts.push(format!("{}={}", name, value.bind(py).repr()?));
+        }
+        Ok(format!("Pattern({})", parts.join(", ")))
+    }
+}
+
+/// Whether `value` matches `pattern`. Patterns may be a [`Pattern`], [`AnyPattern`],
+/// a list or tuple of patterns matched elementwise, or a plain value. A
+/// string matches an identifier of that name, and a plain value matches a
+/// constant with that value.
+pub fn matches(pattern: &Bound<PyAny>, value: &Bound<PyAny>) -> PyResult<bool> {
+    if pattern.is_instance_of::<AnyPattern>() {
+        return Ok(true);
+    }
+
+    if let Ok(pattern) = pattern.cast::<Pattern>() {
+        let pattern = pattern.get();
+        let Ok((kind, fields)) = visitor::ast_fields(value) else {
+            return Ok(false);
+        };
+        if kind != pattern.kind {
+            return Ok(false);
+        }
+        for (name, sub_pattern) in pattern.fields.iter() {
+            let Some(field) = fields.iter().find(|field| field.name == name.as_str()) else {
+                return Err(PyValueError::new_err(format!(
+                    "{} has no field {:?}",
+                    kind, name
+                )));
+            };
+            if !matches(sub_pattern.bind(value.py()), field.value.bind(value.py()))? {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+
+    if pattern.is_instance_of::<PyList>() || pattern.is_instance_of::<PyTuple>() {
+        if !value.is_instance_of::<PyList>() && !value.is_instance_of::<PyTuple>() {
+            return Ok(false);
+        }
+        if pattern.len()? != value.len()? {
+            return Ok(false);
+        }
+        for (sub_pattern, item) in pattern.try_iter()?.zip(value.try_iter()?) {
+            if !matches(&sub_pattern?, &item?)? {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+
+    if let Ok(expr) = value.cast::<Expression>() {
+        match expr.get() {
+            Expression::Identifier { name, .. } | Expression::GlobalIdentifier { name, .. } => {
+                if let Ok(pattern) = pattern.cast::<PyString>() {
+                    return Ok(pattern.to_str()? == name);
+                }
+            }
+            Expression::Constant { constant, .. } => {
+                return pattern.eq(constant.get_val(value.py())?);
+            }
+            _ => {}
+        }
+    }
+
+    pattern.eq(value)
+}
+
+#[pyclass(module = "avulto")]
+pub struct QueryMatch {
+    #[pyo3(get)]
+    pub node: Py<PyAny>,
+    #[pyo3(get)]
+    pub source_loc: Py<PyAny>,
+    #[pyo3(get)]
+    pub proc: Py<ProcDecl>,
+}
+
+#[pymethods]
+impl QueryMatch {
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!(
+            "<QueryMatch {} in {} at {}>",
+            self.node.bind(py).repr()?,
+            self.proc.bind(py).repr()?,
+            self.source_loc.bind(py).str()?
+        ))
+    }
+}
+
+/// Collects the nodes under `item` matching `pattern`, outermost first.
+/// Nodes without a location of their own, such as switch cases, take the
+/// location of their parent.
+fn search(
+    dme: &Bound<Dme>,
+    pattern: &Bound<PyAny>,
+    item: &Bound<PyAny>,
+    parent_loc: &Option<Py<OriginalSourceLocation>>,
+    entry: &mut ProcEntry<'_>,
+    out: &Bound<PyList>,
+) -> PyResult<()> {
+    let py = item.py();
+    let source_loc = if item.is_instance_of::<SwitchCase>() {
+        parent_loc.as_ref().map(|loc| loc.clone_ref(py))
+    } else {
+        item.getattr("source_loc")?
+            .extract::<Option<Py<OriginalSourceLocation>>>()?
+            .or_else(|| parent_loc.as_ref().map(|loc| loc.clone_ref(py)))
+    };
+
+    if matches(pattern, item)? {
+        out.append(QueryMatch {
+            node: item.clone().unbind(),
+            source_loc: dme.borrow().populate_source_loc(&source_loc, py),
+            proc: entry.proc_decl()?,
+        })?;
+    }
+    for child in visitor::child_nodes(item)? {
+        search(dme, pattern, &child, &source_loc, entry, out)?;
+    }
+    Ok(())
+}
+
+/// The implementation of `DME.query`.
+pub fn query<'py>(
+    dme: &Bound<'py, Dme>,
+    pattern: &Bound<'py, PyAny>,
+    filter: Option<&Bound<'py, PyAny>>,
+    proc_name: Option<&str>,
+    include_builtins: bool,
+    py: Python<'py>,
+) -> PyResult<Bound<'py, PyList>> {
+    if !pattern.is_instance_of::<Pattern>() {
+        return Err(PyValueError::new_err(format!(
+            "expected an ast.Pattern, got {}",
+            pattern.repr()?
+        )));
+    }
+
+    let prefix = proc_walk::type_filter(filter)?;
+    let out = PyList::empty(py);
+    proc_walk::each_proc(dme, &prefix, include_builtins, py, |entry, items| {
+        if proc_name.is_some_and(|name| name != entry.name) {
+            return Ok(());
+        }
+        for item in items.iter() {
+            search(dme, pattern, item, &None, entry, &out)?;
+        }
+        Ok(())
+    })?;
+    Ok(out)
+}
@@ -0,0 +1,243 @@
+use dreammaker::{
+    Location,
+    ast::{Follow, Prefab, ProcReturnType, Spanned, Statement, Term, VarType},
+    objtree::{NodeIndex, ObjectTree, ProcValue, TypeVar},
+};
+use pyo3::{
+    Bound, Py, PyAny, PyResult, Python,
+    exceptions::{PyRuntimeError, PyValueError},
+    types::{PyAnyMethods, PyTuple, PyTupleMethods},
+};
+
+use crate::typedecl::{ProcDecl, VarDecl};
+
+use super::{
+    Dme, callgraph,
+    evaluate::resolve_context,
+    nodes::OriginalSourceLocation,
+    resolve::{self, PrefabRef, ProcKey, Scope, Visitor},
+};
+
+/// The declaration whose references are being searched for.
+pub enum Target {
+    Type(NodeIndex),
+    Proc(ProcKey),
+    /// A var, identified by the type declaring it and its name.
+    Var(NodeIndex, String),
+}
+
+impl Target {
+    /// Converts the `target` argument of `DME.find_references`.
+    pub fn from_python(dme: &Dme, target: &Bound<PyAny>) -> PyResult<Target> {
+        if let Ok(proc) = target.cast::<ProcDecl>() {
+            return Ok(Target::Proc(proc.borrow().key()));
+        }
+        let (type_arg, var_name) = if let Ok(var) = target.cast::<VarDecl>() {
+            let var = var.borrow();
+            (var.type_path.bind(target.py()).clone(), var.name.clone())
+        } else if let Ok(pair) = target.cast::<PyTuple>() {
+            if pair.len() != 2 {
+                return Err(PyValueError::new_err(
+                    "expected a (type, var name) pair as the target",
+                ));
+            }
+            (pair.get_item(0)?, pair.get_item(1)?.extract::<String>()?)
+        } else {
+            let type_index = resolve_context(dme, Some(target))?
+                .ok_or_else(|| PyValueError::new_err("invalid reference target None"))?;
+            return Ok(Target::Type(type_index));
+        };
+
+        let type_index = resolve_context(dme, Some(&type_arg))?
+            .ok_or_else(|| PyValueError::new_err("invalid reference target None"))?;
+        match resolve::find_var_declaration(&dme.objtree, type_index, &var_name) {
+            Some(declaring) => Ok(Target::Var(declaring, var_name)),
+            None => Err(PyValueError::new_err(format!(
+                "cannot find var {} on {}",
+                var_name, type_arg
+            ))),
+        }
+    }
+}
+
+struct ReferenceCollector<'a> {
+    target: &'a Target,
+    out: &'a mut Vec<Location>,
+}
+
+impl ReferenceCollector<'_> {
+    fn is_target_type(&self, objtree: &ObjectTree, var_type: Option<&VarType>) -> bool {
+        match self.target {
+            Target::Type(type_index) => var_type
+                .and_then(|vt| resolve::tree_path_type(objtree, &vt.type_path))
+                .is_some_and(|idx| idx == *type_index),
+            _ => false,
+        }
+    }
+
+    fn is_target_var(&self, objtree: &ObjectTree, type_index: NodeIndex, name: &str) -> bool {
+        match self.target {
+            Target::Var(declaring, var_name) => {
+                var_name == name
+                    && resolve::find_var_declaration(objtree, type_index, name)
+                        .is_some_and(|idx| idx == *declaring)
+            }
+            _ => false,
+        }
+    }
+
+    fn visit_prefab(&mut self, scope: &Scope, prefab: &Prefab, location: Location) {
+        let PrefabRef::Type(type_index) =
+            resolve::resolve_prefab(scope.objtree, prefab, scope.type_index)
+        else {
+            return;
+        };
+        if matches!(self.target, Target::Type(idx) if *idx == type_index) {
+            self.out.push(location);
+        }
+        for (name, _) in prefab.vars.iter() {
+            if self.is_target_var(scope.objtree, type_index, name.as_str()) {
+                self.out.push(location);
+            }
+        }
+    }
+}
+
+impl Visitor for ReferenceCollector<'_> {
+    fn visit_statement(&mut self, scope: &Scope, stmt: &Statement, location: Location) {
+        let declared = match stmt {
+            Statement::Var(var) => self.is_target_type(scope.objtree, Some(&var.var_type)),
+            Statement::Vars(vars) => vars
+                .iter()
+                .any(|var| self.is_target_type(scope.objtree, Some(&var.var_type))),
+            Statement::ForList(for_list) => {
+                self.is_target_type(scope.objtree, for_list.var_type.as_ref())
+            }
+            Statement::ForKeyValue(for_key_value) => {
+                self.is_target_type(scope.objtree, for_key_value.var_type.as_ref())
+            }
+            _ => false,
+        };
+        if declared {
+            self.out.push(location);
+        }
+    }
+
+    fn visit_term(&mut self, scope: &Scope, term: &Spanned<Term>) {
+        match &term.elem {
+            Term::Prefab(prefab) | Term::NewPrefab { prefab, .. } => {
+                self.visit_prefab(scope, prefab, term.location);
+            }
+            Term::Ident(name) => {
+                if !scope.is_local(name.as_str())
+                    && self.is_target_var(scope.objtree, scope.type_index, name.as_str())
+                {
+                    self.out.push(term.location);
+                }
+            }
+            Term::GlobalIdent(name) => {
+                let root = scope.objtree.root().index();
+                if self.is_target_var(scope.objtree, root, name.as_str()) {
+                    self.out.push(term.location);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_follow(&mut self, scope: &Scope, base: Option<NodeIndex>, follow: &Spanned<Follow>) {
+        let (Follow::Field(_, name) | Follow::StaticField(name)) = &follow.elem else {
+            return;
+        };
+        if base.is_some_and(|base| self.is_target_var(scope.objtree, base, name.as_str())) {
+            self.out.push(follow.location);
+        }
+    }
+
+    fn visit_type_var(
+        &mut self,
+        objtree: &ObjectTree,
+        type_index: NodeIndex,
+        name: &str,
+        var: &TypeVar,
+    ) {
+        match &var.declaration {
+            Some(declaration) => {
+                if self.is_target_type(objtree, Some(&declaration.var_type)) {
+                    self.out.push(declaration.location);
+                }
+            }
+            None => {
+                if self.is_target_var(objtree, type_index, name) {
+                    self.out.push(var.value.location);
+                }
+            }
+        }
+    }
+
+    fn visit_proc(&mut self, objtree: &ObjectTree, _key: &ProcKey, proc_value: &ProcValue) {
+        for param in proc_value.parameters.iter() {
+            if self.is_target_type(objtree, Some(&param.var_type)) {
+                self.out.push(param.location);
+            }
+        }
+        if let (Target::Type(type_index), Some(ProcReturnType::TypePath(type_path))) =
+            (self.target, &proc_value.return_type)
+        {
+            if resolve::tree_path_type(objtree, type_path) == Some(*type_index) {
+                self.out.push(proc_value.location);
+            }
+        }
+    }
+}
+
+/// Finds every location referring to `target`, sorted by file and position.
+pub fn find_references(objtree: &ObjectTree, target: &Target) -> Vec<Location> {
+    let mut out = Vec::new();
+
+    if let Target::Proc(key) = target {
+        let mut edges = Vec::new();
+        for caller in callgraph::procs_with_code(objtree) {
+            callgraph::collect_calls(objtree, &caller, &mut edges);
+        }
+        out.extend(
+            edges
+                .iter()
+                .filter(|edge| edge.callee.as_ref() == Some(key))
+                .map(|edge| edge.location),
+        );
+    } else {
+        resolve::walk_tree(
+            objtree,
+            &mut ReferenceCollector {
+                target,
+                out: &mut out,
+            },
+        );
+    }
+
+    out.sort();
+    out.dedup();
+    out
+}
+
+/// The implementation of `DME.find_references`.
+pub fn find_references_py(
+    dme: &Dme,
+    target: &Bound<PyAny>,
+    py: Python<'_>,
+) -> PyResult<Vec<Py<PyAny>>> {
+    if !dme.procs_parsed {
+        return Err(PyRuntimeError::new_err(
+            "parse_procs=True was not included in DME's constructor",
+        ));
+    }
+    let target = Target::from_python(dme, target)?;
+    Ok(find_references(&dme.objtree, &target)
+        .iter()
+        .map(|location| {
+            let osl = Some(OriginalSourceLocation::from_location(location));
+            dme.populate_source_loc(&osl, py)
+        })
+        .collect())
+}
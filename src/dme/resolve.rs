@@ -0,0 +1,750 @@
+//! Static resolution of the names used in proc bodies against the object
+//! tree, shared by the analyses which work directly on SpacemanDMM's AST
+//! rather than on the Python-facing node classes.
+
+use std::collections::HashMap;
+
+use dreammaker::{
+    Location,
+    ast::{
+        AssignOp, Case, Expression, Follow, PathOp, Prefab, ProcDeclKind, ProcReturnType,
+        Spanned, Statement, Term, VarType,
+    },
+    objtree::{NodeIndex, ObjectTree, ProcValue, TypeVar},
+};
+
+/// Identifies a single definition of a proc on a type.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProcKey {
+    pub type_index: NodeIndex,
+    pub name: String,
+    pub proc_index: usize,
+}
+
+/// What a prefab such as `/obj/item` or `.proc/attack` refers to.
+pub enum PrefabRef {
+    Type(NodeIndex),
+    Proc(NodeIndex, String),
+    Unknown,
+}
+
+pub fn type_by_path(objtree: &ObjectTree, path: &str) -> Option<NodeIndex> {
+    objtree.find(path).map(|ty| ty.index())
+}
+
+/// Looks up the type of a declared type path such as `obj/item`.
+pub fn tree_path_type(objtree: &ObjectTree, tree_path: &[String]) -> Option<NodeIndex> {
+    if tree_path.is_empty() {
+        return None;
+    }
+    let mut path = String::new();
+    for part in tree_path {
+        path.push('/');
+        path.push_str(part);
+    }
+    type_by_path(objtree, &path)
+}
+
+/// Resolves a prefab to the type or proc it names. Proc paths relative to
+/// the current type, such as `.proc/attack`, are looked up from `context`.
+pub fn resolve_prefab(objtree: &ObjectTree, prefab: &Prefab, context: NodeIndex) -> PrefabRef {
+    let mut relative = false;
+    let mut segments: Vec<String> = Vec::new();
+    for (i, (op, name)) in prefab.path.iter().enumerate() {
+        match op {
+            PathOp::Slash => {}
+            PathOp::Dot if i == 0 => relative = true,
+            _ => return PrefabRef::Unknown,
+        }
+        segments.push(name.to_string());
+    }
+
+    if let Some(pos) = segments.iter().position(|s| s == "proc" || s == "verb") {
+        if pos + 2 != segments.len() {
+            return PrefabRef::Unknown;
+        }
+        let type_index = if pos == 0 {
+            Some(if relative { context } else { objtree.root().index() })
+        } else if relative {
+            None
+        } else {
+            tree_path_type(objtree, &segments[..pos])
+        };
+        return match type_index {
+            Some(type_index) => PrefabRef::Proc(type_index, segments[pos + 1].clone()),
+            None => PrefabRef::Unknown,
+        };
+    }
+
+    if relative {
+        return PrefabRef::Unknown;
+    }
+    match tree_path_type(objtree, &segments) {
+        Some(type_index) => PrefabRef::Type(type_index),
+        None => PrefabRef::Unknown,
+    }
+}
+
+/// Finds the definition of `name` which a call on an instance of
+/// `type_index` would reach: the last definition on the nearest type,
+/// walking up to global procs on the root.
+pub fn find_proc(objtree: &ObjectTree, type_index: NodeIndex, name: &str) -> Option<ProcKey> {
+    let mut index = Some(type_index);
+    while let Some(idx) = index {
+        if let Some(type_proc) = objtree[idx].procs.get(name) {
+            if !type_proc.value.is_empty() {
+                return Some(ProcKey {
+                    type_index: idx,
+                    name: name.to_string(),
+                    proc_index: type_proc.value.len() - 1,
+                });
+            }
+        }
+        index = objtree[idx].parent_type_index();
+    }
+
+    None
+}
+
+/// Whether the proc `key` was declared as a verb, on its type or the
+/// nearest parent declaring it.
+pub fn is_verb(objtree: &ObjectTree, key: &ProcKey) -> bool {
+    let mut index = Some(key.type_index);
+    while let Some(idx) = index {
+        if let Some(declaration) = objtree[idx]
+            .procs
+            .get(&key.name)
+            .and_then(|type_proc| type_proc.declaration.as_ref())
+        {
+            return matches!(declaration.kind, ProcDeclKind::Verb);
+        }
+        index = objtree[idx].parent_type_index();
+    }
+    false
+}
+
+/// Finds the definition reached by `..()` from `key`.
+pub fn parent_proc(objtree: &ObjectTree, key: &ProcKey) -> Option<ProcKey> {
+    if key.proc_index > 0 {
+        return Some(ProcKey {
+            proc_index: key.proc_index - 1,
+            ..key.clone()
+        });
+    }
+    objtree[key.type_index]
+        .parent_type_index()
+        .and_then(|parent| find_proc(objtree, parent, &key.name))
+}
+
+/// Finds the type declaring the var `name`, starting at `type_index` and
+/// walking up through its parents to global vars on the root.
+pub fn find_var_declaration(
+    objtree: &ObjectTree,
+    type_index: NodeIndex,
+    name: &str,
+) -> Option<NodeIndex> {
+    let mut index = Some(type_index);
+    while let Some(idx) = index {
+        let type_def = &objtree[idx];
+        if type_def
+            .vars
+            .get(name)
+            .is_some_and(|var| var.declaration.is_some())
+        {
+            return Some(idx);
+        }
+        index = if type_def.is_root() {
+            None
+        } else {
+            type_def.parent_type_index()
+        };
+    }
+
+    None
+}
+
+/// The declared type of the var `name` as seen from `type_index`.
+pub fn var_type(objtree: &ObjectTree, type_index: NodeIndex, name: &str) -> Option<NodeIndex> {
+    let declaring = find_var_declaration(objtree, type_index, name)?;
+    let declaration = objtree[declaring].vars[name].declaration.as_ref()?;
+    tree_path_type(objtree, &declaration.var_type.type_path)
+}
+
+/// The declared `as` return type of a proc, inherited from the definitions
+/// it overrides if it does not declare one itself.
+pub fn proc_return_type(objtree: &ObjectTree, key: &ProcKey) -> Option<NodeIndex> {
+    let mut current = Some(key.clone());
+    while let Some(key) = current {
+        let proc_value = &objtree[key.type_index].procs[&key.name].value[key.proc_index];
+        match &proc_value.return_type {
+            Some(ProcReturnType::TypePath(type_path)) => {
+                return tree_path_type(objtree, type_path);
+            }
+            Some(ProcReturnType::InputType(_)) => return None,
+            None => current = parent_proc(objtree, &key),
+        }
+    }
+
+    None
+}
+
+/// The names in scope at a point in a proc body or var initializer, and
+/// their static types.
+pub struct Scope<'a> {
+    pub objtree: &'a ObjectTree,
+    /// The type the code belongs to.
+    pub type_index: NodeIndex,
+    /// The proc being walked, or `None` for a var initializer.
+    pub proc: Option<ProcKey>,
+    frames: Vec<HashMap<String, Option<NodeIndex>>>,
+    writing: bool,
+    spawn_depth: usize,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(objtree: &'a ObjectTree, proc: ProcKey) -> Self {
+        let mut args = HashMap::new();
+        let proc_value = &objtree[proc.type_index].procs[&proc.name].value[proc.proc_index];
+        for param in proc_value.parameters.iter() {
+            args.insert(
+                param.name.to_string(),
+                tree_path_type(objtree, &param.var_type.type_path),
+            );
+        }
+        Scope {
+            objtree,
+            type_index: proc.type_index,
+            proc: Some(proc),
+            frames: vec![args],
+            writing: false,
+            spawn_depth: 0,
+        }
+    }
+
+    pub fn for_type(objtree: &'a ObjectTree, type_index: NodeIndex) -> Self {
+        Scope {
+            objtree,
+            type_index,
+            proc: None,
+            frames: vec![HashMap::new()],
+            writing: false,
+            spawn_depth: 0,
+        }
+    }
+
+    fn push(&mut self) {
+        self.frames.push(HashMap::new());
+    }
+
+    fn pop(&mut self) {
+        self.frames.pop();
+    }
+
+    fn declare(&mut self, name: &str, var_type: Option<&VarType>) {
+        let declared = var_type.and_then(|vt| tree_path_type(self.objtree, &vt.type_path));
+        if let Some(frame) = self.frames.last_mut() {
+            frame.insert(name.to_string(), declared);
+        }
+    }
+
+    /// Whether `name` is a local var or argument of the proc.
+    pub fn is_local(&self, name: &str) -> bool {
+        self.frames.iter().any(|frame| frame.contains_key(name))
+    }
+
+    /// Whether the term or follow being visited is the target of a plain
+    /// `=` assignment, and so is written without being read.
+    pub fn is_write(&self) -> bool {
+        self.writing
+    }
+
+    /// Whether the code being visited is inside a `spawn()` block, and so
+    /// runs separately from the rest of the proc.
+    pub fn in_spawn(&self) -> bool {
+        self.spawn_depth > 0
+    }
+
+    /// The type of `src`, or `None` in a global proc.
+    pub fn src_type(&self) -> Option<NodeIndex> {
+        if self.objtree[self.type_index].is_root() {
+            None
+        } else {
+            Some(self.type_index)
+        }
+    }
+
+    pub fn ident_type(&self, name: &str) -> Option<NodeIndex> {
+        for frame in self.frames.iter().rev() {
+            if let Some(declared) = frame.get(name) {
+                return *declared;
+            }
+        }
+        match name {
+            "src" => self.src_type(),
+            "usr" => type_by_path(self.objtree, "/mob"),
+            "world" => type_by_path(self.objtree, "/world"),
+            "args" => type_by_path(self.objtree, "/list"),
+            _ => var_type(self.objtree, self.type_index, name),
+        }
+    }
+
+    pub fn term_type(&self, term: &Term) -> Option<NodeIndex> {
+        let objtree = self.objtree;
+        match term {
+            Term::Ident(name) => self.ident_type(name.as_str()),
+            Term::Expr(expr) => self.expr_type(expr),
+            Term::Call(name, _) => find_proc(objtree, self.type_index, name.as_str())
+                .and_then(|key| proc_return_type(objtree, &key)),
+            Term::SelfCall(_) => self
+                .proc
+                .as_ref()
+                .and_then(|key| proc_return_type(objtree, key)),
+            Term::ParentCall(_) => self
+                .proc
+                .as_ref()
+                .and_then(|key| parent_proc(objtree, key))
+                .and_then(|key| proc_return_type(objtree, &key)),
+            Term::GlobalIdent(name) => var_type(objtree, objtree.root().index(), name.as_str()),
+            Term::GlobalCall(name, _) => find_proc(objtree, objtree.root().index(), name.as_str())
+                .and_then(|key| proc_return_type(objtree, &key)),
+            Term::NewPrefab { prefab, .. } => {
+                match resolve_prefab(objtree, prefab, self.type_index) {
+                    PrefabRef::Type(type_index) => Some(type_index),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The type of `follow` applied to an expression of type `base`.
+    pub fn follow_type(&self, base: Option<NodeIndex>, follow: &Follow) -> Option<NodeIndex> {
+        let base = base?;
+        match follow {
+            Follow::Field(_, name) | Follow::StaticField(name) => {
+                var_type(self.objtree, base, name.as_str())
+            }
+            Follow::Call(_, name, _) => find_proc(self.objtree, base, name.as_str())
+                .and_then(|key| proc_return_type(self.objtree, &key)),
+            _ => None,
+        }
+    }
+
+    /// The statically known type of `expr`, if any.
+    pub fn expr_type(&self, expr: &Expression) -> Option<NodeIndex> {
+        match expr {
+            Expression::Base { term, follow } => {
+                let mut current = self.term_type(&term.elem);
+                for f in follow.iter() {
+                    current = self.follow_type(current, &f.elem);
+                }
+                current
+            }
+            Expression::TernaryOp { if_, else_, .. } => {
+                let if_type = self.expr_type(if_);
+                if if_type == self.expr_type(else_) {
+                    if_type
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The type a follow on `term` applies to. This is the static type of
+    /// the term, except for type paths such as `/obj/item::proc_name()`,
+    /// where it is the type itself.
+    fn follow_base(&self, term: &Term) -> Option<NodeIndex> {
+        match term {
+            Term::Prefab(prefab) => {
+                match resolve_prefab(self.objtree, prefab, self.type_index) {
+                    PrefabRef::Type(type_index) => Some(type_index),
+                    _ => None,
+                }
+            }
+            _ => self.term_type(term),
+        }
+    }
+}
+
+/// Callbacks for [walk_proc]. Every method has an empty default, and
+/// children are always walked after their parent is visited.
+pub trait Visitor {
+    fn visit_statement(&mut self, _scope: &Scope, _stmt: &Statement, _location: Location) {}
+
+    /// Called for the term which starts each chain of follows.
+    fn visit_term(&mut self, _scope: &Scope, _term: &Spanned<Term>) {}
+
+    /// Called for each follow, with the static type of the expression it
+    /// follows.
+    fn visit_follow(
+        &mut self,
+        _scope: &Scope,
+        _base: Option<NodeIndex>,
+        _follow: &Spanned<Follow>,
+    ) {
+    }
+
+    /// Called by [walk_tree] for each var declared or overridden on a type.
+    fn visit_type_var(
+        &mut self,
+        _objtree: &ObjectTree,
+        _type_index: NodeIndex,
+        _name: &str,
+        _var: &TypeVar,
+    ) {
+    }
+
+    /// Called by [walk_tree] for each proc definition, before its body is
+    /// walked.
+    fn visit_proc(&mut self, _objtree: &ObjectTree, _key: &ProcKey, _proc_value: &ProcValue) {}
+}
+
+/// Walks every var initializer and proc body in the object tree, skipping
+/// builtins.
+pub fn walk_tree<V: Visitor>(objtree: &ObjectTree, visitor: &mut V) {
+    for ty in objtree.iter_types() {
+        for (name, var) in ty.vars.iter() {
+            if var.value.location.is_builtins() {
+                continue;
+            }
+            visitor.visit_type_var(objtree, ty.index(), name, var);
+            if let Some(expr) = &var.value.expression {
+                walk_var_value(objtree, ty.index(), expr, visitor);
+            }
+        }
+
+        for (name, type_proc) in ty.procs.iter() {
+            for (proc_index, proc_value) in type_proc.value.iter().enumerate() {
+                if proc_value.location.is_builtins() {
+                    continue;
+                }
+                let key = ProcKey {
+                    type_index: ty.index(),
+                    name: name.clone(),
+                    proc_index,
+                };
+                visitor.visit_proc(objtree, &key, proc_value);
+                walk_proc(objtree, &key, visitor);
+            }
+        }
+    }
+}
+
+/// Walks the body of the proc definition `key`, including its argument
+/// defaults, tracking local vars as they are declared.
+pub fn walk_proc<V: Visitor>(objtree: &ObjectTree, key: &ProcKey, visitor: &mut V) {
+    let proc_value = &objtree[key.type_index].procs[&key.name].value[key.proc_index];
+    let mut scope = Scope::new(objtree, key.clone());
+    for param in proc_value.parameters.iter() {
+        if let Some(default) = &param.default {
+            walk_expr(&mut scope, default, visitor);
+        }
+    }
+    if let Some(code) = &proc_value.code {
+        walk_block(&mut scope, code, visitor);
+    }
+}
+
+/// Walks the initial value of a var declared or overridden on `type_index`.
+pub fn walk_var_value<V: Visitor>(
+    objtree: &ObjectTree,
+    type_index: NodeIndex,
+    expr: &Expression,
+    visitor: &mut V,
+) {
+    walk_expr(&mut Scope::for_type(objtree, type_index), expr, visitor);
+}
+
+fn walk_block<V: Visitor>(scope: &mut Scope, block: &[Spanned<Statement>], visitor: &mut V) {
+    scope.push();
+    for stmt in block.iter() {
+        walk_statement(scope, &stmt.elem, stmt.location, visitor);
+    }
+    scope.pop();
+}
+
+fn walk_statement<V: Visitor>(
+    scope: &mut Scope,
+    stmt: &Statement,
+    location: Location,
+    visitor: &mut V,
+) {
+    visitor.visit_statement(scope, stmt, location);
+    match stmt {
+        Statement::Expr(expr) | Statement::Throw(expr) | Statement::Del(expr) => {
+            walk_expr(scope, expr, visitor);
+        }
+        Statement::Return(expr) | Statement::Crash(expr) => {
+            if let Some(expr) = expr {
+                walk_expr(scope, expr, visitor);
+            }
+        }
+        Statement::While { condition, block } => {
+            walk_expr(scope, condition, visitor);
+            walk_block(scope, block, visitor);
+        }
+        Statement::DoWhile { block, condition } => {
+            walk_block(scope, block, visitor);
+            walk_expr(scope, &condition.elem, visitor);
+        }
+        Statement::If { arms, else_arm } => {
+            for (condition, block) in arms.iter() {
+                walk_expr(scope, &condition.elem, visitor);
+                walk_block(scope, block, visitor);
+            }
+            if let Some(block) = else_arm {
+                walk_block(scope, block, visitor);
+            }
+        }
+        Statement::ForInfinite { block } => walk_block(scope, block, visitor),
+        Statement::ForLoop {
+            init,
+            test,
+            inc,
+            block,
+        } => {
+            scope.push();
+            if let Some(init) = init {
+                walk_statement(scope, init, location, visitor);
+            }
+            if let Some(test) = test {
+                walk_expr(scope, test, visitor);
+            }
+            if let Some(inc) = inc {
+                walk_statement(scope, inc, location, visitor);
+            }
+            walk_block(scope, block, visitor);
+            scope.pop();
+        }
+        Statement::ForList(for_list) => {
+            if let Some(in_list) = &for_list.in_list {
+                walk_expr(scope, in_list, visitor);
+            }
+            scope.push();
+            scope.declare(&for_list.name, for_list.var_type.as_ref());
+            walk_block(scope, &for_list.block, visitor);
+            scope.pop();
+        }
+        Statement::ForKeyValue(for_key_value) => {
+            if let Some(in_list) = &for_key_value.in_list {
+                walk_expr(scope, in_list, visitor);
+            }
+            scope.push();
+            scope.declare(&for_key_value.key, for_key_value.var_type.as_ref());
+            scope.declare(&for_key_value.value, None);
+            walk_block(scope, &for_key_value.block, visitor);
+            scope.pop();
+        }
+        Statement::ForRange(for_range) => {
+            walk_expr(scope, &for_range.start, visitor);
+            walk_expr(scope, &for_range.end, visitor);
+            if let Some(step) = &for_range.step {
+                walk_expr(scope, step, visitor);
+            }
+            scope.push();
+            scope.declare(&for_range.name, None);
+            walk_block(scope, &for_range.block, visitor);
+            scope.pop();
+        }
+        Statement::Var(var) => {
+            if let Some(value) = &var.value {
+                walk_expr(scope, value, visitor);
+            }
+            scope.declare(&var.name, Some(&var.var_type));
+        }
+        Statement::Vars(vars) => {
+            for var in vars.iter() {
+                if let Some(value) = &var.value {
+                    walk_expr(scope, value, visitor);
+                }
+                scope.declare(&var.name, Some(&var.var_type));
+            }
+        }
+        Statement::Setting { .. } => {}
+        Statement::Spawn { delay, block } => {
+            if let Some(delay) = delay {
+                walk_expr(scope, delay, visitor);
+            }
+            scope.spawn_depth += 1;
+            walk_block(scope, block, visitor);
+            scope.spawn_depth -= 1;
+        }
+        Statement::Switch {
+            input,
+            cases,
+            default,
+        } => {
+            walk_expr(scope, input, visitor);
+            for (case, block) in cases.iter() {
+                for case_type in case.elem.iter() {
+                    match case_type {
+                        Case::Exact(expr) => walk_expr(scope, expr, visitor),
+                        Case::Range(start, end) => {
+                            walk_expr(scope, start, visitor);
+                            walk_expr(scope, end, visitor);
+                        }
+                    }
+                }
+                walk_block(scope, block, visitor);
+            }
+            if let Some(block) = default {
+                walk_block(scope, block, visitor);
+            }
+        }
+        Statement::TryCatch {
+            try_block,
+            catch_params,
+            catch_block,
+        } => {
+            walk_block(scope, try_block, visitor);
+            scope.push();
+            for param in catch_params.iter() {
+                if let Some((name, type_path)) = param.split_last() {
+                    let type_path = match type_path.split_first() {
+                        Some((first, rest)) if first == "var" => rest,
+                        _ => type_path,
+                    };
+                    let declared = tree_path_type(scope.objtree, type_path);
+                    if let Some(frame) = scope.frames.last_mut() {
+                        frame.insert(name.to_string(), declared);
+                    }
+                }
+            }
+            walk_block(scope, catch_block, visitor);
+            scope.pop();
+        }
+        Statement::Label { block, .. } => walk_block(scope, block, visitor),
+        Statement::Continue(_) | Statement::Break(_) | Statement::Goto(_) => {}
+    }
+}
+
+pub fn walk_expr<V: Visitor>(scope: &mut Scope, expr: &Expression, visitor: &mut V) {
+    walk_expr_access(scope, expr, false, visitor);
+}
+
+/// Walks `expr`, which is the target of a plain assignment if `write` is
+/// set, in which case only its final term or follow is marked as written.
+fn walk_expr_access<V: Visitor>(
+    scope: &mut Scope,
+    expr: &Expression,
+    write: bool,
+    visitor: &mut V,
+) {
+    match expr {
+        Expression::Base { term, follow } => {
+            walk_term(scope, term, write && follow.is_empty(), visitor);
+            let mut base = scope.follow_base(&term.elem);
+            for (i, f) in follow.iter().enumerate() {
+                scope.writing = write && i + 1 == follow.len();
+                visitor.visit_follow(scope, base, f);
+                scope.writing = false;
+                match &f.elem {
+                    Follow::Index(_, index) => walk_expr(scope, index, visitor),
+                    Follow::Call(_, _, args) => {
+                        for arg in args.iter() {
+                            walk_expr(scope, arg, visitor);
+                        }
+                    }
+                    _ => {}
+                }
+                base = scope.follow_type(base, &f.elem);
+            }
+        }
+        Expression::AssignOp { op, lhs, rhs } => {
+            walk_expr_access(scope, lhs, matches!(op, AssignOp::Assign), visitor);
+            walk_expr(scope, rhs, visitor);
+        }
+        Expression::BinaryOp { lhs, rhs, .. } => {
+            walk_expr(scope, lhs, visitor);
+            walk_expr(scope, rhs, visitor);
+        }
+        Expression::TernaryOp { cond, if_, else_ } => {
+            walk_expr(scope, cond, visitor);
+            walk_expr(scope, if_, visitor);
+            walk_expr(scope, else_, visitor);
+        }
+    }
+}
+
+fn walk_exprs<V: Visitor>(scope: &mut Scope, exprs: &[Expression], visitor: &mut V) {
+    for expr in exprs.iter() {
+        walk_expr(scope, expr, visitor);
+    }
+}
+
+fn walk_prefab<V: Visitor>(scope: &mut Scope, prefab: &Prefab, visitor: &mut V) {
+    for (_, value) in prefab.vars.iter() {
+        walk_expr(scope, value, visitor);
+    }
+}
+
+fn walk_term<V: Visitor>(scope: &mut Scope, term: &Spanned<Term>, write: bool, visitor: &mut V) {
+    scope.writing = write;
+    visitor.visit_term(scope, term);
+    scope.writing = false;
+    match &term.elem {
+        Term::Expr(expr) => walk_expr(scope, expr, visitor),
+        Term::Prefab(prefab) => walk_prefab(scope, prefab, visitor),
+        Term::InterpString(_, parts) => {
+            for (expr, _) in parts.iter() {
+                if let Some(expr) = expr {
+                    walk_expr(scope, expr, visitor);
+                }
+            }
+        }
+        Term::Call(_, args)
+        | Term::SelfCall(args)
+        | Term::ParentCall(args)
+        | Term::GlobalCall(_, args)
+        | Term::List(args) => walk_exprs(scope, args, visitor),
+        Term::NewImplicit { args } | Term::NewMiniExpr { args, .. } => {
+            if let Some(args) = args {
+                walk_exprs(scope, args, visitor);
+            }
+        }
+        Term::NewPrefab { prefab, args } => {
+            walk_prefab(scope, prefab, visitor);
+            if let Some(args) = args {
+                walk_exprs(scope, args, visitor);
+            }
+        }
+        Term::Input { args, in_list, .. } => {
+            walk_exprs(scope, args, visitor);
+            if let Some(in_list) = in_list {
+                walk_expr(scope, in_list, visitor);
+            }
+        }
+        Term::Locate { args, in_list } => {
+            walk_exprs(scope, args, visitor);
+            if let Some(in_list) = in_list {
+                walk_expr(scope, in_list, visitor);
+            }
+        }
+        Term::Pick(choices) => {
+            for (weight, value) in choices.iter() {
+                if let Some(weight) = weight {
+                    walk_expr(scope, weight, visitor);
+                }
+                walk_expr(scope, value, visitor);
+            }
+        }
+        Term::DynamicCall(proc_args, call_args) => {
+            walk_exprs(scope, proc_args, visitor);
+            walk_exprs(scope, call_args, visitor);
+        }
+        Term::ExternalCall {
+            library,
+            function,
+            args,
+        } => {
+            if let Some(library) = library {
+                walk_expr(scope, library, visitor);
+            }
+            walk_expr(scope, function, visitor);
+            walk_exprs(scope, args, visitor);
+        }
+        _ => {}
+    }
+}
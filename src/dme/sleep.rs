@@ -0,0 +1,161 @@
+//! Finds whether a proc may sleep, following its calls transitively.
+
+use std::collections::HashSet;
+
+use dreammaker::{
+    ast::{Expression, Follow, Spanned, Statement, Term},
+    objtree::{NodeIndex, ObjectTree, ProcValue},
+};
+
+use super::{
+    callgraph::{CallCollector, CallEdge, CallKind},
+    resolve::{self, ProcKey, Scope, Visitor},
+    type_index::TypeIndex,
+};
+
+/// Builtin procs which sleep the calling proc.
+const SLEEPING_PROCS: &[&str] = &["sleep", "alert", "input", "shell", "winexists", "winget"];
+
+/// Collects the calls a proc makes outside of `spawn()` blocks, along with
+/// its uses of `input()`.
+struct SleepCollector<'a> {
+    calls: CallCollector<'a>,
+}
+
+impl Visitor for SleepCollector<'_> {
+    fn visit_term(&mut self, scope: &Scope, term: &Spanned<Term>) {
+        if scope.in_spawn() {
+            return;
+        }
+        if let Term::Input { .. } = &term.elem {
+            self.calls.push(None, "input", CallKind::Call, term.location);
+        } else {
+            self.calls.visit_term(scope, term);
+        }
+    }
+
+    fn visit_follow(&mut self, scope: &Scope, base: Option<NodeIndex>, follow: &Spanned<Follow>) {
+        if !scope.in_spawn() {
+            self.calls.visit_follow(scope, base, follow);
+        }
+    }
+}
+
+fn proc_value<'a>(objtree: &'a ObjectTree, key: &ProcKey) -> &'a ProcValue {
+    &objtree[key.type_index].procs[&key.name].value[key.proc_index]
+}
+
+/// Whether `edge` is a call to a builtin which sleeps.
+fn is_sleeping_builtin(objtree: &ObjectTree, edge: &CallEdge) -> bool {
+    edge.kind == CallKind::Call
+        && SLEEPING_PROCS.contains(&edge.name.as_str())
+        && edge
+            .callee
+            .as_ref()
+            .is_none_or(|callee| proc_value(objtree, callee).location.is_builtins())
+}
+
+fn is_false(expr: &Expression) -> bool {
+    match expr {
+        Expression::Base { term, follow } if follow.is_empty() => match term.elem {
+            Term::Int(i) => i == 0,
+            Term::Float(f) => f == 0.0,
+            Term::Null => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether callers of the proc wait for it to return, which is not the case
+/// when it sets `waitfor = FALSE`.
+fn waits_for(objtree: &ObjectTree, key: &ProcKey) -> bool {
+    let Some(code) = &proc_value(objtree, key).code else {
+        return true;
+    };
+    !code.iter().any(|stmt| {
+        matches!(&stmt.elem, Statement::Setting { name, value, .. }
+            if name == "waitfor" && is_false(value))
+    })
+}
+
+/// The definitions a call to `callee` may reach at runtime: `callee` itself,
+/// then the last definition of the proc on each of its subtypes which
+/// overrides it, in path order.
+fn dispatch_targets(objtree: &ObjectTree, types: &TypeIndex, callee: &ProcKey) -> Vec<ProcKey> {
+    let mut out = vec![callee.clone()];
+    let mut pending: Vec<NodeIndex> =
+        types.children(callee.type_index).iter().rev().copied().collect();
+    while let Some(type_index) = pending.pop() {
+        if let Some(type_proc) = objtree[type_index].procs.get(&callee.name) {
+            out.push(ProcKey {
+                type_index,
+                name: callee.name.clone(),
+                proc_index: type_proc.value.len() - 1,
+            });
+        }
+        pending.extend(types.children(type_index).iter().rev());
+    }
+    out
+}
+
+fn chain_from(
+    objtree: &ObjectTree,
+    types: &TypeIndex,
+    key: &ProcKey,
+    seen: &mut HashSet<ProcKey>,
+) -> Option<Vec<CallEdge>> {
+    let mut edges = Vec::new();
+    resolve::walk_proc(
+        objtree,
+        key,
+        &mut SleepCollector {
+            calls: CallCollector::new(key, &mut edges),
+        },
+    );
+
+    // Prefer sleeping directly, which gives the shortest explanation.
+    if let Some(index) = edges.iter().position(|edge| is_sleeping_builtin(objtree, edge)) {
+        return Some(vec![edges.swap_remove(index)]);
+    }
+
+    for edge in edges {
+        if !matches!(edge.kind, CallKind::Call | CallKind::SelfCall | CallKind::ParentCall) {
+            continue;
+        }
+        let Some(callee) = edge.callee.clone() else {
+            continue;
+        };
+        // Calls other than `..()` and `.()` may reach an override instead.
+        let targets = if edge.kind == CallKind::Call {
+            dispatch_targets(objtree, types, &callee)
+        } else {
+            vec![callee]
+        };
+        for target in targets {
+            if !waits_for(objtree, &target) || !seen.insert(target.clone()) {
+                continue;
+            }
+            if let Some(mut chain) = chain_from(objtree, types, &target, seen) {
+                chain.insert(0, edge);
+                return Some(chain);
+            }
+        }
+    }
+    None
+}
+
+/// Returns the calls through which the proc definition `key` may sleep,
+/// starting in its body and ending with a call to a sleeping builtin or
+/// `input()`, or `None` if it never sleeps. Code in `spawn()` blocks and
+/// calls to procs which set `waitfor = FALSE` cannot sleep the caller. Calls
+/// are followed into the overrides of the called proc on subtypes, since any
+/// of them may be reached at runtime.
+pub fn sleep_chain(
+    objtree: &ObjectTree,
+    types: &TypeIndex,
+    key: &ProcKey,
+) -> Option<Vec<CallEdge>> {
+    let mut seen = HashSet::from([key.clone()]);
+    chain_from(objtree, types, key, &mut seen)
+}
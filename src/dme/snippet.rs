@@ -0,0 +1,92 @@
+use dreammaker::{
+    Context, Severity,
+    ast::{Spanned, Statement},
+    objtree::ObjectTree,
+    preprocessor::Preprocessor,
+};
+use pyo3::{
+    IntoPyObject, Py, PyResult, Python, exceptions::PySyntaxError, pyfunction, types::PyList,
+};
+
+use super::{expression::Expression, nodes::Node};
+
+const SNIPPET_PROC_NAME: &str = "__avulto_snippet";
+
+/// Parses `body` as the body of a global proc, returning the object tree it
+/// was parsed into.
+fn parse_proc_body(body: &str) -> PyResult<ObjectTree> {
+    let mut buffer = format!("/proc/{}()\n", SNIPPET_PROC_NAME);
+    for line in body.lines() {
+        buffer.push('\t');
+        buffer.push_str(line);
+        buffer.push('\n');
+    }
+
+    let ctx = Context::default();
+    let pp = Preprocessor::from_buffer(&ctx, "(snippet)".into(), buffer);
+    let indents = dreammaker::indents::IndentProcessor::new(&ctx, pp);
+    let mut parser = dreammaker::parser::Parser::new(&ctx, indents);
+    parser.enable_procs();
+    let (fatal_errored, tree) = parser.parse_object_tree_2();
+
+    let errors: Vec<String> = ctx
+        .errors()
+        .iter()
+        .filter(|e| e.severity() == Severity::Error)
+        .map(|e| {
+            format!(
+                "{}:{}: {}",
+                e.location().line,
+                e.location().column,
+                e.description()
+            )
+        })
+        .collect();
+    if fatal_errored || !errors.is_empty() {
+        return Err(PySyntaxError::new_err(format!(
+            "failed to parse snippet: {}",
+            errors.join("; ")
+        )));
+    }
+
+    Ok(tree)
+}
+
+fn snippet_code(tree: &ObjectTree) -> PyResult<&[Spanned<Statement>]> {
+    tree.root()
+        .get()
+        .procs
+        .get(SNIPPET_PROC_NAME)
+        .and_then(|p| p.value.first())
+        .and_then(|v| v.code.as_deref())
+        .ok_or_else(|| PySyntaxError::new_err("failed to parse snippet"))
+}
+
+/// Parse a single DM expression.
+#[pyfunction]
+pub fn parse_expression(src: &str, py: Python<'_>) -> PyResult<Py<Expression>> {
+    let tree = parse_proc_body(format!("return {}", src.trim()).as_str())?;
+    let code = snippet_code(&tree)?;
+    match code.first().map(|stmt| &stmt.elem) {
+        Some(Statement::Return(Some(expr))) if code.len() == 1 => {
+            Ok(Expression::parse(py, expr).into_pyobject(py)?.unbind())
+        }
+        _ => Err(PySyntaxError::new_err(format!(
+            "not a single expression: {:?}",
+            src
+        ))),
+    }
+}
+
+/// Parse one or more DM statements, as they would appear in a proc body.
+#[pyfunction]
+pub fn parse_statements(src: &str, py: Python<'_>) -> PyResult<Py<PyList>> {
+    let tree = parse_proc_body(src)?;
+    let code = snippet_code(&tree)?;
+    let nodes: Vec<Py<Node>> = code
+        .iter()
+        .map(|stmt| Node::from_statement(py, &stmt.elem, Some(stmt.location)))
+        .collect();
+
+    Ok(PyList::new(py, nodes)?.unbind())
+}
@@ -0,0 +1,622 @@
+//! Maps expressions back onto the text they were parsed from, for
+//! `DME.source_span`.
+//!
+//! The parser only records where each expression starts, so the end of an
+//! expression is found by scanning the source text from the locations of the
+//! expression and its sub-expressions.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use dreammaker::{FileId, Location};
+use pyo3::{Bound, Py, PyResult, Python, exceptions::PyOSError, types::PyAnyMethods};
+
+use super::{
+    Dme, FilledSourceLocation,
+    expression::{Constant, Expression},
+    nodes::{OriginalSourceLocation, PyExpr},
+    operators::UnaryOperator,
+    visitor,
+};
+
+/// The contents of a source file, along with the offset of each line.
+pub struct SourceText {
+    bytes: Vec<u8>,
+    line_starts: Vec<usize>,
+}
+
+/// The contents of the files read so far, for `Dme::source_texts`.
+pub type SourceTexts = Mutex<HashMap<FileId, Arc<SourceText>>>;
+
+fn is_ident_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+fn is_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+impl SourceText {
+    pub fn new(bytes: Vec<u8>) -> SourceText {
+        let mut line_starts = vec![0];
+        line_starts.extend(
+            bytes
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| **b == b'\n')
+                .map(|(i, _)| i + 1),
+        );
+        SourceText { bytes, line_starts }
+    }
+
+    /// The byte offset of a line and column, both starting at 1.
+    fn offset(&self, line: u32, column: u16) -> Option<usize> {
+        let line_start = *self.line_starts.get((line as usize).checked_sub(1)?)?;
+        let offset = line_start + (column as usize).checked_sub(1)?;
+        (offset <= self.bytes.len()).then_some(offset)
+    }
+
+    /// The line and column of a byte offset.
+    fn position(&self, offset: usize) -> (u32, u16) {
+        let line = self.line_starts.partition_point(|start| *start <= offset);
+        let column = offset - self.line_starts[line - 1] + 1;
+        (line as u32, column.min(u16::MAX as usize) as u16)
+    }
+
+    fn at(&self, pos: usize) -> u8 {
+        self.bytes.get(pos).copied().unwrap_or(0)
+    }
+
+    fn starts_with(&self, pos: usize, text: &[u8]) -> bool {
+        self.bytes
+            .get(pos..)
+            .is_some_and(|rest| rest.starts_with(text))
+    }
+
+    /// Skips whitespace, comments and line continuations.
+    fn skip_trivia(&self, mut pos: usize) -> usize {
+        loop {
+            match self.at(pos) {
+                b' ' | b'\t' | b'\r' | b'\n' | b'\\' => pos += 1,
+                b'/' if self.at(pos + 1) == b'/' => {
+                    while pos < self.bytes.len() && self.at(pos) != b'\n' {
+                        pos += 1;
+                    }
+                }
+                b'/' if self.at(pos + 1) == b'*' => {
+                    let mut depth = 0;
+                    while pos < self.bytes.len() {
+                        if self.starts_with(pos, b"/*") {
+                            depth += 1;
+                            pos += 2;
+                        } else if self.starts_with(pos, b"*/") {
+                            depth -= 1;
+                            pos += 2;
+                            if depth == 0 {
+                                break;
+                            }
+                        } else {
+                            pos += 1;
+                        }
+                    }
+                }
+                _ => return pos,
+            }
+        }
+    }
+
+    /// Skips whitespace backwards from `pos`.
+    fn skip_space_back(&self, mut pos: usize) -> usize {
+        while pos > 0 && matches!(self.at(pos - 1), b' ' | b'\t' | b'\r' | b'\n') {
+            pos -= 1;
+        }
+        pos
+    }
+
+    fn skip_ident(&self, mut pos: usize) -> usize {
+        while is_ident(self.at(pos)) {
+            pos += 1;
+        }
+        pos
+    }
+
+    /// Skips the identifier `name` at `pos`, if it is there.
+    fn expect_ident(&self, pos: usize, name: &str) -> Option<usize> {
+        let end = pos + name.len();
+        (self.starts_with(pos, name.as_bytes()) && !is_ident(self.at(end))).then_some(end)
+    }
+
+    fn expect_byte(&self, pos: usize, b: u8) -> Option<usize> {
+        (self.at(pos) == b).then_some(pos + 1)
+    }
+
+    fn skip_number(&self, start: usize) -> usize {
+        let hex = self.at(start) == b'0' && matches!(self.at(start + 1), b'x' | b'X');
+        let mut pos = start;
+        loop {
+            match self.at(pos) {
+                b'e' | b'E' if !hex && matches!(self.at(pos + 1), b'+' | b'-') => pos += 2,
+                b'#' if self.starts_with(pos + 1, b"INF") || self.starts_with(pos + 1, b"IND") => {
+                    pos += 4
+                }
+                b if is_ident(b) || b == b'.' => pos += 1,
+                _ => return pos,
+            }
+        }
+    }
+
+    /// Skips a string literal, including any embedded expressions.
+    fn skip_string(&self, pos: usize) -> Option<usize> {
+        let (mut pos, long) = if self.starts_with(pos, b"{\"") {
+            (pos + 2, true)
+        } else {
+            (self.expect_byte(pos, b'"')?, false)
+        };
+        loop {
+            match *self.bytes.get(pos)? {
+                b'\\' => pos += 2,
+                b'[' => pos = self.skip_group(pos)?,
+                b'"' if !long => return Some(pos + 1),
+                b'"' if self.at(pos + 1) == b'}' => return Some(pos + 2),
+                b'\n' if !long => return None,
+                _ => pos += 1,
+            }
+        }
+    }
+
+    /// Skips a raw string, such as `@"..."` or `@{"..."}`.
+    fn skip_raw_string(&self, pos: usize) -> Option<usize> {
+        let (start, terminator): (usize, &[u8]) = if self.starts_with(pos + 1, b"{\"") {
+            (pos + 3, b"\"}")
+        } else {
+            (pos + 2, std::slice::from_ref(self.bytes.get(pos + 1)?))
+        };
+        let rest = self.bytes.get(start..)?;
+        let found = rest
+            .windows(terminator.len())
+            .position(|window| window == terminator)?;
+        Some(start + found + terminator.len())
+    }
+
+    fn skip_resource(&self, mut pos: usize) -> Option<usize> {
+        pos += 1;
+        loop {
+            match *self.bytes.get(pos)? {
+                b'\\' => pos += 2,
+                b'\'' => return Some(pos + 1),
+                b'\n' => return None,
+                _ => pos += 1,
+            }
+        }
+    }
+
+    /// Skips a bracketed group, up to and including its closing bracket.
+    fn skip_group(&self, pos: usize) -> Option<usize> {
+        let close = match self.at(pos) {
+            b'(' => b')',
+            b'[' => b']',
+            b'{' => b'}',
+            _ => return None,
+        };
+        let mut pos = self.skip_trivia(pos + 1);
+        loop {
+            match *self.bytes.get(pos)? {
+                b if b == close => return Some(pos + 1),
+                b')' | b']' | b'}' => return None,
+                _ => pos = self.skip_trivia(self.skip_token(pos)?),
+            }
+        }
+    }
+
+    /// Skips a single token, or a whole group if `pos` is an opening
+    /// bracket.
+    fn skip_token(&self, pos: usize) -> Option<usize> {
+        match *self.bytes.get(pos)? {
+            b'"' => self.skip_string(pos),
+            b'{' if self.at(pos + 1) == b'"' => self.skip_string(pos),
+            b'@' => self.skip_raw_string(pos),
+            b'\'' => self.skip_resource(pos),
+            b'(' | b'[' | b'{' => self.skip_group(pos),
+            b'0'..=b'9' => Some(self.skip_number(pos)),
+            b'.' if self.at(pos + 1).is_ascii_digit() => Some(self.skip_number(pos)),
+            b if is_ident_start(b) => Some(self.skip_ident(pos)),
+            _ => Some(pos + 1),
+        }
+    }
+
+    /// Skips a group if one starts at `pos`, after any trivia.
+    fn skip_optional_group(&self, pos: usize) -> Option<usize> {
+        let next = self.skip_trivia(pos);
+        if self.at(next) == b'(' {
+            self.skip_group(next)
+        } else {
+            Some(pos)
+        }
+    }
+
+    /// Skips a type path, such as `/obj/item` or `.proc/foo`, and any vars
+    /// set on it.
+    fn skip_path(&self, mut pos: usize) -> Option<usize> {
+        let start = pos;
+        while matches!(self.at(pos), b'/' | b'.') || is_ident(self.at(pos)) {
+            pos += 1;
+        }
+        if pos == start {
+            return None;
+        }
+        if self.at(pos) == b'{' {
+            pos = self.skip_group(pos)?;
+        }
+        Some(pos)
+    }
+
+    /// Skips the operator introducing a follow, such as `.`, `?.` or `:`.
+    fn skip_follow_punct(&self, mut pos: usize) -> usize {
+        if self.at(pos) == b'?' {
+            pos += 1;
+        }
+        while matches!(self.at(pos), b'.' | b':') {
+            pos += 1;
+        }
+        self.skip_trivia(pos)
+    }
+
+    /// Extends `start..end` outwards until its brackets are balanced.
+    fn balance(&self, mut start: usize, mut end: usize) -> Option<(usize, usize)> {
+        let mut unopened = 0;
+        let mut unclosed = 0;
+        let mut pos = self.skip_trivia(start);
+        while pos < end {
+            match self.at(pos) {
+                b'{' if self.at(pos + 1) == b'"' => pos = self.skip_string(pos)?,
+                b'(' | b'[' | b'{' => {
+                    unclosed += 1;
+                    pos += 1;
+                }
+                b')' | b']' | b'}' => {
+                    if unclosed > 0 {
+                        unclosed -= 1;
+                    } else {
+                        unopened += 1;
+                    }
+                    pos += 1;
+                }
+                _ => pos = self.skip_token(pos)?,
+            }
+            if pos > end {
+                return None;
+            }
+            pos = self.skip_trivia(pos);
+        }
+
+        for _ in 0..unopened {
+            start = self.skip_space_back(start);
+            if start == 0 || !matches!(self.at(start - 1), b'(' | b'[') {
+                return None;
+            }
+            start -= 1;
+        }
+        for _ in 0..unclosed {
+            end = self.skip_trivia(end);
+            if !matches!(self.at(end), b')' | b']' | b'}') {
+                return None;
+            }
+            end += 1;
+        }
+        Some((start, end))
+    }
+}
+
+/// Where a sub-expression was found, if anywhere.
+enum Extent {
+    /// Neither the expression nor any of its sub-expressions have a
+    /// location, as with the name of a call.
+    Unlocated,
+    Span(usize, usize),
+}
+
+fn loc_of(expr: &Bound<Expression>) -> PyResult<Option<Py<OriginalSourceLocation>>> {
+    expr.getattr("source_loc")?.extract()
+}
+
+fn ident_name(expr: &PyExpr, py: Python<'_>) -> Option<String> {
+    match &*expr.borrow(py) {
+        Expression::Identifier { name, .. } => Some(name.clone()),
+        _ => None,
+    }
+}
+
+fn unary_text(op: UnaryOperator) -> &'static str {
+    match op {
+        UnaryOperator::Neg => "-",
+        UnaryOperator::Not => "!",
+        UnaryOperator::BitNot => "~",
+        UnaryOperator::PreIncr | UnaryOperator::PostIncr => "++",
+        UnaryOperator::PreDecr | UnaryOperator::PostDecr => "--",
+        UnaryOperator::Ref => "&",
+        UnaryOperator::Deref => "*",
+    }
+}
+
+struct SpanFinder<'a> {
+    text: &'a SourceText,
+    file: FileId,
+}
+
+impl SpanFinder<'_> {
+    /// The end of the tokens belonging to `expr` itself, which start at
+    /// `pos`, or `None` if they don't match the expression, as happens when
+    /// it came from a macro.
+    fn own_end(&self, expr: &Expression, pos: usize, py: Python<'_>) -> Option<usize> {
+        let text = self.text;
+        match expr {
+            Expression::Identifier { name, .. } => text.expect_ident(pos, name),
+            Expression::GlobalIdentifier { name, .. } => {
+                let pos = text.skip_trivia(text.expect_ident(pos, "global")?);
+                let pos = text.skip_trivia(text.expect_byte(pos, b'.')?);
+                text.expect_ident(pos, name)
+            }
+            Expression::Constant { constant, .. } => {
+                let pos = match constant {
+                    Constant::Int(_) | Constant::Float(_) if text.at(pos) == b'-' => {
+                        text.skip_trivia(pos + 1)
+                    }
+                    _ => pos,
+                };
+                text.skip_token(pos)
+            }
+            Expression::InterpString { .. } => text.skip_token(pos),
+            Expression::Prefab { .. } => text.skip_path(pos),
+            Expression::List { .. } | Expression::Pick { .. } | Expression::Locate { .. } => {
+                let pos = text.skip_trivia(text.skip_ident(pos));
+                text.skip_group(pos)
+            }
+            Expression::As { .. } => text.skip_optional_group(text.skip_ident(pos)),
+            Expression::Input { .. } => {
+                let pos = text.skip_trivia(text.skip_ident(pos));
+                let mut end = text.skip_group(pos)?;
+                let next = text.skip_trivia(end);
+                if let Some(mut pos) = text.expect_ident(next, "as") {
+                    loop {
+                        pos = text.skip_trivia(pos);
+                        end = text.skip_ident(pos);
+                        let next = text.skip_trivia(end);
+                        if text.at(next) != b'|' {
+                            break;
+                        }
+                        pos = next + 1;
+                    }
+                }
+                Some(end)
+            }
+            Expression::DynamicCall { .. } | Expression::ExternalCall { .. } => {
+                let pos = text.skip_trivia(text.skip_ident(pos));
+                let end = text.skip_group(pos)?;
+                text.skip_optional_group(end)
+            }
+            Expression::Call { name, .. } => {
+                let pos = text.skip_follow_punct(pos);
+                let pos = text.skip_trivia(text.expect_ident(pos, &ident_name(name, py)?)?);
+                text.skip_group(pos)
+            }
+            Expression::GlobalCall { name, .. } => {
+                let pos = text.skip_trivia(text.expect_ident(pos, "global")?);
+                let pos = text.skip_trivia(text.expect_byte(pos, b'.')?);
+                let pos = text.skip_trivia(text.expect_ident(pos, &ident_name(name, py)?)?);
+                text.skip_group(pos)
+            }
+            Expression::SelfCall { .. } | Expression::ParentCall { .. } => {
+                text.skip_group(text.skip_follow_punct(pos))
+            }
+            Expression::NewImplicit { .. } => {
+                text.skip_optional_group(text.expect_ident(pos, "new")?)
+            }
+            Expression::NewPrefab { .. } => {
+                let pos = text.skip_trivia(text.expect_ident(pos, "new")?);
+                text.skip_optional_group(text.skip_path(pos)?)
+            }
+            Expression::NewMiniExpr { name, fields, .. } => {
+                let pos = text.skip_trivia(text.expect_ident(pos, "new")?);
+                let mut end = text.expect_ident(pos, &ident_name(name, py)?)?;
+                for _ in fields.iter() {
+                    let pos = text.skip_trivia(text.expect_byte(text.skip_trivia(end), b'.')?);
+                    end = text.skip_ident(pos);
+                }
+                text.skip_optional_group(end)
+            }
+            Expression::Index { .. } => {
+                let pos = if text.at(pos) == b'?' { pos + 1 } else { pos };
+                text.skip_group(text.skip_trivia(pos))
+            }
+            Expression::Field { field, .. } | Expression::StaticField { field, .. } => {
+                let pos = text.skip_follow_punct(pos);
+                text.expect_ident(pos, &ident_name(field, py)?)
+            }
+            Expression::ProcReference { name, .. } => {
+                let pos = text.skip_follow_punct(pos);
+                let pos = text.skip_trivia(text.expect_ident(pos, &ident_name(name, py)?)?);
+                text.skip_group(pos)
+            }
+            Expression::UnaryOp { unary_op, .. } => {
+                let op = unary_text(*unary_op);
+                text.starts_with(pos, op.as_bytes())
+                    .then_some(pos + op.len())
+            }
+            Expression::BinaryOp { .. }
+            | Expression::AssignOp { .. }
+            | Expression::TernaryOp { .. } => Some(pos),
+        }
+    }
+
+    /// The start of the prefix operator of a `UnaryOp`, when its location
+    /// is that of its operand: the operator is searched for just before it.
+    fn prefix_start(&self, op: UnaryOperator, operand_start: usize) -> Option<usize> {
+        let text = self.text;
+        let mut pos = operand_start;
+        loop {
+            pos = text.skip_space_back(pos);
+            if pos > 0 && text.at(pos - 1) == b'(' {
+                pos -= 1;
+            } else {
+                break;
+            }
+        }
+        let op = unary_text(op).as_bytes();
+        let start = pos.checked_sub(op.len())?;
+        text.starts_with(start, op).then_some(start)
+    }
+
+    fn extent(&self, expr: &Bound<Expression>) -> PyResult<Option<Extent>> {
+        let py = expr.py();
+        let mut span: Option<(usize, usize)> = None;
+        let mut all_located = true;
+        for child in visitor::child_nodes(expr.as_any())? {
+            let Ok(child) = child.cast_into::<Expression>() else {
+                continue;
+            };
+            match self.extent(&child)? {
+                None => return Ok(None),
+                Some(Extent::Unlocated) => all_located = false,
+                Some(Extent::Span(start, end)) => {
+                    span = Some(match span {
+                        Some((s, e)) => (s.min(start), e.max(end)),
+                        None => (start, end),
+                    });
+                }
+            }
+        }
+
+        let borrowed = expr.borrow();
+        let inner = &*borrowed;
+        if matches!(
+            inner,
+            Expression::BinaryOp { .. }
+                | Expression::AssignOp { .. }
+                | Expression::TernaryOp { .. }
+        ) && !all_located
+        {
+            return Ok(None);
+        }
+
+        let Some(loc) = loc_of(expr)? else {
+            return Ok(Some(match span {
+                Some((start, end)) => Extent::Span(start, end),
+                None => Extent::Unlocated,
+            }));
+        };
+        let loc = loc.borrow(py);
+        if loc.file != self.file {
+            return Ok(None);
+        }
+        let Some(offset) = self.text.offset(loc.line, loc.column) else {
+            return Ok(None);
+        };
+        let pos = self.text.skip_trivia(offset);
+
+        if let Expression::UnaryOp { unary_op, .. } = inner {
+            let Some((operand_start, operand_end)) = span else {
+                return Ok(None);
+            };
+            let own = match unary_op {
+                UnaryOperator::PostIncr | UnaryOperator::PostDecr => self
+                    .own_end(inner, pos, py)
+                    .filter(|_| pos >= operand_end)
+                    .map(|end| (operand_start, end)),
+                _ if pos < operand_start => {
+                    self.own_end(inner, pos, py).map(|_| (pos, operand_end))
+                }
+                _ => self
+                    .prefix_start(*unary_op, operand_start)
+                    .map(|start| (start, operand_end)),
+            };
+            return Ok(own.map(|(start, end)| Extent::Span(start, end)));
+        }
+
+        let Some(end) = self.own_end(inner, pos, py) else {
+            return Ok(None);
+        };
+        Ok(Some(match span {
+            Some((start, child_end)) => Extent::Span(start.min(pos), child_end.max(end)),
+            None => Extent::Span(pos, end),
+        }))
+    }
+}
+
+/// The file of the first located part of `expr`.
+fn find_file(expr: &Bound<Expression>) -> PyResult<Option<FileId>> {
+    if let Some(loc) = loc_of(expr)? {
+        return Ok(Some(loc.borrow(expr.py()).file));
+    }
+    for child in visitor::child_nodes(expr.as_any())? {
+        if let Ok(child) = child.cast_into::<Expression>() {
+            if let Some(file) = find_file(&child)? {
+                return Ok(Some(file));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The text of `file`, read on first use.
+fn source_text(dme: &Dme, file: FileId, py: Python<'_>) -> PyResult<Option<Arc<SourceText>>> {
+    if let Some(text) = dme.source_texts.lock().unwrap().get(&file) {
+        return Ok(Some(text.clone()));
+    }
+    let Some(rel_path) = dme
+        .file_data
+        .borrow(py)
+        .file_ids
+        .get(&file)
+        .map(|p| p.clone_ref(py))
+    else {
+        return Ok(None);
+    };
+    let path: PathBuf = dme
+        .filepath
+        .bind(py)
+        .getattr("parent")?
+        .call_method1("joinpath", (rel_path,))?
+        .extract()?;
+    let bytes = std::fs::read(&path)
+        .map_err(|e| PyOSError::new_err(format!("error reading {:?}: {}", path, e)))?;
+    let text = Arc::new(SourceText::new(bytes));
+    dme.source_texts.lock().unwrap().insert(file, text.clone());
+    Ok(Some(text))
+}
+
+/// The implementation of `DME.source_span`.
+pub fn source_span(
+    dme: &Dme,
+    expr: &Bound<Expression>,
+    py: Python<'_>,
+) -> PyResult<Option<(FilledSourceLocation, usize, usize)>> {
+    let Some(file) = find_file(expr)? else {
+        return Ok(None);
+    };
+    if file == Location::builtins().file {
+        return Ok(None);
+    }
+    let Some(text) = source_text(dme, file, py)? else {
+        return Ok(None);
+    };
+    let finder = SpanFinder { text: &text, file };
+    let Some(Extent::Span(start, end)) = finder.extent(expr)? else {
+        return Ok(None);
+    };
+    let Some((start, end)) = text.balance(start, end) else {
+        return Ok(None);
+    };
+    let (line, column) = text.position(start);
+    Ok(Some((
+        FilledSourceLocation {
+            file_path: dme.file_data.borrow(py).file_ids[&file].clone_ref(py),
+            line,
+            column,
+        },
+        start,
+        end,
+    )))
+}
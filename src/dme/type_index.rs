@@ -0,0 +1,80 @@
+//! An index over the object tree for subtype lookups and navigation.
+
+use std::collections::HashMap;
+
+use dreammaker::objtree::{NodeIndex, ObjectTree};
+
+use crate::path::Path;
+
+pub struct TypeIndex {
+    /// The path of every type but the root, sorted. Since path parts never
+    /// contain characters ordered before `/`, the subtypes of a path always
+    /// directly follow it.
+    paths: Vec<Path>,
+    /// The types whose `parent_type` is each type, sorted by path.
+    children: HashMap<NodeIndex, Vec<NodeIndex>>,
+}
+
+impl TypeIndex {
+    pub fn build(objtree: &ObjectTree) -> TypeIndex {
+        let mut paths = Vec::new();
+        let mut children: HashMap<NodeIndex, Vec<(Path, NodeIndex)>> = HashMap::new();
+        for ty in objtree.iter_types() {
+            if ty.is_root() {
+                continue;
+            }
+            let path = Path::make_trusted(&ty.path);
+            if let Some(parent) = ty.parent_type_index() {
+                children
+                    .entry(parent)
+                    .or_default()
+                    .push((path.clone(), ty.index()));
+            }
+            paths.push(path);
+        }
+        paths.sort();
+        paths.dedup();
+
+        TypeIndex {
+            paths,
+            children: children
+                .into_iter()
+                .map(|(parent, mut kids)| {
+                    kids.sort();
+                    (parent, kids.into_iter().map(|(_, index)| index).collect())
+                })
+                .collect(),
+        }
+    }
+
+    /// Appends the paths of `needle` and its subtypes, or only its subtypes
+    /// if `strict`, in sorted order.
+    pub fn subtype_paths(&self, needle: &Path, strict: bool, out: &mut Vec<Path>) {
+        if needle.abs == "/" {
+            if !strict {
+                out.push(Path::root());
+            }
+            out.extend(self.paths.iter().cloned());
+            return;
+        }
+
+        let prefix = format!("{}/", needle.abs);
+        let start = self.paths.partition_point(|path| path.abs < needle.abs);
+        for path in self.paths[start..].iter() {
+            if path.abs == needle.abs {
+                if !strict {
+                    out.push(path.clone());
+                }
+            } else if path.abs.starts_with(&prefix) {
+                out.push(path.clone());
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// The types whose `parent_type` is `index`.
+    pub fn children(&self, index: NodeIndex) -> &[NodeIndex] {
+        self.children.get(&index).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
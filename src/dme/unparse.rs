@@ -0,0 +1,824 @@
+use pyo3::{
+    Bound, Py, PyAny, PyResult, Python,
+    exceptions::{PyTypeError, PyValueError},
+    pyfunction,
+    types::{PyAnyMethods, PyDict, PyDictMethods, PyList, PyListMethods},
+};
+
+use super::{
+    expression::{Constant, Expression},
+    nodes::{Node, PyCodeBlock, PyExpr},
+    operators::{AssignOperator, BinaryOperator, SettingMode, UnaryOperator},
+    prefab::Prefab,
+};
+
+// Operator precedence, from loosest to tightest binding. DM places `in`
+// below assignment, and comparisons above the shift operators.
+const PREC_IN: u8 = 1;
+const PREC_TO: u8 = 2;
+const PREC_ASSIGN: u8 = 3;
+const PREC_TERNARY: u8 = 4;
+const PREC_UNARY: u8 = 16;
+const PREC_POSTFIX: u8 = 17;
+const PREC_ATOM: u8 = 18;
+
+fn binary_prec(op: &BinaryOperator) -> u8 {
+    match op {
+        BinaryOperator::In => PREC_IN,
+        BinaryOperator::To => PREC_TO,
+        BinaryOperator::Or => 5,
+        BinaryOperator::And => 6,
+        BinaryOperator::BitOr => 7,
+        BinaryOperator::BitXor => 8,
+        BinaryOperator::BitAnd => 9,
+        BinaryOperator::Eq
+        | BinaryOperator::NotEq
+        | BinaryOperator::LessOrGreater
+        | BinaryOperator::Equiv
+        | BinaryOperator::NotEquiv => 10,
+        BinaryOperator::LShift | BinaryOperator::RShift => 11,
+        BinaryOperator::Less
+        | BinaryOperator::Greater
+        | BinaryOperator::LessEq
+        | BinaryOperator::GreaterEq => 12,
+        BinaryOperator::Add | BinaryOperator::Sub => 13,
+        BinaryOperator::Mul
+        | BinaryOperator::Div
+        | BinaryOperator::Mod
+        | BinaryOperator::FloatMod => 14,
+        BinaryOperator::Pow => 15,
+    }
+}
+
+pub fn binary_op_str(op: &BinaryOperator) -> &'static str {
+    match op {
+        BinaryOperator::Add => "+",
+        BinaryOperator::Sub => "-",
+        BinaryOperator::Mul => "*",
+        BinaryOperator::Div => "/",
+        BinaryOperator::Pow => "**",
+        BinaryOperator::Mod => "%",
+        BinaryOperator::FloatMod => "%%",
+        BinaryOperator::Eq => "==",
+        BinaryOperator::NotEq => "!=",
+        BinaryOperator::Less => "<",
+        BinaryOperator::Greater => ">",
+        BinaryOperator::LessEq => "<=",
+        BinaryOperator::GreaterEq => ">=",
+        BinaryOperator::Equiv => "~=",
+        BinaryOperator::NotEquiv => "~!",
+        BinaryOperator::BitAnd => "&",
+        BinaryOperator::BitXor => "^",
+        BinaryOperator::BitOr => "|",
+        BinaryOperator::LShift => "<<",
+        BinaryOperator::RShift => ">>",
+        BinaryOperator::And => "&&",
+        BinaryOperator::Or => "||",
+        BinaryOperator::In => "in",
+        BinaryOperator::To => "to",
+        BinaryOperator::LessOrGreater => "<>",
+    }
+}
+
+pub fn assign_op_str(op: &AssignOperator) -> &'static str {
+    match op {
+        AssignOperator::Assign => "=",
+        AssignOperator::AssignAdd => "+=",
+        AssignOperator::AssignSub => "-=",
+        AssignOperator::AssignMul => "*=",
+        AssignOperator::AssignDiv => "/=",
+        AssignOperator::AssignMod => "%=",
+        AssignOperator::AssignFloatMod => "%%=",
+        AssignOperator::AssignInto => ":=",
+        AssignOperator::AssignBitAnd => "&=",
+        AssignOperator::AssignAnd => "&&=",
+        AssignOperator::AssignOr => "||=",
+        AssignOperator::AssignBitOr => "|=",
+        AssignOperator::AssignBitXor => "^=",
+        AssignOperator::AssignLShift => "<<=",
+        AssignOperator::AssignRShift => ">>=",
+    }
+}
+
+pub fn escape_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '[' => out.push_str("\\["),
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn float_to_string(f: f32) -> String {
+    if f.is_infinite() {
+        return if f > 0.0 { "1.#INF" } else { "-1.#INF" }.to_string();
+    }
+    if f.is_nan() {
+        return "1.#IND".to_string();
+    }
+    let out = format!("{}", f);
+    if out.contains('.') || out.contains('e') {
+        out
+    } else {
+        out + ".0"
+    }
+}
+
+pub fn constant_to_string(constant: &Constant) -> String {
+    match constant {
+        Constant::Null() => "null".to_string(),
+        Constant::Int(i) => i.to_string(),
+        Constant::Float(f) => float_to_string(*f),
+        Constant::String(s) => format!("\"{}\"", escape_string(s)),
+        Constant::Resource(s) => format!("'{}'", s),
+        Constant::Path(p) => p.rel.clone(),
+        Constant::ProcMacro() => "__PROC__".to_string(),
+        Constant::TypeMacro() => "__TYPE__".to_string(),
+        Constant::ImpliedTypeMacro() => "__IMPLIED_TYPE__".to_string(),
+    }
+}
+
+fn constant_prec(constant: &Constant) -> u8 {
+    match constant {
+        Constant::Int(i) if *i < 0 => PREC_UNARY,
+        Constant::Float(f) if *f < 0.0 => PREC_UNARY,
+        _ => PREC_ATOM,
+    }
+}
+
+/// Whether `expr` is the placeholder receiver used for calls without one,
+/// such as `foo()`, as opposed to an explicit `null.foo()`.
+fn is_implicit_receiver(expr: &Expression) -> bool {
+    matches!(
+        expr,
+        Expression::Constant {
+            constant: Constant::Null(),
+            source_loc: None
+        }
+    )
+}
+
+pub struct Unparser<'py> {
+    py: Python<'py>,
+    indent: String,
+    lines: Vec<String>,
+}
+
+impl<'py> Unparser<'py> {
+    pub fn new(py: Python<'py>, indent: &str) -> Self {
+        Unparser {
+            py,
+            indent: indent.to_string(),
+            lines: vec![],
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.lines.join("\n")
+    }
+
+    fn ident_name(&self, expr: &PyExpr) -> PyResult<String> {
+        match expr.get() {
+            Expression::Identifier { name, .. } => Ok(name.clone()),
+            _ => self.expr(expr),
+        }
+    }
+
+    fn args(&self, args: &[PyExpr]) -> PyResult<String> {
+        let mut out: Vec<String> = vec![];
+        for arg in args.iter() {
+            out.push(self.expr_prec(arg, PREC_ASSIGN)?);
+        }
+        Ok(out.join(", "))
+    }
+
+    fn prefab(&self, prefab: &Prefab) -> PyResult<String> {
+        let mut out = prefab.path.rel.clone();
+        let mut vars: Vec<String> = vec![];
+        if let Ok(var_list) = prefab.vars.cast_bound::<PyList>(self.py) {
+            for item in var_list.iter() {
+                if let Ok(var) = item.cast::<PyDict>() {
+                    for (k, v) in var.iter() {
+                        let value = if let Ok(expr) = v.cast::<Expression>() {
+                            self.expr_prec(&expr.clone().unbind(), PREC_ASSIGN)?
+                        } else {
+                            v.str()?.to_string()
+                        };
+                        vars.push(format!("{} = {}", k.str()?, value));
+                    }
+                }
+            }
+        }
+        if !vars.is_empty() {
+            out.push('{');
+            out.push_str(vars.join("; ").as_str());
+            out.push('}');
+        }
+        Ok(out)
+    }
+
+    fn precedence(&self, expr: &Expression) -> u8 {
+        match expr {
+            Expression::Constant { constant, .. } => constant_prec(constant),
+            Expression::BinaryOp { op, .. } => binary_prec(op),
+            Expression::AssignOp { .. } => PREC_ASSIGN,
+            Expression::TernaryOp { .. } => PREC_TERNARY,
+            Expression::UnaryOp { unary_op, .. } => match unary_op {
+                UnaryOperator::PostIncr | UnaryOperator::PostDecr => PREC_POSTFIX,
+                _ => PREC_UNARY,
+            },
+            Expression::Index { .. }
+            | Expression::Field { .. }
+            | Expression::StaticField { .. }
+            | Expression::Call { .. }
+            | Expression::ProcReference { .. } => PREC_POSTFIX,
+            Expression::NewMiniExpr { .. }
+            | Expression::NewImplicit { .. }
+            | Expression::NewPrefab { .. }
+            | Expression::Input { .. }
+            | Expression::Locate { .. } => PREC_UNARY,
+            _ => PREC_ATOM,
+        }
+    }
+
+    /// Unparse `expr`, wrapping it in parentheses if it binds more loosely
+    /// than `min_prec`.
+    fn expr_prec(&self, expr: &PyExpr, min_prec: u8) -> PyResult<String> {
+        let out = self.expr(expr)?;
+        if self.precedence(expr.get()) < min_prec {
+            Ok(format!("({})", out))
+        } else {
+            Ok(out)
+        }
+    }
+
+    pub fn expr(&self, expr: &PyExpr) -> PyResult<String> {
+        let py = self.py;
+        Ok(match expr.get() {
+            Expression::Constant { constant, .. } => constant_to_string(constant),
+            Expression::Identifier { name, .. } => name.clone(),
+            Expression::GlobalIdentifier { name, .. } => format!("global.{}", name),
+            Expression::List { list, .. } => {
+                let dmlist = list.borrow(py);
+                let mut items: Vec<String> = vec![];
+                for (key, val) in dmlist.keys.iter().zip(dmlist.vals.iter()) {
+                    let key_str = match key.cast_bound::<Expression>(py) {
+                        Ok(k) => self.expr_prec(&k.clone().unbind(), PREC_ASSIGN + 1)?,
+                        Err(_) => key.bind(py).str()?.to_string(),
+                    };
+                    match val.cast_bound::<Expression>(py) {
+                        Ok(v) if !is_implicit_receiver(v.get()) => items.push(format!(
+                            "{} = {}",
+                            key_str,
+                            self.expr_prec(&v.clone().unbind(), PREC_ASSIGN)?
+                        )),
+                        _ => items.push(key_str),
+                    }
+                }
+                format!("list({})", items.join(", "))
+            }
+            Expression::BinaryOp { op, lhs, rhs, .. } => {
+                let prec = binary_prec(op);
+                // `**` is right-associative, everything else is left-associative.
+                let (lhs_prec, rhs_prec) = if *op == BinaryOperator::Pow {
+                    (prec + 1, prec)
+                } else {
+                    (prec, prec + 1)
+                };
+                format!(
+                    "{} {} {}",
+                    self.expr_prec(lhs, lhs_prec)?,
+                    binary_op_str(op),
+                    self.expr_prec(rhs, rhs_prec)?
+                )
+            }
+            Expression::AssignOp { op, lhs, rhs, .. } => format!(
+                "{} {} {}",
+                self.expr_prec(lhs, PREC_ASSIGN + 1)?,
+                assign_op_str(op),
+                self.expr_prec(rhs, PREC_ASSIGN)?
+            ),
+            Expression::TernaryOp {
+                cond,
+                if_expr,
+                else_expr,
+                ..
+            } => format!(
+                "{} ? {} : {}",
+                self.expr_prec(cond, PREC_TERNARY + 1)?,
+                self.expr_prec(if_expr, PREC_TERNARY)?,
+                self.expr_prec(else_expr, PREC_TERNARY)?
+            ),
+            Expression::InterpString {
+                first, token_pairs, ..
+            } => {
+                let mut out = String::from("\"");
+                if let Constant::String(s) = first {
+                    out.push_str(escape_string(s).as_str());
+                }
+                for (token_expr, token_str) in token_pairs.iter() {
+                    out.push('[');
+                    if let Some(token_expr) = token_expr {
+                        out.push_str(self.expr(token_expr)?.as_str());
+                    }
+                    out.push(']');
+                    if let Constant::String(s) = token_str.get() {
+                        out.push_str(escape_string(s).as_str());
+                    }
+                }
+                out.push('"');
+                out
+            }
+            Expression::Locate { args, in_list, .. } => {
+                let mut out = format!("locate({})", self.args(args)?);
+                if let Some(in_list) = in_list {
+                    out.push_str(format!(" in {}", self.expr_prec(in_list, PREC_TO)?).as_str());
+                }
+                out
+            }
+            Expression::Prefab { prefab, .. } => self.prefab(&prefab.borrow(py))?,
+            Expression::Index { expr, index, .. } => format!(
+                "{}[{}]",
+                self.expr_prec(expr, PREC_POSTFIX)?,
+                self.expr(index)?
+            ),
+            Expression::Field { expr, field, .. } => match expr {
+                Some(expr) => format!(
+                    "{}.{}",
+                    self.expr_prec(expr, PREC_POSTFIX)?,
+                    self.ident_name(field)?
+                ),
+                None => self.ident_name(field)?,
+            },
+            Expression::StaticField { expr, field, .. } => format!(
+                "{}::{}",
+                self.expr_prec(expr, PREC_POSTFIX)?,
+                self.ident_name(field)?
+            ),
+            Expression::Call {
+                expr, name, args, ..
+            } => {
+                if is_implicit_receiver(expr.get()) {
+                    format!("{}({})", self.ident_name(name)?, self.args(args)?)
+                } else {
+                    format!(
+                        "{}.{}({})",
+                        self.expr_prec(expr, PREC_POSTFIX)?,
+                        self.ident_name(name)?,
+                        self.args(args)?
+                    )
+                }
+            }
+            Expression::GlobalCall { name, args, .. } => {
+                format!("global.{}({})", self.ident_name(name)?, self.args(args)?)
+            }
+            Expression::SelfCall { args, .. } => format!(".({})", self.args(args)?),
+            Expression::ParentCall { args, .. } => format!("..({})", self.args(args)?),
+            Expression::UnaryOp { expr, unary_op, .. } => {
+                let operand = self.expr_prec(expr, PREC_UNARY)?;
+                match unary_op {
+                    UnaryOperator::PostIncr => {
+                        format!("{}++", self.expr_prec(expr, PREC_POSTFIX)?)
+                    }
+                    UnaryOperator::PostDecr => {
+                        format!("{}--", self.expr_prec(expr, PREC_POSTFIX)?)
+                    }
+                    _ => {
+                        let op = match unary_op {
+                            UnaryOperator::Neg => "-",
+                            UnaryOperator::Not => "!",
+                            UnaryOperator::BitNot => "~",
+                            UnaryOperator::PreIncr => "++",
+                            UnaryOperator::PreDecr => "--",
+                            UnaryOperator::Ref => "&",
+                            UnaryOperator::Deref => "*",
+                            UnaryOperator::PostIncr | UnaryOperator::PostDecr => unreachable!(),
+                        };
+                        // Keep `-(-x)` from turning into `--x`, and so on.
+                        if operand.starts_with(['-', '+', '&', '*']) {
+                            format!("{}({})", op, operand)
+                        } else {
+                            format!("{}{}", op, operand)
+                        }
+                    }
+                }
+            }
+            Expression::ProcReference { expr, name, .. } => format!(
+                "{}::{}()",
+                self.expr_prec(expr, PREC_POSTFIX)?,
+                self.ident_name(name)?
+            ),
+            Expression::ExternalCall {
+                library_name,
+                function_name,
+                args,
+                ..
+            } => match library_name {
+                Some(library_name) => format!(
+                    "call_ext({}, {})({})",
+                    self.expr_prec(library_name, PREC_ASSIGN)?,
+                    self.expr_prec(function_name, PREC_ASSIGN)?,
+                    self.args(args)?
+                ),
+                None => format!(
+                    "call_ext({})({})",
+                    self.expr_prec(function_name, PREC_ASSIGN)?,
+                    self.args(args)?
+                ),
+            },
+            Expression::NewMiniExpr {
+                name, fields, args, ..
+            } => {
+                let mut out = format!("new {}", self.ident_name(name)?);
+                for field in fields.iter() {
+                    out.push('.');
+                    out.push_str(self.expr(field)?.as_str());
+                }
+                if let Some(args) = args {
+                    out.push_str(format!("({})", self.args(args)?).as_str());
+                }
+                out
+            }
+            Expression::NewImplicit { args, .. } => match args {
+                Some(args) => format!("new({})", self.args(args)?),
+                None => "new".to_string(),
+            },
+            Expression::NewPrefab { prefab, args, .. } => {
+                let mut out = format!("new {}", self.prefab(&prefab.borrow(py))?);
+                if let Some(args) = args {
+                    out.push_str(format!("({})", self.args(args)?).as_str());
+                }
+                out
+            }
+            Expression::DynamicCall {
+                lib_name,
+                proc_name,
+                ..
+            } => format!("call({})({})", self.args(lib_name)?, self.args(proc_name)?),
+            Expression::Input {
+                args,
+                input_type,
+                in_list,
+                ..
+            } => {
+                let mut out = format!("input({})", self.args(args)?);
+                if let Some(input_type) = input_type {
+                    let input_type = dreammaker::ast::InputType::from_bits_truncate(*input_type);
+                    out.push_str(format!(" as {}", input_type).as_str());
+                }
+                if let Some(in_list) = in_list {
+                    out.push_str(format!(" in {}", self.expr_prec(in_list, PREC_TO)?).as_str());
+                }
+                out
+            }
+            Expression::Pick { args, .. } => {
+                let mut items: Vec<String> = vec![];
+                for (weight, value) in args.iter() {
+                    match weight {
+                        Some(weight) => items.push(format!(
+                            "{}; {}",
+                            self.expr_prec(weight, PREC_ASSIGN)?,
+                            self.expr_prec(value, PREC_ASSIGN)?
+                        )),
+                        None => items.push(self.expr_prec(value, PREC_ASSIGN)?),
+                    }
+                }
+                format!("pick({})", items.join(", "))
+            }
+            Expression::As { input_type, .. } => {
+                let input_type = dreammaker::ast::InputType::from_bits_truncate(*input_type);
+                format!("as({})", input_type)
+            }
+        })
+    }
+
+    fn push_line(&mut self, depth: usize, line: String) {
+        self.lines.push(format!("{}{}", self.indent.repeat(depth), line));
+    }
+
+    fn block(&mut self, header: String, block: &PyCodeBlock, depth: usize) -> PyResult<()> {
+        if block.is_empty() {
+            self.push_line(depth, format!("{} {{}}", header));
+            return Ok(());
+        }
+        self.push_line(depth, header);
+        for stmt in block.iter() {
+            self.stmt(stmt, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn var_decl(&self, node: &Node) -> PyResult<String> {
+        match node {
+            Node::Var {
+                name,
+                value,
+                declared_type,
+                ..
+            } => {
+                let mut out = String::from("var");
+                if let Some(declared_type) = declared_type {
+                    out.push_str(declared_type.rel.as_str());
+                }
+                out.push('/');
+                out.push_str(self.ident_name(name)?.as_str());
+                if let Some(value) = value {
+                    out.push_str(" = ");
+                    out.push_str(self.expr_prec(value, PREC_ASSIGN)?.as_str());
+                }
+                Ok(out)
+            }
+            _ => Err(PyValueError::new_err("expected a var declaration")),
+        }
+    }
+
+    fn loop_var(
+        &self,
+        declared: bool,
+        var_type: &Option<crate::path::Path>,
+        name: &PyExpr,
+    ) -> PyResult<String> {
+        Ok(match var_type {
+            _ if !declared => self.ident_name(name)?,
+            Some(var_type) => format!("var{}/{}", var_type.rel, self.ident_name(name)?),
+            None => format!("var/{}", self.ident_name(name)?),
+        })
+    }
+
+    /// Unparse a statement which fits on a single line, such as the
+    /// initializer or increment of a `for` loop.
+    fn simple_stmt(&self, node: &Node) -> PyResult<String> {
+        Ok(match node {
+            Node::Expression { expr, .. } => self.expr(expr)?,
+            Node::Var { .. } => self.var_decl(node)?,
+            Node::Vars { vars, .. } => {
+                let mut out: Vec<String> = vec![];
+                for var in vars.iter() {
+                    out.push(self.var_decl(var.get())?);
+                }
+                out.join(", ")
+            }
+            Node::Crash { expr, .. } => match expr {
+                Some(expr) => format!("CRASH({})", self.expr(expr)?),
+                None => "CRASH()".to_string(),
+            },
+            Node::Return { retval, .. } => match retval {
+                Some(retval) => format!("return {}", self.expr(retval)?),
+                None => "return".to_string(),
+            },
+            Node::Throw { expr, .. } => format!("throw {}", self.expr(expr)?),
+            Node::Del { expr, .. } => format!("del({})", self.expr(expr)?),
+            Node::Break { label, .. } => match label {
+                Some(label) => format!("break {}", self.ident_name(label)?),
+                None => "break".to_string(),
+            },
+            Node::Continue { name, .. } => match name {
+                Some(name) => format!("continue {}", self.ident_name(name)?),
+                None => "continue".to_string(),
+            },
+            Node::Goto { label, .. } => format!("goto {}", self.ident_name(label)?),
+            Node::Setting {
+                name, mode, value, ..
+            } => format!(
+                "set {} {} {}",
+                self.ident_name(name)?,
+                match mode {
+                    SettingMode::Assign => "=",
+                    SettingMode::In => "in",
+                },
+                self.expr(value)?
+            ),
+            _ => {
+                return Err(PyValueError::new_err(
+                    "cannot unparse a block statement on a single line",
+                ));
+            }
+        })
+    }
+
+    pub fn stmt(&mut self, node: &Py<Node>, depth: usize) -> PyResult<()> {
+        match node.get() {
+            Node::Vars { vars, .. } => {
+                for var in vars.iter() {
+                    let line = self.var_decl(var.get())?;
+                    self.push_line(depth, line);
+                }
+            }
+            Node::While {
+                condition, block, ..
+            } => {
+                let header = format!("while({})", self.expr(condition)?);
+                self.block(header, block, depth)?;
+            }
+            Node::DoWhile {
+                condition, block, ..
+            } => {
+                self.block("do".to_string(), block, depth)?;
+                let footer = format!("while({})", self.expr(condition)?);
+                self.push_line(depth, footer);
+            }
+            Node::If {
+                if_arms, else_arm, ..
+            } => {
+                for (idx, (cond, block)) in if_arms.iter().enumerate() {
+                    let header = format!(
+                        "{}if({})",
+                        if idx == 0 { "" } else { "else " },
+                        self.expr(cond)?
+                    );
+                    self.block(header, block, depth)?;
+                }
+                if let Some(else_arm) = else_arm {
+                    self.block("else".to_string(), else_arm, depth)?;
+                }
+            }
+            Node::ForInfinite { block, .. } => {
+                self.block("for()".to_string(), block, depth)?;
+            }
+            Node::ForList {
+                var_type,
+                declared,
+                name,
+                in_list,
+                block,
+                ..
+            } => {
+                let mut header = format!("for({}", self.loop_var(*declared, var_type, name)?);
+                if let Some(in_list) = in_list {
+                    header.push_str(format!(" in {}", self.expr_prec(in_list, PREC_TO)?).as_str());
+                }
+                header.push(')');
+                self.block(header, block, depth)?;
+            }
+            Node::ForLoop {
+                init,
+                test,
+                inc,
+                block,
+                ..
+            } => {
+                let init = match init {
+                    Some(init) => self.simple_stmt(init.get())?,
+                    None => String::new(),
+                };
+                let test = match test {
+                    Some(test) => self.expr(test)?,
+                    None => String::new(),
+                };
+                let inc = match inc {
+                    Some(inc) => self.simple_stmt(inc.get())?,
+                    None => String::new(),
+                };
+                let header = format!("for({}; {}; {})", init, test, inc);
+                self.block(header, block, depth)?;
+            }
+            Node::ForRange {
+                declared,
+                name,
+                start,
+                end,
+                step,
+                block,
+                ..
+            } => {
+                let mut header = format!(
+                    "for({} = {} to {}",
+                    self.loop_var(*declared, &None, name)?,
+                    self.expr_prec(start, PREC_TO + 1)?,
+                    self.expr_prec(end, PREC_TO + 1)?
+                );
+                if let Some(step) = step {
+                    header.push_str(format!(" step {}", self.expr_prec(step, PREC_TO + 1)?).as_str());
+                }
+                header.push(')');
+                self.block(header, block, depth)?;
+            }
+            Node::ForKeyValue {
+                var_type,
+                declared,
+                key,
+                value,
+                in_list,
+                block,
+                ..
+            } => {
+                let mut header = format!(
+                    "for({}, {}",
+                    self.loop_var(*declared, var_type, key)?,
+                    self.ident_name(value)?
+                );
+                if let Some(in_list) = in_list {
+                    header.push_str(format!(" in {}", self.expr_prec(in_list, PREC_TO)?).as_str());
+                }
+                header.push(')');
+                self.block(header, block, depth)?;
+            }
+            Node::Spawn { delay, block, .. } => {
+                let header = match delay {
+                    Some(delay) => format!("spawn({})", self.expr(delay)?),
+                    None => "spawn()".to_string(),
+                };
+                self.block(header, block, depth)?;
+            }
+            Node::Label { name, block, .. } => {
+                let header = format!("{}:", self.ident_name(name)?);
+                if block.is_empty() {
+                    self.push_line(depth, header);
+                } else {
+                    self.block(header, block, depth)?;
+                }
+            }
+            Node::TryCatch {
+                try_block,
+                catch_params,
+                catch_block,
+                ..
+            } => {
+                self.block("try".to_string(), try_block, depth)?;
+                let mut params: Vec<String> = vec![];
+                for param in catch_params.iter() {
+                    let mut parts: Vec<String> = vec![];
+                    for part in param.iter() {
+                        parts.push(self.ident_name(part)?);
+                    }
+                    params.push(parts.join("/"));
+                }
+                let header = if params.is_empty() {
+                    "catch".to_string()
+                } else {
+                    format!("catch({})", params.join(", "))
+                };
+                self.block(header, catch_block, depth)?;
+            }
+            Node::Switch {
+                input,
+                cases,
+                default,
+                ..
+            } => {
+                let header = format!("switch({})", self.expr(input)?);
+                self.push_line(depth, header);
+                for case in cases.iter() {
+                    let case = case.borrow(self.py);
+                    let mut values: Vec<String> = vec![];
+                    for exact in case.exact.bind(self.py).iter() {
+                        let exact = exact.cast_into::<Expression>()?;
+                        values.push(self.expr_prec(&exact.unbind(), PREC_TO + 1)?);
+                    }
+                    for range in case.range.bind(self.py).iter() {
+                        let range = range.cast_into::<PyList>()?;
+                        let start = range.get_item(0)?.cast_into::<Expression>()?.unbind();
+                        let end = range.get_item(1)?.cast_into::<Expression>()?.unbind();
+                        values.push(format!(
+                            "{} to {}",
+                            self.expr_prec(&start, PREC_TO + 1)?,
+                            self.expr_prec(&end, PREC_TO + 1)?
+                        ));
+                    }
+                    let case_header = format!("if({})", values.join(", "));
+                    self.block(case_header, &case.block, depth + 1)?;
+                }
+                if let Some(default) = default {
+                    self.block("else".to_string(), default, depth + 1)?;
+                }
+            }
+            other => {
+                let line = self.simple_stmt(other)?;
+                self.push_line(depth, line);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Convert a node, expression, or list of nodes back into DM source code.
+#[pyfunction]
+#[pyo3(signature = (node, indent="\t"))]
+pub fn unparse(node: &Bound<PyAny>, indent: &str, py: Python<'_>) -> PyResult<String> {
+    let mut unparser = Unparser::new(py, indent);
+    if let Ok(expr) = node.cast::<Expression>() {
+        return unparser.expr(&expr.clone().unbind());
+    } else if let Ok(stmt) = node.cast::<Node>() {
+        unparser.stmt(&stmt.clone().unbind(), 0)?;
+    } else if let Ok(prefab) = node.cast::<Prefab>() {
+        return unparser.prefab(&prefab.borrow());
+    } else if let Ok(stmts) = node.cast::<PyList>() {
+        for stmt in stmts.iter() {
+            let stmt: Py<Node> = stmt.cast_into::<Node>()?.unbind();
+            unparser.stmt(&stmt, 0)?;
+        }
+    } else {
+        return Err(PyTypeError::new_err(format!(
+            "cannot unparse {}",
+            node.get_type().name()?
+        )));
+    }
+
+    Ok(unparser.finish())
+}
@@ -0,0 +1,338 @@
+use std::collections::HashSet;
+
+use dreammaker::{
+    Location,
+    ast::{Expression, Follow, ProcReturnType, Spanned, Statement, Term, VarType},
+    objtree::{NodeIndex, ObjectTree, ProcValue, TypeVar},
+};
+use pyo3::{
+    Bound, Py, PyRef, PyResult, Python, exceptions::PyRuntimeError, pyclass, pymethods,
+    types::{PyList, PyListMethods},
+};
+
+use crate::{
+    dmm::Dmm,
+    path::Path,
+    typedecl::ProcDecl,
+};
+
+use super::{
+    Dme, callgraph,
+    callgraph::CallKind,
+    resolve::{self, PrefabRef, ProcKey, Scope, Visitor},
+};
+
+/// Records which vars are read and which types are referenced anywhere in
+/// the object tree.
+#[derive(Default)]
+struct UsageCollector {
+    /// Vars which are read, by declaring type and name.
+    var_reads: HashSet<(NodeIndex, String)>,
+    /// Names of vars read from values whose type is not known.
+    field_reads: HashSet<String>,
+    types: HashSet<NodeIndex>,
+    /// Types passed to `typesof()` or `subtypesof()`, whose subtypes are
+    /// all considered used.
+    subtype_roots: HashSet<NodeIndex>,
+}
+
+impl UsageCollector {
+    fn use_var_type(&mut self, objtree: &ObjectTree, var_type: Option<&VarType>) {
+        if let Some(type_index) =
+            var_type.and_then(|vt| resolve::tree_path_type(objtree, &vt.type_path))
+        {
+            self.types.insert(type_index);
+        }
+    }
+
+    fn read_var(&mut self, objtree: &ObjectTree, type_index: NodeIndex, name: &str) {
+        if let Some(declaring) = resolve::find_var_declaration(objtree, type_index, name) {
+            self.var_reads.insert((declaring, name.to_string()));
+        }
+    }
+}
+
+impl Visitor for UsageCollector {
+    fn visit_statement(&mut self, scope: &Scope, stmt: &Statement, _location: Location) {
+        match stmt {
+            Statement::Var(var) => self.use_var_type(scope.objtree, Some(&var.var_type)),
+            Statement::Vars(vars) => {
+                for var in vars.iter() {
+                    self.use_var_type(scope.objtree, Some(&var.var_type));
+                }
+            }
+            Statement::ForList(for_list) => {
+                self.use_var_type(scope.objtree, for_list.var_type.as_ref());
+            }
+            Statement::ForKeyValue(for_key_value) => {
+                self.use_var_type(scope.objtree, for_key_value.var_type.as_ref());
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_term(&mut self, scope: &Scope, term: &Spanned<Term>) {
+        let objtree = scope.objtree;
+        match &term.elem {
+            Term::Prefab(prefab) | Term::NewPrefab { prefab, .. } => {
+                if let PrefabRef::Type(type_index) =
+                    resolve::resolve_prefab(objtree, prefab, scope.type_index)
+                {
+                    self.types.insert(type_index);
+                }
+            }
+            Term::Ident(name) => {
+                if !scope.is_write() && !scope.is_local(name.as_str()) {
+                    self.read_var(objtree, scope.type_index, name.as_str());
+                }
+            }
+            Term::GlobalIdent(name) => {
+                if !scope.is_write() {
+                    self.read_var(objtree, objtree.root().index(), name.as_str());
+                }
+            }
+            Term::Call(name, args)
+                if name.as_str() == "typesof" || name.as_str() == "subtypesof" =>
+            {
+                for arg in args.iter() {
+                    let Expression::Base { term, follow } = arg else {
+                        continue;
+                    };
+                    if let (Term::Prefab(prefab), true) = (&term.elem, follow.is_empty()) {
+                        if let PrefabRef::Type(type_index) =
+                            resolve::resolve_prefab(objtree, prefab, scope.type_index)
+                        {
+                            self.subtype_roots.insert(type_index);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_follow(&mut self, scope: &Scope, base: Option<NodeIndex>, follow: &Spanned<Follow>) {
+        let (Follow::Field(_, name) | Follow::StaticField(name)) = &follow.elem else {
+            return;
+        };
+        if scope.is_write() {
+            return;
+        }
+        let declaring =
+            base.and_then(|base| resolve::find_var_declaration(scope.objtree, base, name.as_str()));
+        match declaring {
+            Some(declaring) => {
+                self.var_reads.insert((declaring, name.to_string()));
+            }
+            None => {
+                self.field_reads.insert(name.to_string());
+            }
+        }
+    }
+
+    fn visit_type_var(
+        &mut self,
+        objtree: &ObjectTree,
+        _type_index: NodeIndex,
+        _name: &str,
+        var: &TypeVar,
+    ) {
+        if let Some(declaration) = &var.declaration {
+            self.use_var_type(objtree, Some(&declaration.var_type));
+        }
+    }
+
+    fn visit_proc(&mut self, objtree: &ObjectTree, _key: &ProcKey, proc_value: &ProcValue) {
+        for param in proc_value.parameters.iter() {
+            self.use_var_type(objtree, Some(&param.var_type));
+        }
+        if let Some(ProcReturnType::TypePath(type_path)) = &proc_value.return_type {
+            if let Some(type_index) = resolve::tree_path_type(objtree, type_path) {
+                self.types.insert(type_index);
+            }
+        }
+    }
+}
+
+/// Whether a proc definition can be reached. Calls resolve statically to
+/// the definition on the type they are made on, but reach the overrides of
+/// that definition on subtypes at runtime, so a definition is used if any
+/// definition it overrides is called other than through `..()`. Overrides of
+/// builtin procs are called by the engine.
+fn proc_is_used(
+    objtree: &ObjectTree,
+    key: &ProcKey,
+    called: &HashSet<ProcKey>,
+    dispatched: &HashSet<ProcKey>,
+    unresolved: &HashSet<&str>,
+) -> bool {
+    if called.contains(key)
+        || unresolved.contains(key.name.as_str())
+        || resolve::is_verb(objtree, key)
+    {
+        return true;
+    }
+
+    let is_last = objtree[key.type_index].procs[&key.name].value.len() == key.proc_index + 1;
+    let mut current = key.clone();
+    while let Some(parent) = resolve::parent_proc(objtree, &current) {
+        if is_last && parent.type_index != key.type_index && dispatched.contains(&parent) {
+            return true;
+        }
+        current = parent;
+    }
+    objtree[current.type_index].procs[&current.name].value[current.proc_index]
+        .location
+        .is_builtins()
+}
+
+/// Whether `type_index` or any of its parents is one of `roots`.
+fn is_under(objtree: &ObjectTree, type_index: NodeIndex, roots: &HashSet<NodeIndex>) -> bool {
+    let mut index = Some(type_index);
+    while let Some(idx) = index {
+        if roots.contains(&idx) {
+            return true;
+        }
+        index = objtree[idx].parent_type_index();
+    }
+    false
+}
+
+#[pyclass(module = "avulto")]
+pub struct UnusedReport {
+    /// Proc definitions which are never called or referenced.
+    #[pyo3(get)]
+    procs: Py<PyList>,
+    /// Vars which are declared but never read.
+    #[pyo3(get)]
+    vars: Py<PyList>,
+    /// Types which are never instantiated, mapped or referenced.
+    #[pyo3(get)]
+    types: Py<PyList>,
+}
+
+#[pymethods]
+impl UnusedReport {
+    fn __repr__(&self, py: Python<'_>) -> String {
+        format!(
+            "<UnusedReport procs={} vars={} types={}>",
+            self.procs.bind(py).len(),
+            self.vars.bind(py).len(),
+            self.types.bind(py).len()
+        )
+    }
+}
+
+/// The implementation of `DME.unused`.
+pub fn find_unused(
+    dme: &Bound<Dme>,
+    dmms: Option<Vec<PyRef<'_, Dmm>>>,
+    py: Python<'_>,
+) -> PyResult<UnusedReport> {
+    let dme_ref = dme.borrow();
+    if !dme_ref.procs_parsed {
+        return Err(PyRuntimeError::new_err(
+            "parse_procs=True was not included in DME's constructor",
+        ));
+    }
+    let objtree = &dme_ref.objtree;
+
+    let mut usage = UsageCollector::default();
+    resolve::walk_tree(objtree, &mut usage);
+
+    for dmm in dmms.iter().flatten() {
+        for prefabs in dmm.map.dictionary.values() {
+            for prefab in prefabs.iter() {
+                if let Some(type_index) = resolve::type_by_path(objtree, &prefab.path) {
+                    usage.types.insert(type_index);
+                }
+            }
+        }
+    }
+
+    let mut edges = Vec::new();
+    for key in callgraph::procs_with_code(objtree) {
+        callgraph::collect_calls(objtree, &key, &mut edges);
+    }
+    let called: HashSet<ProcKey> = edges.iter().filter_map(|edge| edge.callee.clone()).collect();
+    let dispatched: HashSet<ProcKey> = edges
+        .iter()
+        .filter(|edge| edge.kind != CallKind::ParentCall)
+        .filter_map(|edge| edge.callee.clone())
+        .collect();
+    let unresolved: HashSet<&str> = edges
+        .iter()
+        .filter(|edge| edge.callee.is_none() && edge.kind != CallKind::Dynamic)
+        .map(|edge| edge.name.as_str())
+        .collect();
+
+    let mut procs = Vec::new();
+    let mut vars = Vec::new();
+    let mut used_types = HashSet::new();
+    for ty in objtree.iter_types() {
+        if usage.types.contains(&ty.index()) || is_under(objtree, ty.index(), &usage.subtype_roots)
+        {
+            let mut index = Some(ty.index());
+            while let Some(idx) = index {
+                if !used_types.insert(idx) {
+                    break;
+                }
+                index = objtree[idx].parent_type_index();
+            }
+        }
+
+        let mut proc_names: Vec<&String> = ty.procs.keys().collect();
+        proc_names.sort();
+        for name in proc_names {
+            for (proc_index, proc_value) in ty.procs[name].value.iter().enumerate() {
+                if proc_value.location.is_builtins() {
+                    continue;
+                }
+                let key = ProcKey {
+                    type_index: ty.index(),
+                    name: name.clone(),
+                    proc_index,
+                };
+                if !proc_is_used(objtree, &key, &called, &dispatched, &unresolved) {
+                    procs.push(Py::new(
+                        py,
+                        ProcDecl::build(dme, key.type_index, &key.name, key.proc_index, py)?,
+                    )?);
+                }
+            }
+        }
+
+        let mut var_names: Vec<&String> = ty.vars.keys().collect();
+        var_names.sort();
+        for name in var_names {
+            let declared = ty.vars[name]
+                .declaration
+                .as_ref()
+                .is_some_and(|decl| !decl.location.is_builtins());
+            if declared
+                && !usage.var_reads.contains(&(ty.index(), name.clone()))
+                && !usage.field_reads.contains(name)
+            {
+                vars.push(Py::new(
+                    py,
+                    dme_ref.build_var_decl(name, ty.index(), ty.index(), py)?,
+                )?);
+            }
+        }
+    }
+
+    let mut types: Vec<Path> = objtree
+        .iter_types()
+        .filter(|ty| {
+            !ty.is_root() && !ty.location.is_builtins() && !used_types.contains(&ty.index())
+        })
+        .map(|ty| Path::make_trusted(&ty.path))
+        .collect();
+    types.sort();
+
+    Ok(UnusedReport {
+        procs: PyList::new(py, procs)?.unbind(),
+        vars: PyList::new(py, vars)?.unbind(),
+        types: PyList::new(py, types)?.unbind(),
+    })
+}
@@ -0,0 +1,834 @@
+use pyo3::{
+    exceptions::PyTypeError,
+    pyclass, pyfunction, pymethods,
+    types::{PyAnyMethods, PyDict, PyDictMethods, PyList, PyListMethods, PyTuple, PyTupleMethods},
+    Bound, IntoPyObjectExt, Py, PyAny, PyResult, Python,
+};
+
+use crate::{
+    dmlist::DmList,
+    typedecl::{ProcDecl, TypeDecl},
+};
+
+use super::{
+    expression::Expression,
+    nodes::{Node, PyCodeBlock, PyExpr, SwitchCase},
+};
+
+/// A named field of an AST node.
+pub struct AstField {
+    pub name: &'static str,
+    pub value: Py<PyAny>,
+    /// Whether the field holds child nodes which are visited, as opposed to
+    /// names, types and operators.
+    pub is_child: bool,
+}
+
+impl AstField {
+    fn child(name: &'static str, value: Py<PyAny>) -> AstField {
+        AstField {
+            name,
+            value,
+            is_child: true,
+        }
+    }
+
+    fn attr(name: &'static str, value: Py<PyAny>) -> AstField {
+        AstField {
+            name,
+            value,
+            is_child: false,
+        }
+    }
+}
+
+fn expr(e: &PyExpr, py: Python<'_>) -> Py<PyAny> {
+    e.clone_ref(py).into_any()
+}
+
+fn opt_expr(e: &Option<PyExpr>, py: Python<'_>) -> Py<PyAny> {
+    e.as_ref().map_or(py.None(), |e| e.clone_ref(py).into_any())
+}
+
+fn opt_node(n: &Option<Py<Node>>, py: Python<'_>) -> Py<PyAny> {
+    n.as_ref().map_or(py.None(), |n| n.clone_ref(py).into_any())
+}
+
+fn list<T>(items: &[Py<T>], py: Python<'_>) -> PyResult<Py<PyAny>> {
+    Ok(PyList::new(py, items.iter().map(|i| i.clone_ref(py)))?
+        .into_any()
+        .unbind())
+}
+
+fn opt_list<T>(items: &Option<Vec<Py<T>>>, py: Python<'_>) -> PyResult<Py<PyAny>> {
+    match items {
+        Some(items) => list(items, py),
+        None => Ok(py.None()),
+    }
+}
+
+impl Node {
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Node::Expression { .. } => "Expression",
+            Node::Crash { .. } => "Crash",
+            Node::Return { .. } => "Return",
+            Node::Throw { .. } => "Throw",
+            Node::Del { .. } => "Del",
+            Node::Break { .. } => "Break",
+            Node::While { .. } => "While",
+            Node::DoWhile { .. } => "DoWhile",
+            Node::If { .. } => "If",
+            Node::ForInfinite { .. } => "ForInfinite",
+            Node::ForList { .. } => "ForList",
+            Node::ForLoop { .. } => "ForLoop",
+            Node::ForRange { .. } => "ForRange",
+            Node::Var { .. } => "Var",
+            Node::Vars { .. } => "Vars",
+            Node::Setting { .. } => "Setting",
+            Node::Spawn { .. } => "Spawn",
+            Node::Continue { .. } => "Continue",
+            Node::Goto { .. } => "Goto",
+            Node::Label { .. } => "Label",
+            Node::TryCatch { .. } => "TryCatch",
+            Node::Switch { .. } => "Switch",
+            Node::ForKeyValue { .. } => "ForKeyValue",
+        }
+    }
+
+    /// The fields of the node in declaration order, without `source_loc`.
+    pub fn fields(&self, py: Python<'_>) -> PyResult<Vec<AstField>> {
+        Ok(match self {
+            Node::Expression { expr: e, .. } => vec![AstField::child("expr", expr(e, py))],
+            Node::Crash { expr: e, .. } => vec![AstField::child("expr", opt_expr(e, py))],
+            Node::Return { retval, .. } => vec![AstField::child("retval", opt_expr(retval, py))],
+            Node::Throw { expr: e, .. } | Node::Del { expr: e, .. } => {
+                vec![AstField::child("expr", expr(e, py))]
+            }
+            Node::Break { label, .. } => vec![AstField::attr("label", opt_expr(label, py))],
+            Node::While {
+                condition, block, ..
+            }
+            | Node::DoWhile {
+                condition, block, ..
+            } => vec![
+                AstField::child("condition", expr(condition, py)),
+                AstField::child("block", list(block, py)?),
+            ],
+            Node::If {
+                if_arms, else_arm, ..
+            } => {
+                let arms = if_arms
+                    .iter()
+                    .map(|(cond, block)| (expr(cond, py), list(block, py)?).into_py_any(py))
+                    .collect::<PyResult<Vec<_>>>()?;
+                vec![
+                    AstField::child("if_arms", PyList::new(py, arms)?.into_any().unbind()),
+                    AstField::child("else_arm", opt_list(else_arm, py)?),
+                ]
+            }
+            Node::ForInfinite { block, .. } => vec![AstField::child("block", list(block, py)?)],
+            Node::ForList {
+                var_type,
+                declared,
+                name,
+                in_list,
+                block,
+                ..
+            } => vec![
+                AstField::attr("var_type", var_type.clone().into_py_any(py)?),
+                AstField::attr("declared", (*declared).into_py_any(py)?),
+                AstField::attr("name", expr(name, py)),
+                AstField::child("in_list", opt_expr(in_list, py)),
+                AstField::child("block", list(block, py)?),
+            ],
+            Node::ForLoop {
+                init,
+                test,
+                inc,
+                block,
+                ..
+            } => vec![
+                AstField::child("init", opt_node(init, py)),
+                AstField::child("test", opt_expr(test, py)),
+                AstField::child("inc", opt_node(inc, py)),
+                AstField::child("block", list(block, py)?),
+            ],
+            Node::ForRange {
+                declared,
+                name,
+                start,
+                end,
+                step,
+                block,
+                ..
+            } => vec![
+                AstField::attr("declared", (*declared).into_py_any(py)?),
+                AstField::attr("name", expr(name, py)),
+                AstField::child("start", expr(start, py)),
+                AstField::child("end", expr(end, py)),
+                AstField::child("step", opt_expr(step, py)),
+                AstField::child("block", list(block, py)?),
+            ],
+            Node::Var {
+                name,
+                value,
+                declared_type,
+                ..
+            } => vec![
+                AstField::attr("name", expr(name, py)),
+                AstField::child("value", opt_expr(value, py)),
+                AstField::attr("declared_type", declared_type.clone().into_py_any(py)?),
+            ],
+            Node::Vars { vars, .. } => vec![AstField::child("vars", list(vars, py)?)],
+            Node::Setting {
+                name, mode, value, ..
+            } => vec![
+                AstField::attr("name", expr(name, py)),
+                AstField::attr("mode", (*mode).into_py_any(py)?),
+                AstField::child("value", expr(value, py)),
+            ],
+            Node::Spawn { delay, block, .. } => vec![
+                AstField::child("delay", opt_expr(delay, py)),
+                AstField::child("block", list(block, py)?),
+            ],
+            Node::Continue { name, .. } => vec![AstField::attr("name", opt_expr(name, py))],
+            Node::Goto { label, .. } => vec![AstField::attr("label", expr(label, py))],
+            Node::Label { name, block, .. } => vec![
+                AstField::attr("name", expr(name, py)),
+                AstField::child("block", list(block, py)?),
+            ],
+            Node::TryCatch {
+                try_block,
+                catch_params,
+                catch_block,
+                ..
+            } => {
+                let params = catch_params
+                    .iter()
+                    .map(|param| list(param, py))
+                    .collect::<PyResult<Vec<_>>>()?;
+                vec![
+                    AstField::child("try_block", list(try_block, py)?),
+                    AstField::attr("catch_params", PyList::new(py, params)?.into_any().unbind()),
+                    AstField::child("catch_block", list(catch_block, py)?),
+                ]
+            }
+            Node::Switch {
+                input,
+                cases,
+                default,
+                ..
+            } => vec![
+                AstField::child("input", expr(input, py)),
+                AstField::child("cases", list(cases, py)?),
+                AstField::child("default", opt_list(default, py)?),
+            ],
+            Node::ForKeyValue {
+                var_type,
+                declared,
+                key,
+                value,
+                in_list,
+                block,
+                ..
+            } => vec![
+                AstField::attr("var_type", var_type.clone().into_py_any(py)?),
+                AstField::attr("declared", (*declared).into_py_any(py)?),
+                AstField::attr("key", expr(key, py)),
+                AstField::attr("value", expr(value, py)),
+                AstField::child("in_list", opt_expr(in_list, py)),
+                AstField::child("block", list(block, py)?),
+            ],
+        })
+    }
+}
+
+impl Expression {
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Expression::Constant { .. } => "Constant",
+            Expression::Identifier { .. } => "Identifier",
+            Expression::GlobalIdentifier { .. } => "GlobalIdentifier",
+            Expression::List { .. } => "List",
+            Expression::BinaryOp { .. } => "BinaryOp",
+            Expression::AssignOp { .. } => "AssignOp",
+            Expression::TernaryOp { .. } => "TernaryOp",
+            Expression::InterpString { .. } => "InterpString",
+            Expression::Locate { .. } => "Locate",
+            Expression::Prefab { .. } => "Prefab",
+            Expression::Index { .. } => "Index",
+            Expression::Field { .. } => "Field",
+            Expression::StaticField { .. } => "StaticField",
+            Expression::Call { .. } => "Call",
+            Expression::GlobalCall { .. } => "GlobalCall",
+            Expression::SelfCall { .. } => "SelfCall",
+            Expression::ParentCall { .. } => "ParentCall",
+            Expression::UnaryOp { .. } => "UnaryOp",
+            Expression::ProcReference { .. } => "ProcReference",
+            Expression::ExternalCall { .. } => "ExternalCall",
+            Expression::NewMiniExpr { .. } => "NewMiniExpr",
+            Expression::NewImplicit { .. } => "NewImplicit",
+            Expression::NewPrefab { .. } => "NewPrefab",
+            Expression::DynamicCall { .. } => "DynamicCall",
+            Expression::Input { .. } => "Input",
+            Expression::Pick { .. } => "Pick",
+            Expression::As { .. } => "As",
+        }
+    }
+
+    /// The fields of the expression in declaration order, without
+    /// `source_loc`. Identifiers naming fields and procs are not children,
+    /// since they are never evaluated.
+    pub fn fields(&self, py: Python<'_>) -> PyResult<Vec<AstField>> {
+        Ok(match self {
+            Expression::Constant { constant, .. } => {
+                vec![AstField::attr("constant", constant.clone().into_py_any(py)?)]
+            }
+            Expression::Identifier { name, .. } | Expression::GlobalIdentifier { name, .. } => {
+                vec![AstField::attr("name", name.into_py_any(py)?)]
+            }
+            Expression::List { list, .. } => {
+                vec![AstField::child("list", list.clone_ref(py).into_any())]
+            }
+            Expression::BinaryOp { op, lhs, rhs, .. } => vec![
+                AstField::attr("op", (*op).into_py_any(py)?),
+                AstField::child("lhs", expr(lhs, py)),
+                AstField::child("rhs", expr(rhs, py)),
+            ],
+            Expression::AssignOp { op, lhs, rhs, .. } => vec![
+                AstField::attr("op", (*op).into_py_any(py)?),
+                AstField::child("lhs", expr(lhs, py)),
+                AstField::child("rhs", expr(rhs, py)),
+            ],
+            Expression::TernaryOp {
+                cond,
+                if_expr,
+                else_expr,
+                ..
+            } => vec![
+                AstField::child("cond", expr(cond, py)),
+                AstField::child("if_expr", expr(if_expr, py)),
+                AstField::child("else_expr", expr(else_expr, py)),
+            ],
+            Expression::InterpString {
+                first, token_pairs, ..
+            } => {
+                let pairs = token_pairs
+                    .iter()
+                    .map(|(e, c)| (opt_expr(e, py), c.clone_ref(py)).into_py_any(py))
+                    .collect::<PyResult<Vec<_>>>()?;
+                vec![
+                    AstField::attr("first", first.clone().into_py_any(py)?),
+                    AstField::child("token_pairs", PyList::new(py, pairs)?.into_any().unbind()),
+                ]
+            }
+            Expression::Locate { args, in_list, .. } => vec![
+                AstField::child("args", list(args, py)?),
+                AstField::child("in_list", opt_expr(in_list, py)),
+            ],
+            Expression::Prefab { prefab, .. } => {
+                vec![AstField::attr("prefab", prefab.clone_ref(py).into_any())]
+            }
+            Expression::Index { expr: e, index, .. } => vec![
+                AstField::child("expr", expr(e, py)),
+                AstField::child("index", expr(index, py)),
+            ],
+            Expression::Field { expr: e, field, .. } => vec![
+                AstField::child("expr", opt_expr(e, py)),
+                AstField::attr("field", expr(field, py)),
+            ],
+            Expression::StaticField { expr: e, field, .. } => vec![
+                AstField::child("expr", expr(e, py)),
+                AstField::attr("field", expr(field, py)),
+            ],
+            Expression::Call {
+                expr: e, name, args, ..
+            } => vec![
+                AstField::child("expr", expr(e, py)),
+                AstField::attr("name", expr(name, py)),
+                AstField::child("args", list(args, py)?),
+            ],
+            Expression::GlobalCall { name, args, .. } => vec![
+                AstField::attr("name", expr(name, py)),
+                AstField::child("args", list(args, py)?),
+            ],
+            Expression::SelfCall { args, .. } | Expression::ParentCall { args, .. } => {
+                vec![AstField::child("args", list(args, py)?)]
+            }
+            Expression::UnaryOp {
+                expr: e, unary_op, ..
+            } => vec![
+                AstField::child("expr", expr(e, py)),
+                AstField::attr("unary_op", (*unary_op).into_py_any(py)?),
+            ],
+            Expression::ProcReference { expr: e, name, .. } => vec![
+                AstField::child("expr", expr(e, py)),
+                AstField::attr("name", expr(name, py)),
+            ],
+            Expression::ExternalCall {
+                library_name,
+                function_name,
+                args,
+                ..
+            } => vec![
+                AstField::child("library_name", opt_expr(library_name, py)),
+                AstField::child("function_name", expr(function_name, py)),
+                AstField::child("args", list(args, py)?),
+            ],
+            Expression::NewMiniExpr {
+                name, fields, args, ..
+            } => vec![
+                AstField::attr("name", expr(name, py)),
+                AstField::attr("fields", list(fields, py)?),
+                AstField::child("args", opt_list(args, py)?),
+            ],
+            Expression::NewImplicit { args, .. } => {
+                vec![AstField::child("args", opt_list(args, py)?)]
+            }
+            Expression::NewPrefab { prefab, args, .. } => vec![
+                AstField::attr("prefab", prefab.clone_ref(py).into_any()),
+                AstField::child("args", opt_list(args, py)?),
+            ],
+            Expression::DynamicCall {
+                lib_name,
+                proc_name,
+                ..
+            } => vec![
+                AstField::child("lib_name", list(lib_name, py)?),
+                AstField::child("proc_name", list(proc_name, py)?),
+            ],
+            Expression::Input {
+                args,
+                input_type,
+                in_list,
+                ..
+            } => vec![
+                AstField::child("args", list(args, py)?),
+                AstField::attr("input_type", (*input_type).into_py_any(py)?),
+                AstField::child("in_list", opt_expr(in_list, py)),
+            ],
+            Expression::Pick { args, .. } => {
+                let pairs = args
+                    .iter()
+                    .map(|(weight, value)| (opt_expr(weight, py), expr(value, py)).into_py_any(py))
+                    .collect::<PyResult<Vec<_>>>()?;
+                vec![AstField::child("args", PyList::new(py, pairs)?.into_any().unbind())]
+            }
+            Expression::As { input_type, .. } => {
+                vec![AstField::attr("input_type", (*input_type).into_py_any(py)?)]
+            }
+        })
+    }
+}
+
+impl SwitchCase {
+    pub fn fields(&self, py: Python<'_>) -> PyResult<Vec<AstField>> {
+        Ok(vec![
+            AstField::child("exact", self.exact.clone_ref(py).into_any()),
+            AstField::child("range", self.range.clone_ref(py).into_any()),
+            AstField::child("block", list(&self.block, py)?),
+        ])
+    }
+}
+
+fn is_ast_node(value: &Bound<PyAny>) -> bool {
+    value.is_instance_of::<Node>()
+        || value.is_instance_of::<Expression>()
+        || value.is_instance_of::<SwitchCase>()
+}
+
+/// The class name and fields of an AST node.
+pub fn ast_fields(node: &Bound<PyAny>) -> PyResult<(&'static str, Vec<AstField>)> {
+    let py = node.py();
+    if let Ok(node) = node.cast::<Node>() {
+        let node = node.get();
+        Ok((node.variant_name(), node.fields(py)?))
+    } else if let Ok(expr) = node.cast::<Expression>() {
+        let expr = expr.borrow();
+        Ok((expr.variant_name(), expr.fields(py)?))
+    } else if let Ok(case) = node.cast::<SwitchCase>() {
+        Ok(("SwitchCase", case.borrow().fields(py)?))
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "expected an AST node, got {}",
+            node.get_type().name()?
+        )))
+    }
+}
+
+/// The name of the kind of an AST node, as used in `visit_<Kind>` methods.
+#[pyfunction]
+pub fn kind_name(node: &Bound<PyAny>) -> PyResult<&'static str> {
+    Ok(ast_fields(node)?.0)
+}
+
+fn collect_nodes<'py>(value: &Bound<'py, PyAny>, out: &mut Vec<Bound<'py, PyAny>>) -> PyResult<()> {
+    if is_ast_node(value) {
+        out.push(value.clone());
+    } else if let Ok(items) = value.cast::<PyList>() {
+        for item in items.iter() {
+            collect_nodes(&item, out)?;
+        }
+    } else if let Ok(items) = value.cast::<PyTuple>() {
+        for item in items.iter() {
+            collect_nodes(&item, out)?;
+        }
+    } else if let Ok(dmlist) = value.cast::<DmList>() {
+        let dmlist = dmlist.borrow();
+        for (key, val) in dmlist.keys.iter().zip(dmlist.vals.iter()) {
+            collect_nodes(key.bind(value.py()), out)?;
+            collect_nodes(val.bind(value.py()), out)?;
+        }
+    }
+    Ok(())
+}
+
+/// The direct children of an AST node, in field order.
+pub fn child_nodes<'py>(node: &Bound<'py, PyAny>) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    let mut out = vec![];
+    for field in ast_fields(node)?.1 {
+        if field.is_child {
+            collect_nodes(field.value.bind(node.py()), &mut out)?;
+        }
+    }
+    Ok(out)
+}
+
+/// The names of the fields of an AST node, as `_fields`.
+pub fn field_names<'py>(node: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyTuple>> {
+    PyTuple::new(node.py(), ast_fields(node)?.1.iter().map(|field| field.name))
+}
+
+/// The `(name, value)` pairs of the fields of an AST node, as `iter_fields()`.
+pub fn iter_fields(node: &Bound<PyAny>) -> PyResult<Vec<(&'static str, Py<PyAny>)>> {
+    Ok(ast_fields(node)?
+        .1
+        .into_iter()
+        .map(|field| (field.name, field.value))
+        .collect())
+}
+
+/// Visits the nodes in `value`, returning the replacement for `value` if
+/// any of them were changed. Nodes in lists may be removed by returning
+/// `None`, or replaced by several nodes by returning a list.
+fn transform_value<'py>(
+    visitor: &Bound<'py, PyAny>,
+    value: &Bound<'py, PyAny>,
+) -> PyResult<Option<Bound<'py, PyAny>>> {
+    let py = value.py();
+    if is_ast_node(value) {
+        let result = visitor.call_method1("visit", (value,))?;
+        return Ok((!result.is(value)).then_some(result));
+    }
+
+    if let Ok(items) = value.cast::<PyList>() {
+        let mut changed = false;
+        let mut out = vec![];
+        for item in items.iter() {
+            if !is_ast_node(&item) {
+                match transform_value(visitor, &item)? {
+                    Some(new_item) => {
+                        changed = true;
+                        out.push(new_item);
+                    }
+                    None => out.push(item),
+                }
+                continue;
+            }
+            let result = visitor.call_method1("visit", (&item,))?;
+            if result.is(&item) {
+                out.push(item);
+                continue;
+            }
+            changed = true;
+            if let Ok(spliced) = result.cast::<PyList>() {
+                out.extend(spliced.iter());
+            } else if !result.is_none() {
+                out.push(result);
+            }
+        }
+        return Ok(if changed {
+            Some(PyList::new(py, out)?.into_any())
+        } else {
+            None
+        });
+    }
+
+    if let Ok(items) = value.cast::<PyTuple>() {
+        let mut changed = false;
+        let mut out = vec![];
+        for item in items.iter() {
+            match transform_value(visitor, &item)? {
+                Some(new_item) => {
+                    changed = true;
+                    out.push(new_item);
+                }
+                None => out.push(item),
+            }
+        }
+        return Ok(if changed {
+            Some(PyTuple::new(py, out)?.into_any())
+        } else {
+            None
+        });
+    }
+
+    if let Ok(dmlist) = value.cast::<DmList>() {
+        let dmlist = dmlist.borrow();
+        let mut changed = false;
+        let mut transform_all = |items: &[Py<PyAny>]| -> PyResult<Vec<Py<PyAny>>> {
+            items
+                .iter()
+                .map(|item| match transform_value(visitor, item.bind(py))? {
+                    Some(new_item) => {
+                        changed = true;
+                        Ok(new_item.unbind())
+                    }
+                    None => Ok(item.clone_ref(py)),
+                })
+                .collect()
+        };
+        let keys = transform_all(&dmlist.keys)?;
+        let vals = transform_all(&dmlist.vals)?;
+        return Ok(if changed {
+            Some(Py::new(py, DmList { keys, vals })?.into_bound(py).into_any())
+        } else {
+            None
+        });
+    }
+
+    Ok(None)
+}
+
+/// Where a `NodeVisitor` is in the tree it is walking.
+#[pyclass(module = "avulto.ast")]
+pub struct WalkContext {
+    /// The nodes enclosing the node being visited, outermost first.
+    #[pyo3(get)]
+    parents: Py<PyList>,
+    /// The proc being walked, if the walk was started from a proc.
+    #[pyo3(get, name = "proc")]
+    proc_decl: Option<Py<ProcDecl>>,
+    /// The type the proc being walked is defined on.
+    #[pyo3(get)]
+    type_decl: Option<Py<TypeDecl>>,
+}
+
+impl WalkContext {
+    fn count_parents(&self, py: Python<'_>, pred: impl Fn(&Node) -> bool) -> usize {
+        self.parents
+            .bind(py)
+            .iter()
+            .filter(|parent| parent.cast::<Node>().is_ok_and(|node| pred(node.get())))
+            .count()
+    }
+}
+
+#[pymethods]
+impl WalkContext {
+    /// The innermost node enclosing the node being visited.
+    #[getter]
+    fn get_parent(&self, py: Python<'_>) -> PyResult<Option<Py<PyAny>>> {
+        let parents = self.parents.bind(py);
+        if parents.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(parents.get_item(parents.len() - 1)?.unbind()))
+    }
+
+    /// The number of loops enclosing the node being visited.
+    #[getter]
+    fn get_loop_depth(&self, py: Python<'_>) -> usize {
+        self.count_parents(py, |node| {
+            matches!(
+                node,
+                Node::While { .. }
+                    | Node::DoWhile { .. }
+                    | Node::ForInfinite { .. }
+                    | Node::ForList { .. }
+                    | Node::ForLoop { .. }
+                    | Node::ForRange { .. }
+                    | Node::ForKeyValue { .. }
+            )
+        })
+    }
+
+    /// The number of `spawn()` blocks enclosing the node being visited.
+    #[getter]
+    fn get_spawn_depth(&self, py: Python<'_>) -> usize {
+        self.count_parents(py, |node| matches!(node, Node::Spawn { .. }))
+    }
+
+    /// Returns the innermost enclosing node of the given kind, if any.
+    fn enclosing(&self, kind: &Bound<PyAny>, py: Python<'_>) -> PyResult<Option<Py<PyAny>>> {
+        for parent in self.parents.bind(py).iter().rev() {
+            if parent.getattr("kind")?.eq(kind)? {
+                return Ok(Some(parent.unbind()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> String {
+        format!("<WalkContext depth={}>", self.parents.bind(py).len())
+    }
+}
+
+/// Calls `f` with `node` pushed onto the parents of the visitor's context.
+fn with_parent<R>(
+    visitor: &Bound<NodeVisitor>,
+    node: &Bound<PyAny>,
+    f: impl FnOnce() -> PyResult<R>,
+) -> PyResult<R> {
+    let py = node.py();
+    let parents = visitor.borrow().context.borrow(py).parents.clone_ref(py);
+    let parents = parents.bind(py);
+    parents.append(node)?;
+    let result = f();
+    parents.del_item(parents.len() - 1)?;
+    result
+}
+
+/// Sets the proc and type of the visitor's context, returning the previous
+/// ones.
+pub fn set_proc(
+    visitor: &Bound<NodeVisitor>,
+    proc_decl: Option<Py<ProcDecl>>,
+    type_decl: Option<Py<TypeDecl>>,
+) -> (Option<Py<ProcDecl>>, Option<Py<TypeDecl>>) {
+    let py = visitor.py();
+    let visitor = visitor.borrow();
+    let mut context = visitor.context.borrow_mut(py);
+    (
+        std::mem::replace(&mut context.proc_decl, proc_decl),
+        std::mem::replace(&mut context.type_decl, type_decl),
+    )
+}
+
+/// Calls `f` with the visitor's context set to the proc `proc_decl`, defined
+/// on `type_decl`.
+pub fn in_proc<R>(
+    visitor: &Bound<NodeVisitor>,
+    proc_decl: Py<ProcDecl>,
+    type_decl: Py<TypeDecl>,
+    f: impl FnOnce() -> PyResult<R>,
+) -> PyResult<R> {
+    let (previous_proc, previous_type) = set_proc(visitor, Some(proc_decl), Some(type_decl));
+    let result = f();
+    set_proc(visitor, previous_proc, previous_type);
+    result
+}
+
+/// A base class for walking the AST of proc bodies and expressions,
+/// modelled on Python's `ast.NodeVisitor`.
+#[pyclass(module = "avulto.ast", subclass)]
+pub struct NodeVisitor {
+    /// Where the visitor is in the tree.
+    #[pyo3(get)]
+    context: Py<WalkContext>,
+}
+
+impl NodeVisitor {
+    fn base(py: Python<'_>) -> PyResult<NodeVisitor> {
+        Ok(NodeVisitor {
+            context: Py::new(
+                py,
+                WalkContext {
+                    parents: PyList::empty(py).unbind(),
+                    proc_decl: None,
+                    type_decl: None,
+                },
+            )?,
+        })
+    }
+}
+
+#[pymethods]
+impl NodeVisitor {
+    #[new]
+    #[pyo3(signature = (*_args, **_kwargs))]
+    fn new(
+        _args: &Bound<PyTuple>,
+        _kwargs: Option<&Bound<PyDict>>,
+        py: Python<'_>,
+    ) -> PyResult<Self> {
+        NodeVisitor::base(py)
+    }
+
+    /// Calls `visit_<Kind>(node)` if the visitor defines it, and
+    /// `generic_visit(node)` otherwise.
+    fn visit(slf: &Bound<Self>, node: &Bound<PyAny>) -> PyResult<Py<PyAny>> {
+        let (name, _) = ast_fields(node)?;
+        let method = format!("visit_{}", name);
+        if slf.hasattr(method.as_str())? {
+            return Ok(slf.call_method1(method.as_str(), (node,))?.unbind());
+        }
+        Ok(slf.call_method1("generic_visit", (node,))?.unbind())
+    }
+
+    /// Visits each child of `node`.
+    fn generic_visit(slf: &Bound<Self>, node: &Bound<PyAny>) -> PyResult<()> {
+        with_parent(slf, node, || {
+            for child in child_nodes(node)? {
+                slf.call_method1("visit", (child,))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A visitor which rebuilds the nodes it visits from the results of visiting
+/// their children, modelled on Python's `ast.NodeTransformer`.
+#[pyclass(module = "avulto.ast", extends = NodeVisitor, subclass)]
+pub struct NodeTransformer;
+
+#[pymethods]
+impl NodeTransformer {
+    #[new]
+    #[pyo3(signature = (*_args, **_kwargs))]
+    fn new(
+        _args: &Bound<PyTuple>,
+        _kwargs: Option<&Bound<PyDict>>,
+        py: Python<'_>,
+    ) -> PyResult<(Self, NodeVisitor)> {
+        Ok((NodeTransformer, NodeVisitor::base(py)?))
+    }
+
+    /// Visits each child of `node`, returning `node` if none of them were
+    /// replaced, and a copy of `node` with the replacements otherwise.
+    fn generic_visit(slf: &Bound<Self>, node: &Bound<PyAny>) -> PyResult<Py<PyAny>> {
+        let py = node.py();
+        let (_, fields) = ast_fields(node)?;
+        let kwargs = PyDict::new(py);
+        let changed = with_parent(slf.as_super(), node, || {
+            let mut changed = false;
+            for field in fields {
+                let value = field.value.into_bound(py);
+                let new_value = if field.is_child {
+                    transform_value(slf.as_any(), &value)?
+                } else {
+                    None
+                };
+                changed |= new_value.is_some();
+                kwargs.set_item(field.name, new_value.unwrap_or(value))?;
+            }
+            Ok(changed)
+        })?;
+        if !changed {
+            return Ok(node.clone().unbind());
+        }
+
+        if node.is_instance_of::<SwitchCase>() {
+            let block: PyCodeBlock = kwargs.get_item("block")?.unwrap().extract()?;
+            let case = SwitchCase {
+                exact: kwargs.get_item("exact")?.unwrap().cast_into::<PyList>()?.unbind(),
+                range: kwargs.get_item("range")?.unwrap().cast_into::<PyList>()?.unbind(),
+                block,
+            };
+            return Ok(Py::new(py, case)?.into_any());
+        }
+        kwargs.set_item("source_loc", node.getattr("source_loc")?)?;
+        Ok(node.get_type().call((), Some(&kwargs))?.unbind())
+    }
+}
@@ -1,7 +1,7 @@
 #[macro_use]
 extern crate lazy_static;
 
-use dme::{EmptyProcError, MissingProcError, MissingTypeError};
+use dme::{evaluate::EvaluationError, EmptyProcError, MissingProcError, MissingTypeError};
 use dmi::IconError;
 use dmlist::DmList;
 use path::PathError;
@@ -39,6 +39,14 @@ fn avulto(_py: Python, m: &Bound<PyModule>) -> PyResult<()> {
     m.add_class::<dmi::StateIter>()?;
 
     m.add_class::<dme::Dme>()?;
+    m.add_class::<dme::defines::DefineDecl>()?;
+    m.add_class::<dme::callgraph::CallGraph>()?;
+    m.add_class::<dme::callgraph::CallSite>()?;
+    m.add_class::<dme::cfg::BasicBlock>()?;
+    m.add_class::<dme::cfg::ControlFlowGraph>()?;
+    m.add_class::<dme::inference::InferredTypes>()?;
+    m.add_class::<dme::unused::UnusedReport>()?;
+    m.add_class::<dme::query::QueryMatch>()?;
     m.add_class::<ProcDecl>()?;
     m.add_class::<TypeDecl>()?;
     m.add_class::<VarDecl>()?;
@@ -59,6 +67,7 @@ fn avulto(_py: Python, m: &Bound<PyModule>) -> PyResult<()> {
     err_submodule.add("MissingProcError", _py.get_type::<MissingProcError>())?;
     err_submodule.add("IconError", _py.get_type::<IconError>())?;
     err_submodule.add("PathError", _py.get_type::<PathError>())?;
+    err_submodule.add("EvaluationError", _py.get_type::<EvaluationError>())?;
 
     m.add_submodule(&err_submodule)?;
 
@@ -1,16 +1,25 @@
 extern crate dreammaker;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use dreammaker::objtree::NodeIndex;
+use dreammaker::{
+    ast::{ProcDeclKind, ProcReturnType, Statement},
+    objtree::{NodeIndex, Type},
+};
 use pyo3::{
-    exceptions::PyValueError,
+    exceptions::{PyRuntimeError, PyValueError},
     prelude::*,
-    types::PyList,
+    types::{PyDict, PyList},
 };
 
 use crate::{
-    dme::{Dme, FilledSourceLocation},
+    dme::{
+        callgraph::{self, CallSite}, cfg::ControlFlowGraph, evaluate::Evaluator,
+        expression::Expression, inference::InferredTypes,
+        nodes::{OriginalSourceLocation, PyExpr}, proc_walk,
+        resolve::{self, ProcKey}, sleep, unparse::Unparser,
+        visitor::{self, NodeVisitor}, Dme,
+    },
     path::Path,
 };
 
@@ -25,11 +34,36 @@ pub struct VarDecl {
     #[pyo3(get)]
     pub const_val: Option<Py<PyAny>>,
     #[pyo3(get)]
+    pub evaluated_val: Option<Py<PyAny>>,
+    #[pyo3(get)]
     pub source_loc: Option<Py<PyAny>>,
+    #[pyo3(get)]
+    pub declaring_type: Option<Path>,
+    #[pyo3(get)]
+    pub is_static: bool,
+    #[pyo3(get)]
+    pub is_const: bool,
+    #[pyo3(get)]
+    pub is_tmp: bool,
+    #[pyo3(get)]
+    pub is_final: bool,
+    #[pyo3(get)]
+    pub is_private: bool,
+    #[pyo3(get)]
+    pub is_protected: bool,
+    #[pyo3(get)]
+    pub input_type: Option<String>,
+    #[pyo3(get)]
+    pub var_type: Option<String>,
 }
 
 #[pymethods]
 impl VarDecl {
+    #[getter]
+    fn is_global(&self) -> bool {
+        self.is_static
+    }
+
     fn __str__(&self) -> PyResult<String> {
         self.__repr__()
     }
@@ -63,6 +97,39 @@ pub struct ProcArg {
     pub arg_name: Py<PyAny>,
     #[pyo3(get)]
     pub arg_type: Py<PyAny>,
+    #[pyo3(get)]
+    pub default: Option<Py<Expression>>,
+    #[pyo3(get)]
+    pub input_type: Option<String>,
+    #[pyo3(get)]
+    pub in_list: Option<Py<Expression>>,
+    #[pyo3(get)]
+    pub source_loc: Py<PyAny>,
+}
+
+impl ProcArg {
+    /// Renders the argument as it would be declared, e.g. `mob/user = null`.
+    fn render(&self, unparser: &Unparser, py: Python<'_>) -> PyResult<String> {
+        let mut out = String::new();
+        if let Ok(arg_type) = self.arg_type.extract::<Path>(py) {
+            out.push_str(arg_type.rel.trim_start_matches('/'));
+            out.push('/');
+        }
+        out.push_str(self.arg_name.extract::<String>(py)?.as_str());
+        if let Some(default) = &self.default {
+            out.push_str(" = ");
+            out.push_str(unparser.expr(default)?.as_str());
+        }
+        if let Some(input_type) = &self.input_type {
+            out.push_str(" as ");
+            out.push_str(input_type);
+        }
+        if let Some(in_list) = &self.in_list {
+            out.push_str(" in ");
+            out.push_str(unparser.expr(in_list)?.as_str());
+        }
+        Ok(out)
+    }
 }
 
 #[pymethods]
@@ -93,6 +160,176 @@ pub struct ProcDecl {
     proc_index: usize,
     #[pyo3(get)]
     source_loc: Py<PyAny>,
+    #[pyo3(get)]
+    pub is_verb: bool,
+    #[pyo3(get)]
+    pub is_override: bool,
+    #[pyo3(get)]
+    pub return_type: Option<String>,
+    #[pyo3(get)]
+    pub settings: Py<PyDict>,
+}
+
+fn type_def_path(type_def: &Type) -> Path {
+    if type_def.is_root() {
+        Path::root()
+    } else {
+        Path::make_trusted(&type_def.path)
+    }
+}
+
+impl TypeDecl {
+    /// Builds the declaration for the type at `node_index`.
+    pub fn build(dme: &Bound<Dme>, node_index: NodeIndex, py: Python<'_>) -> PyResult<TypeDecl> {
+        let dme_ref = dme.borrow();
+        let type_def = &dme_ref.objtree[node_index];
+        let osl = Some(OriginalSourceLocation::from_location(&type_def.location));
+        Ok(TypeDecl {
+            dme: dme.clone().into_any().unbind(),
+            node_index,
+            path: type_def_path(type_def),
+            source_loc: Some(dme_ref.populate_source_loc(&osl, py)),
+        })
+    }
+
+    /// Builds the declarations for each of `indexes`.
+    fn build_all(
+        dme: &Bound<Dme>,
+        indexes: impl IntoIterator<Item = NodeIndex>,
+        py: Python<'_>,
+    ) -> PyResult<Vec<TypeDecl>> {
+        indexes
+            .into_iter()
+            .map(|index| TypeDecl::build(dme, index, py))
+            .collect()
+    }
+}
+
+impl ProcDecl {
+    /// Builds the declaration for the `proc_index`th definition of
+    /// `proc_name` on the type at `node_index`.
+    pub fn build(
+        dme: &Bound<Dme>,
+        node_index: NodeIndex,
+        proc_name: &str,
+        proc_index: usize,
+        py: Python<'_>,
+    ) -> PyResult<ProcDecl> {
+        let dme_ref = dme.borrow();
+        let objtree = &dme_ref.objtree;
+        let type_def = &objtree[node_index];
+        let type_proc = &type_def.procs[proc_name];
+        let proc_value = &type_proc.value[proc_index];
+
+        let mut args_out: Vec<ProcArg> = Vec::new();
+        for arg in proc_value.parameters.iter() {
+            let arg_typepath = if arg.var_type.type_path.is_empty() {
+                py.None()
+            } else {
+                Path::from_tree_path(&arg.var_type.type_path)
+                    .into_pyobject(py)?
+                    .into_any()
+                    .unbind()
+            };
+            let arg_osl = Some(OriginalSourceLocation::from_location(&arg.location));
+            args_out.push(ProcArg {
+                arg_name: arg.name.clone().into_pyobject(py)?.into(),
+                arg_type: arg_typepath.into_pyobject(py)?.into(),
+                default: match &arg.default {
+                    Some(default) => Some(Expression::parse(py, default).into_pyobject(py)?.unbind()),
+                    None => None,
+                },
+                input_type: arg
+                    .input_type
+                    .filter(|input_type| !input_type.is_empty())
+                    .map(|input_type| input_type.to_string()),
+                in_list: match &arg.in_list {
+                    Some(in_list) => Some(Expression::parse(py, in_list).into_pyobject(py)?.unbind()),
+                    None => None,
+                },
+                source_loc: dme_ref.populate_source_loc(&arg_osl, py),
+            });
+        }
+
+        let mut declaration = None;
+        let mut index = Some(node_index);
+        while let Some(idx) = index {
+            declaration = objtree[idx]
+                .procs
+                .get(proc_name)
+                .and_then(|type_proc| type_proc.declaration.as_ref());
+            if declaration.is_some() {
+                break;
+            }
+            index = objtree[idx].parent_type_index();
+        }
+        let is_verb = declaration.is_some_and(|decl| matches!(decl.kind, ProcDeclKind::Verb));
+        let is_override = type_proc.declaration.is_none() || proc_index > 0;
+
+        let return_type = proc_value.return_type.as_ref().map(|return_type| match return_type {
+            ProcReturnType::InputType(input_type) => input_type.to_string(),
+            ProcReturnType::TypePath(type_path) => type_path
+                .iter()
+                .map(|part| format!("/{}", part))
+                .collect(),
+        });
+
+        let settings = PyDict::new(py);
+        if let Some(code) = &proc_value.code {
+            for stmt in code.iter() {
+                if let Statement::Setting { name, value, .. } = &stmt.elem {
+                    let expr: PyExpr = Expression::parse(py, value).into_pyobject(py)?.unbind();
+                    let setting = match Evaluator::new(&dme_ref, Some(node_index), py)
+                        .eval(&expr)
+                        .and_then(|value| value.to_python(py))
+                    {
+                        Ok(value) => value,
+                        Err(_) => expr.into_any(),
+                    };
+                    settings.set_item(name.as_str(), setting)?;
+                }
+            }
+        }
+
+        let osl = Some(OriginalSourceLocation::from_location(&proc_value.location));
+        Ok(ProcDecl {
+            dme: dme.clone().into_any().unbind(),
+            name: proc_name.to_string(),
+            type_path: type_def_path(type_def).into_pyobject(py)?.into_any().unbind(),
+            args: PyList::new(
+                py,
+                args_out
+                    .into_iter()
+                    .map(|f| f.into_pyobject(py).unwrap().into_any().unbind())
+                    .collect::<Vec<Py<PyAny>>>(),
+            )?
+            .into_pyobject(py)?
+            .into_any()
+            .unbind(),
+            proc_index,
+            type_index: node_index,
+            source_loc: dme_ref.populate_source_loc(&osl, py),
+            is_verb,
+            is_override,
+            return_type,
+            settings: settings.unbind(),
+        })
+    }
+
+    pub fn key(&self) -> ProcKey {
+        ProcKey {
+            type_index: self.type_index,
+            name: self.name.clone(),
+            proc_index: self.proc_index,
+        }
+    }
+
+    fn setting_is_true(&self, name: &str, default: bool, py: Python<'_>) -> PyResult<bool> {
+        match self.settings.bind(py).get_item(name)? {
+            Some(value) => value.is_truthy(),
+            None => Ok(default),
+        }
+    }
 }
 
 #[pymethods]
@@ -105,16 +342,124 @@ impl ProcDecl {
         Ok(format!("<Proc {}/proc/{}>", self.type_path, self.name))
     }
 
-    pub fn walk(&self, walker: &Bound<PyAny>, py: Python<'_>) -> PyResult<()> {
+    #[getter]
+    fn should_call_parent(&self, py: Python<'_>) -> PyResult<bool> {
+        self.setting_is_true("SpacemanDMM_should_call_parent", false, py)
+    }
+
+    #[getter]
+    fn should_not_sleep(&self, py: Python<'_>) -> PyResult<bool> {
+        self.setting_is_true("SpacemanDMM_should_not_sleep", false, py)
+    }
+
+    #[getter]
+    fn waitfor(&self, py: Python<'_>) -> PyResult<bool> {
+        self.setting_is_true("waitfor", true, py)
+    }
+
+    /// Renders the proc's declaration, e.g. `/obj/proc/attack(mob/target, damage = 10)`.
+    pub fn signature(&self, py: Python<'_>) -> PyResult<String> {
+        let unparser = Unparser::new(py, "\t");
+        let mut args: Vec<String> = Vec::new();
+        for arg in self.args.bind(py).cast::<PyList>()?.iter() {
+            args.push(arg.cast::<ProcArg>()?.borrow().render(&unparser, py)?);
+        }
+        let type_path = self.type_path.extract::<Path>(py)?;
+        let prefix = if type_path.rel == "/" {
+            ""
+        } else {
+            type_path.rel.as_str()
+        };
+        Ok(format!(
+            "{}/{}/{}({})",
+            prefix,
+            if self.is_verb { "verb" } else { "proc" },
+            self.name,
+            args.join(", ")
+        ))
+    }
+
+    /// Returns the definition this proc overrides: an earlier definition on
+    /// the same type, or the nearest definition on a parent type.
+    pub fn parent_proc(&self, py: Python<'_>) -> PyResult<Option<ProcDecl>> {
         let dme = self.dme.cast_bound::<Dme>(py).unwrap();
-        Dme::walk_proc(
-            &dme.borrow(),
-            self.type_index,
-            self.name.clone(),
-            walker,
-            self.proc_index,
-            py,
-        )
+        let parent = resolve::parent_proc(&dme.borrow().objtree, &self.key());
+        match parent {
+            Some(key) => {
+                ProcDecl::build(dme, key.type_index, &key.name, key.proc_index, py).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Builds the control-flow graph of the proc body.
+    pub fn cfg(&self, py: Python<'_>) -> PyResult<ControlFlowGraph> {
+        let dme = self.dme.cast_bound::<Dme>(py).unwrap();
+        ControlFlowGraph::for_proc(&dme.borrow(), &self.key(), py)
+    }
+
+    /// Whether calling the proc may sleep the caller, either directly or
+    /// through the procs it calls.
+    #[getter]
+    fn may_sleep(&self, py: Python<'_>) -> PyResult<bool> {
+        Ok(self.sleep_chain(py)?.is_some())
+    }
+
+    /// Returns the calls through which the proc may sleep, ending with the
+    /// sleeping builtin, or `None` if it never sleeps.
+    pub fn sleep_chain(&self, py: Python<'_>) -> PyResult<Option<Vec<CallSite>>> {
+        let dme = self.dme.cast_bound::<Dme>(py).unwrap();
+        let edges = {
+            let dme_ref = dme.borrow();
+            if !dme_ref.procs_parsed {
+                return Err(PyRuntimeError::new_err(
+                    "parse_procs=True was not included in DME's constructor",
+                ));
+            }
+            sleep::sleep_chain(&dme_ref.objtree, &dme_ref.type_index, &self.key())
+        };
+        let Some(edges) = edges else {
+            return Ok(None);
+        };
+        let mut cache = HashMap::new();
+        edges
+            .iter()
+            .map(|edge| callgraph::call_site(dme, edge, &mut cache, py))
+            .collect::<PyResult<Vec<_>>>()
+            .map(Some)
+    }
+
+    /// Infers the static types of the expressions in the proc body, for
+    /// lookup by their source location.
+    pub fn infer_types(&self, py: Python<'_>) -> PyResult<InferredTypes> {
+        let dme = self.dme.cast_bound::<Dme>(py).unwrap();
+        InferredTypes::build(&dme.borrow(), &self.key())
+    }
+
+    pub fn walk(slf: &Bound<Self>, walker: &Bound<PyAny>, py: Python<'_>) -> PyResult<()> {
+        let this = slf.borrow();
+        let dme = this.dme.cast_bound::<Dme>(py).unwrap();
+        let walk = || {
+            Dme::walk_proc(
+                &dme.borrow(),
+                this.type_index,
+                this.name.clone(),
+                walker,
+                this.proc_index,
+                py,
+            )
+        };
+        match walker.cast::<NodeVisitor>() {
+            Ok(visitor) => {
+                let type_decl = dme
+                    .getattr("types")?
+                    .get_item(this.type_path.bind(py))?
+                    .cast_into::<TypeDecl>()?
+                    .unbind();
+                visitor::in_proc(visitor, slf.clone().unbind(), type_decl, walk)
+            }
+            Err(_) => walk(),
+        }
     }
 }
 
@@ -175,6 +520,11 @@ impl TypeDecl {
             .unbind())
     }
 
+    /// Walks the initial values of the vars set on this type.
+    pub fn walk_vars(slf: &Bound<Self>, walker: &Bound<PyAny>, py: Python<'_>) -> PyResult<()> {
+        proc_walk::walk_vars(slf, walker, py)
+    }
+
     #[pyo3(signature = (name, parents=true))]
     pub fn var_decl(&self, name: String, parents: bool, py: Python<'_>) -> PyResult<Py<PyAny>> {
         let bound = self.dme.cast_bound::<Dme>(py).unwrap();
@@ -182,6 +532,34 @@ impl TypeDecl {
         dme.get_var_decl(name, self.node_index, parents, py)
     }
 
+    pub fn vars(&self, py: Python<'_>) -> PyResult<Py<PyList>> {
+        let bound = self.dme.cast_bound::<Dme>(py).unwrap();
+        let dme = bound.borrow();
+        let objtree = &dme.objtree;
+
+        // The nearest type with an entry for a var is the one setting its value.
+        let mut value_indexes: HashMap<&str, NodeIndex> = HashMap::new();
+        let mut index = Some(self.node_index);
+        while let Some(idx) = index {
+            let type_def = &objtree[idx];
+            for var_name in type_def.vars.keys() {
+                value_indexes.entry(var_name.as_str()).or_insert(idx);
+            }
+            index = type_def
+                .parent_type_index()
+                .filter(|parent| !objtree[*parent].is_root());
+        }
+
+        let mut names: Vec<&str> = value_indexes.keys().copied().collect();
+        names.sort();
+        let mut out: Vec<VarDecl> = Vec::new();
+        for name in names {
+            out.push(dme.build_var_decl(name, value_indexes[name], self.node_index, py)?);
+        }
+
+        Ok(PyList::new(py, out)?.unbind())
+    }
+
     #[pyo3(signature = (declared=false, modified=false, unmodified=false))]
     pub fn proc_names(
         &self,
@@ -239,65 +617,34 @@ impl TypeDecl {
     #[pyo3(signature = (name=None))]
     pub fn proc_decls(&self, name: Option<String>, py: Python<'_>) -> PyResult<Py<PyAny>> {
         let dme = self.dme.cast_bound::<Dme>(py).unwrap();
-        let objtree = &dme.borrow().objtree;
         let mut out: Vec<ProcDecl> = Vec::new();
 
-        let type_def = &objtree[self.node_index];
-        for (proc_name, proc) in type_def.procs.iter() {
-            if name.as_ref().is_some_and(|p| !proc_name.eq(p)) {
-                continue;
-            }
-            for (proc_index, proc_value) in proc.value.iter().enumerate() {
-                if !proc_value.location.is_builtins() {
-                    let mut args_out: Vec<ProcArg> = Vec::new();
-                    for arg in proc_value.parameters.iter() {
-                        let arg_typepath = if arg.var_type.type_path.is_empty() {
-                            py.None()
-                        } else {
-                            Path::from_tree_path(&arg.var_type.type_path)
-                                .into_pyobject(py)?
-                                .into_any()
-                                .unbind()
-                        };
-                        args_out.push(ProcArg {
-                            arg_name: arg.name.clone().into_pyobject(py)?.into(),
-                            arg_type: arg_typepath.into_pyobject(py)?.into(),
-                        });
+        let proc_names: Vec<(String, usize)> = {
+            let objtree = &dme.borrow().objtree;
+            let type_def = &objtree[self.node_index];
+            let mut names = Vec::new();
+            for (proc_name, proc) in type_def.procs.iter() {
+                if name.as_ref().is_some_and(|p| !proc_name.eq(p)) {
+                    continue;
+                }
+                for (proc_index, proc_value) in proc.value.iter().enumerate() {
+                    if !proc_value.location.is_builtins() {
+                        names.push((proc_name.clone(), proc_index));
                     }
-
-                    out.push(ProcDecl {
-                        dme: self.dme.clone_ref(py),
-                        name: proc_name.clone(),
-                        type_path: self.path.clone().into_pyobject(py)?.into_any().unbind(),
-
-                        args: PyList::new(
-                            py,
-                            args_out
-                                .into_iter()
-                                .map(|f| f.into_pyobject(py).unwrap().into_any().unbind())
-                                .collect::<Vec<Py<PyAny>>>(),
-                        )?
-                        .into_pyobject(py)?
-                        .into_any()
-                        .unbind(),
-
-                        proc_index,
-                        type_index: self.node_index,
-                        source_loc: FilledSourceLocation {
-                            file_path: dme.borrow().file_data.borrow(py).file_ids
-                                [&proc_value.location.file]
-                                .clone_ref(py),
-                            column: proc_value.location.column,
-                            line: proc_value.location.line,
-                        }
-                        .into_pyobject(py)
-                        .expect("passing proc decl source info")
-                        .into_any()
-                        .unbind(),
-                    });
                 }
             }
+            names
+        };
+        for (proc_name, proc_index) in proc_names {
+            out.push(ProcDecl::build(
+                dme,
+                self.node_index,
+                &proc_name,
+                proc_index,
+                py,
+            )?);
         }
+
         Ok(PyList::new(
             py,
             out.into_iter()
@@ -308,6 +655,54 @@ impl TypeDecl {
         .unbind())
     }
 
+    /// The type this type inherits from, or `None` for the root.
+    #[getter]
+    fn parent(&self, py: Python<'_>) -> PyResult<Option<TypeDecl>> {
+        let dme = self.dme.cast_bound::<Dme>(py).unwrap();
+        let parent = dme.borrow().objtree[self.node_index].parent_type_index();
+        parent.map(|index| TypeDecl::build(dme, index, py)).transpose()
+    }
+
+    /// The types which inherit directly from this type, sorted by path.
+    #[getter]
+    fn children(&self, py: Python<'_>) -> PyResult<Vec<TypeDecl>> {
+        let dme = self.dme.cast_bound::<Dme>(py).unwrap();
+        let children = dme.borrow().type_index.children(self.node_index).to_vec();
+        TypeDecl::build_all(dme, children, py)
+    }
+
+    /// Returns the parent of this type, its parent, and so on up to the root.
+    fn ancestors(&self, py: Python<'_>) -> PyResult<Vec<TypeDecl>> {
+        let dme = self.dme.cast_bound::<Dme>(py).unwrap();
+        let mut indexes = Vec::new();
+        {
+            let objtree = &dme.borrow().objtree;
+            let mut index = objtree[self.node_index].parent_type_index();
+            while let Some(idx) = index {
+                indexes.push(idx);
+                index = objtree[idx].parent_type_index();
+            }
+        }
+        TypeDecl::build_all(dme, indexes, py)
+    }
+
+    /// Returns every type inheriting from this type, each followed by its
+    /// own descendants.
+    fn descendants(&self, py: Python<'_>) -> PyResult<Vec<TypeDecl>> {
+        let dme = self.dme.cast_bound::<Dme>(py).unwrap();
+        let mut indexes = Vec::new();
+        {
+            let dme_ref = dme.borrow();
+            let mut pending: Vec<NodeIndex> =
+                dme_ref.type_index.children(self.node_index).iter().rev().copied().collect();
+            while let Some(index) = pending.pop() {
+                indexes.push(index);
+                pending.extend(dme_ref.type_index.children(index).iter().rev());
+            }
+        }
+        TypeDecl::build_all(dme, indexes, py)
+    }
+
     fn __repr__(&self) -> PyResult<String> {
         Ok(format!("<Type {}>", self.path.rel))
     }
@@ -0,0 +1,276 @@
+import os
+
+import pytest
+
+from avulto import DME, DMM, Path as p, ast
+
+
+def get_fixture_path(name):
+    return os.path.join(os.path.dirname(os.path.abspath(__file__)), "fixtures", name)
+
+
+@pytest.fixture
+def dme() -> DME:
+    return DME.from_file(get_fixture_path("testenv.dme"), parse_procs=True)
+
+
+def test_call_graph(dme: DME):
+    graph = dme.call_graph()
+    start = dme.types["/obj/callgraph"].proc_decls("start")[0]
+
+    calls = graph.calls_from(start)
+    assert [(c.name, c.kind, c.is_resolved) for c in calls] == [
+        ("helper", "call", True),
+        ("helper", "call", True),
+        ("finish", "call", True),
+        ("finish", "call", True),
+        ("finish", "call", False),
+        ("hell_yeah", "call", True),
+        ("finish", "proc_reference", True),
+    ]
+    assert calls[0].callee.type_path == p("/obj/callgraph")
+    assert calls[0].source_loc.line == start.source_loc.line + 1
+    assert calls[5].callee.type_path == p("/")
+
+    callees = graph.callees_of(start)
+    assert [(c.type_path, c.name) for c in callees] == [
+        (p("/obj/callgraph"), "helper"),
+        (p("/obj/callgraph"), "finish"),
+        (p("/"), "hell_yeah"),
+    ]
+
+    finish = dme.types["/obj/callgraph"].proc_decls("finish")[0]
+    assert [c.name for c in graph.callers_of(finish)] == ["start"]
+
+    child_helper = dme.types["/obj/callgraph/child"].proc_decls("helper")[0]
+    (parent_call,) = graph.calls_from(child_helper)
+    assert parent_call.kind == "parent_call"
+    assert parent_call.callee.type_path == p("/obj/callgraph")
+
+    unresolved = graph.calls(resolved=False)
+    assert ("finish", "/obj/callgraph/proc/start") in [
+        (c.name, f"{c.caller.type_path}/proc/{c.caller.name}") for c in unresolved
+    ]
+
+
+def test_call_graph_export(dme: DME):
+    graph = dme.call_graph()
+    dot = graph.to_dot()
+    assert dot.startswith("digraph calls {")
+    assert '"/obj/callgraph/proc/start" -> "/obj/callgraph/proc/helper";' in dot
+    assert '"/obj/callgraph/proc/start" -> "?finish" [style=dashed];' in dot
+    assert '"/obj/proc_meta/verb/interact";' in dot
+    assert '"/obj/proc_meta/proc/interact";' not in dot
+
+    graphml = graph.to_graphml()
+    assert '<node id="?finish"><data key="resolved">false</data></node>' in graphml
+    assert '<node id="/obj/proc_meta/verb/interact">' in graphml
+    assert (
+        '<edge source="/obj/callgraph/child/proc/helper" target="/obj/callgraph/proc/helper">'
+        in graphml
+    )
+
+
+def test_call_graph_requires_procs():
+    dme = DME.from_file(get_fixture_path("testenv.dme"))
+    with pytest.raises(RuntimeError):
+        dme.call_graph()
+
+
+def fixture_lines(*snippets):
+    with open(get_fixture_path("testenv.dm")) as f:
+        lines = f.read().splitlines()
+    return [
+        next(i + 1 for i, line in enumerate(lines) if snippet in line)
+        for snippet in snippets
+    ]
+
+
+def test_may_sleep(dme: DME):
+    def proc(name):
+        return dme.types["/obj/sleepy"].proc_decls(name)[0]
+
+    direct = proc("direct")
+    assert direct.may_sleep
+    (call,) = direct.sleep_chain()
+    assert call.name == "sleep"
+    assert call.source_loc.line == direct.source_loc.line + 1
+
+    chain = proc("indirect").sleep_chain()
+    assert [c.name for c in chain] == ["direct", "sleep"]
+    assert chain[0].caller.name == "indirect"
+    assert chain[1].caller.name == "direct"
+
+    # Neither spawn() nor a callee with waitfor = 0 sleeps the caller.
+    assert not proc("spawned").may_sleep
+    assert proc("no_wait").may_sleep
+    assert not proc("calls_no_wait").may_sleep
+    assert proc("calls_no_wait").sleep_chain() is None
+
+    assert [c.name for c in proc("asks").sleep_chain()] == ["input"]
+
+    # The call may reach an override which sleeps.
+    assert not proc("hook").may_sleep
+    chain = proc("calls_hook").sleep_chain()
+    assert [c.name for c in chain] == ["hook", "sleep"]
+    assert chain[1].caller.type_path == p("/obj/sleepy/child")
+
+    # The chain may pass through a verb.
+    chain = proc("calls_verb").sleep_chain()
+    assert [c.name for c in chain] == ["nap_verb", "sleep"]
+    assert chain[0].callee.is_verb
+    assert chain[1].caller.signature() == "/obj/sleepy/verb/nap_verb()"
+    dot = dme.call_graph().to_dot()
+    assert '"/obj/sleepy/proc/calls_verb" -> "/obj/sleepy/verb/nap_verb";' in dot
+    assert not dme.types["/obj/callgraph"].proc_decls("finish")[0].may_sleep
+
+
+def test_find_type_references(dme: DME):
+    refs = dme.find_references(p("/obj/callgraph"))
+    assert all(ref.file_path.name == "testenv.dm" for ref in refs)
+    assert sorted({ref.line for ref in refs}) == fixture_lines(
+        "var/obj/callgraph/partner",
+        "var/obj/callgraph/other = new",
+        "var/obj/callgraph/linked = /obj/callgraph",
+        "/obj/refs/proc/use(obj/callgraph/target)",
+        "var/obj/callgraph/made = new /obj/callgraph",
+        "if(istype(target, /obj/callgraph))",
+    )
+    assert [str(ref) for ref in dme.find_references("/obj/callgraph")] == [
+        str(ref) for ref in refs
+    ]
+
+
+def test_find_var_references(dme: DME):
+    refs = dme.find_references(("/obj/callgraph", "partner"))
+    assert [ref.line for ref in refs] == fixture_lines(
+        "partner.finish()",
+        "var/untyped = partner",
+        "made.partner = target",
+    )
+
+    var = dme.types["/obj/foo/bar"].var_decl("a")
+    (override,) = dme.find_references(var)
+    assert override.line == fixture_lines("\ta = 4")[0]
+
+
+def test_find_proc_references(dme: DME):
+    finish = dme.types["/obj/callgraph"].proc_decls("finish")[0]
+    refs = dme.find_references(finish)
+    assert [ref.line for ref in refs] == fixture_lines(
+        "partner.finish()",
+        "other.finish()",
+        "hell_yeah(.proc/finish)",
+    )
+
+
+def test_unused(dme: DME):
+    report = dme.unused()
+
+    procs = {(str(proc.type_path), proc.name) for proc in report.procs}
+    assert ("/obj/callgraph", "start") in procs
+    assert ("/obj/foo", "proc1") in procs
+    assert ("/obj/proc_meta/child", "setup") in procs
+    # Called directly, or through an override.
+    assert ("/obj/foo", "proc2") not in procs
+    assert ("/obj/callgraph", "finish") not in procs
+    assert ("/obj/callgraph/child", "helper") not in procs
+    # Reached through ..() and verbs.
+    assert ("/obj/proc_meta", "setup") not in procs
+    assert ("/obj/proc_meta", "interact") not in procs
+
+    vars = {(str(var.type_path), var.name) for var in report.vars}
+    assert ("/obj/foo", "a") in vars
+    assert ("/obj/foo", "cache") in vars
+    assert ("/obj/callgraph", "partner") not in vars
+    assert ("/obj/refs", "linked") not in vars
+
+    assert p("/obj/foo") in report.types
+    assert p("/obj/foo/baz") in report.types
+    assert p("/obj/test_object_2") in report.types
+    assert p("/obj/test_object") not in report.types
+    assert p("/obj/callgraph") not in report.types
+    assert report.types == sorted(report.types)
+
+
+def test_unused_mapped_types(dme: DME):
+    report = dme.unused(dmms=[DMM.from_file(get_fixture_path("map1.dmm"))])
+    assert p("/obj/foo") not in report.types
+    assert p("/obj/foo/baz") in report.types
+
+
+def test_infer_types(dme: DME):
+    proc = dme.types["/obj/infer"].proc_decls("run")[0]
+    types = proc.infer_types()
+
+    class IdentWalker:
+        def __init__(self):
+            self.idents = {}
+
+        def visit_Identifier(self, node, source_loc):
+            self.idents[node.name] = types.type_of(source_loc)
+
+    ident_walker = IdentWalker()
+    proc.walk(ident_walker)
+    assert ident_walker.idents["user"] == p("/mob")
+    assert ident_walker.idents["src"] == p("/obj/infer")
+
+    class CallWalker:
+        def __init__(self):
+            self.calls = []
+            self.news = []
+
+        def visit_Call(self, node, source_loc):
+            self.calls.append(node)
+
+        def visit_NewImplicit(self, node, source_loc):
+            self.news.append(types.type_of(source_loc))
+
+    call_walker = CallWalker()
+    proc.walk(call_walker)
+    make, chained = call_walker.calls
+    assert types.type_of(make) == p("/obj/infer")
+    assert types.type_of(chained) == p("/obj/infer")
+    # made.next_node
+    assert types.type_of(chained.expr) == p("/obj/infer")
+    assert types.type_of(chained.expr.expr) == p("/obj/infer")
+    assert call_walker.news == [p("/obj/infer")]
+
+
+def test_cfg(dme: DME):
+    proc = dme.types["/obj/flow"].proc_decls("branches")[0]
+    cfg = proc.cfg()
+    assert cfg.entry == 0
+    assert cfg.exit == len(cfg) - 1
+    assert cfg.blocks[cfg.exit].successors == []
+    assert not cfg.falls_off_end()
+
+    entry = cfg.blocks[cfg.entry]
+    assert [node.kind for node in entry.nodes] == [ast.NodeKind.IF]
+    kinds = {kind for source, _, kind in cfg.edges if source == cfg.entry}
+    assert kinds == {"true", "false"}
+
+    # The `x = 4` after the final return can never run.
+    (dead,) = cfg.unreachable_blocks()
+    assert dead.predecessors == []
+    assert dead.nodes[0].source_loc.line == fixture_lines("\tx = 4")[0]
+
+    (loop_header,) = [
+        block
+        for block in cfg.blocks
+        if [n.kind for n in block.nodes] == [ast.NodeKind.WHILE]
+    ]
+    assert str(loop_header.condition) == "x"
+    back_edges = [e for e in cfg.edges if e[1] == loop_header.id and e[2] == "loop"]
+    assert len(back_edges) == 1
+    assert any(kind == "break" for _, _, kind in cfg.edges)
+
+
+def test_cfg_no_return(dme: DME):
+    proc = dme.types["/obj/flow"].proc_decls("no_return")[0]
+    cfg = proc.cfg()
+    kinds = {kind for _, _, kind in cfg.edges}
+    assert {"continue", "spawn", "throw", "exception"} <= kinds
+    assert "return" not in kinds
+    assert cfg.falls_off_end()
+    assert cfg.unreachable_blocks() == []
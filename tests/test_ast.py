@@ -0,0 +1,282 @@
+import os
+
+import pytest
+
+from avulto import DME, Dmlist, Path as p, ast, exceptions
+
+
+def get_fixture_path(name):
+    return os.path.join(os.path.dirname(os.path.abspath(__file__)), "fixtures", name)
+
+
+@pytest.fixture
+def dme() -> DME:
+    return DME.from_file(get_fixture_path("testenv.dme"))
+
+
+def test_parse_expression():
+    expr = ast.parse_expression("foo(1, bar)")
+    assert isinstance(expr, ast.Expression.Call)
+    assert expr.name.name == "foo"
+    assert len(expr.args) == 2
+
+    binop = ast.parse_expression("1 + 2 * 3")
+    assert binop.kind == ast.NodeKind.BINARY_OP
+    assert binop.op == ast.BinaryOperator.ADD
+
+
+def test_parse_global_and_macros():
+    ident = ast.parse_expression("global.foo")
+    assert ident.kind == ast.NodeKind.GLOBAL_IDENTIFIER
+    assert ident.name == "foo"
+
+    call = ast.parse_expression("global.foo(1, 2)")
+    assert call.kind == ast.NodeKind.GLOBAL_CALL
+    assert call.name.name == "foo"
+    assert len(call.args) == 2
+
+    assert ast.parse_expression("__TYPE__").kind == ast.NodeKind.CONSTANT
+
+
+def test_no_unknown_node():
+    # Every statement is mapped to its own node, so there is no catch-all.
+    assert not hasattr(ast.Node, "Unknown")
+    assert not hasattr(ast.NodeKind, "UNKNOWN")
+
+
+def test_parse_statements():
+    stmts = ast.parse_statements("var/x = 3\nif(x)\n\treturn x\n")
+    assert [stmt.kind for stmt in stmts] == [ast.NodeKind.VAR, ast.NodeKind.IF]
+
+
+def test_parse_errors():
+    with pytest.raises(SyntaxError):
+        ast.parse_expression("foo(")
+    with pytest.raises(SyntaxError):
+        ast.parse_expression("1\nreturn 2")
+
+
+@pytest.mark.parametrize(
+    "src",
+    [
+        "1 + 2 * 3",
+        "(1 + 2) * 3",
+        "a - (b - c)",
+        "2 ** 3 ** 4",
+        "(2 ** 3) ** 4",
+        "!(a && b) || c",
+        "x = y ? 1 : 2",
+        "-(-x)",
+        "a.b[1].c(2, d)",
+        "..(1)",
+        "list(1, \"a\" = 2)",
+        "\"foo [bar] baz\"",
+        "new /obj/foo(1)",
+        "new x.y(1, 2)",
+        "new x",
+        "/obj/foo{name = \"bar\"}",
+        "locate(/obj) in src",
+        "pick(50; a, b)",
+        "x in 1 to 10",
+        "global.foo",
+        "global.foo(1)",
+        "call_ext(\"lib\", \"fn\")(1)",
+        "__TYPE__",
+        "__IMPLIED_TYPE__",
+    ],
+)
+def test_unparse_expression(src):
+    assert ast.unparse(ast.parse_expression(src)) == src
+
+
+def test_unparse_statements():
+    src = "\n".join(
+        [
+            "var/obj/x = new /obj",
+            "for(var/i = 1 to 10 step 2)",
+            "\tif(i == x)",
+            "\t\tbreak",
+            "\telse if(i > 5)",
+            "\t\tcontinue",
+            "switch(x)",
+            "\tif(1, 3 to 5)",
+            "\t\treturn",
+            "\telse",
+            "\t\tdel(x)",
+            "while(TRUE) {}",
+        ]
+    )
+    stmts = ast.parse_statements(src)
+    assert ast.unparse(stmts) == src
+    assert ast.unparse(stmts[3], indent="  ") == "while(TRUE) {}"
+
+
+@pytest.mark.parametrize(
+    "src",
+    [
+        "for(x in L)",
+        "for(var/obj/x in L)",
+        "for(i = 1 to 10)",
+        "for(var/i = 1 to 10)",
+        "for(k, v in L)",
+        "for(var/k, v in L)",
+    ],
+)
+def test_unparse_loop_vars(src):
+    src = f"{src}\n\tfoo()"
+    assert ast.unparse(ast.parse_statements(src)) == src
+
+
+def test_evaluate(dme: DME):
+    def evaluate(src, context_type=None):
+        return ast.parse_expression(src).evaluate(dme, context_type)
+
+    assert evaluate("1 + 2 * 3") == 7
+    assert evaluate("7 / 2") == 3.5
+    assert evaluate("(1 << 4) | 1") == 17
+    assert evaluate("\"foo\" + \"bar\"") == "foobar"
+    assert evaluate("\"[1 + 1] things\"") == "2 things"
+    assert evaluate("TEST_DEFINE_VALUE * 2") == 60
+    assert evaluate("0 || \"x\"") == "x"
+    assert evaluate("1 > 2 ? 3 : 4") == 4
+    assert evaluate("1e10") == 10000000000
+    assert evaluate("(-2147483647 - 1) % -1") == 0
+    assert evaluate("/obj/foo") == p("/obj/foo")
+    assert evaluate("a * 2", "/obj/foo/bar") == 8
+    assert evaluate("initial(src.a) + 1", dme.types["/obj/foo"]) == 4
+
+    lst = evaluate("list(\"a\" = 1, b = TEST_DEFINE_VALUE, 3)")
+    assert isinstance(lst, Dmlist)
+    assert lst["a"] == 1
+    assert lst["b"] == 30
+
+
+def test_evaluate_errors(dme: DME):
+    with pytest.raises(exceptions.EvaluationError):
+        ast.parse_expression("foo()").evaluate(dme)
+    with pytest.raises(exceptions.EvaluationError):
+        ast.parse_expression("a").evaluate(dme)
+    with pytest.raises(exceptions.EvaluationError):
+        ast.parse_expression("1 / 0").evaluate(dme)
+
+
+def test_child_nodes():
+    call = ast.parse_expression("foo.bar(1, x + 2)")
+    assert call._fields == ("expr", "name", "args")
+    assert [name for name, _ in call.iter_fields()] == list(call._fields)
+    # The proc name is not a child, since it is never evaluated.
+    assert [ast.unparse(c) for c in call.iter_child_nodes()] == ["foo", "1", "x + 2"]
+
+    [if_stmt] = ast.parse_statements("if(a)\n\treturn 1\nelse if(b)\n\tdel(b)\nelse\n\tx++\n")
+    children = if_stmt.iter_child_nodes()
+    assert [type(c).__name__ for c in children] == [
+        "Identifier",
+        "Return",
+        "Identifier",
+        "Del",
+        "Expression",
+    ]
+    assert [ast.kind_name(c) for c in children] == [type(c).__name__ for c in children]
+    assert ast.kind_name(call) == "Call"
+
+
+class IdentCollector(ast.NodeVisitor):
+    def __init__(self):
+        self.idents = []
+        self.ifs = 0
+
+    def visit_If(self, node):
+        self.ifs += 1
+        self.generic_visit(node)
+
+    def visit_Identifier(self, node):
+        self.idents.append(node.name)
+
+
+def test_node_visitor():
+    collector = IdentCollector()
+    for stmt in ast.parse_statements(
+        "if(a)\n\tfoo(b, list(c, d))\nswitch(e)\n\tif(1)\n\t\treturn f\n"
+    ):
+        collector.visit(stmt)
+    assert collector.ifs == 1
+    assert collector.idents == ["a", "b", "c", "d", "e", "f"]
+
+
+def test_node_visitor_walk():
+    dme = DME.from_file(get_fixture_path("testenv.dme"), parse_procs=True)
+    collector = IdentCollector()
+    dme.types["/obj/flow"].proc_decls("branches")[0].walk(collector)
+    assert collector.ifs == 2
+    assert "x" in collector.idents
+
+
+class Rewriter(ast.NodeTransformer):
+    def visit_Identifier(self, node):
+        if node.name == "old":
+            return ast.Expression.Identifier(name="new", source_loc=node.source_loc)
+        return node
+
+    def visit_Del(self, node):
+        return None
+
+    def visit_Crash(self, node):
+        return [
+            ast.parse_statements("world.log << 1")[0],
+            ast.Node.Return(retval=None, source_loc=node.source_loc),
+        ]
+
+
+def test_node_transformer():
+    stmts = ast.parse_statements("if(old)\n\tdel(x)\n\tCRASH(\"no\")\nreturn old + y\n")
+    transformer = Rewriter()
+    result = [transformer.visit(stmt) for stmt in stmts]
+    assert ast.unparse(result) == "if(new)\n\tworld.log << 1\n\treturn\nreturn new + y"
+    assert result[1].source_loc.line == stmts[1].source_loc.line
+
+    unchanged = ast.parse_expression("a + b")
+    assert transformer.visit(unchanged) is unchanged
+
+
+class ContextRecorder(ast.NodeVisitor):
+    def __init__(self):
+        self.seen = {}
+
+    def record(self, node):
+        ctx = self.context
+        self.seen[type(node).__name__] = (
+            [parent.kind for parent in ctx.parents],
+            ctx.loop_depth,
+            ctx.spawn_depth,
+        )
+        self.generic_visit(node)
+
+    visit_Break = visit_Continue = visit_Return = visit_Throw = record
+
+
+def test_walk_context():
+    dme = DME.from_file(get_fixture_path("testenv.dme"), parse_procs=True)
+    flow = dme.types["/obj/flow"]
+
+    recorder = ContextRecorder()
+    flow.proc_decls("branches")[0].walk(recorder)
+    assert recorder.seen["Break"] == ([ast.NodeKind.WHILE, ast.NodeKind.IF], 1, 0)
+    # The context is reset once the walk is over.
+    assert recorder.context.parents == []
+    assert recorder.context.proc is None
+
+    recorder = ContextRecorder()
+    flow.proc_decls("no_return")[0].walk(recorder)
+    assert recorder.seen["Continue"][1:] == (1, 0)
+    assert recorder.seen["Return"] == ([ast.NodeKind.SPAWN], 0, 1)
+    assert recorder.seen["Throw"][0] == [ast.NodeKind.TRY_CATCH, ast.NodeKind.IF]
+
+    class ProcRecorder(ast.NodeVisitor):
+        def visit_Throw(self, node):
+            self.where = (self.context.proc.name, self.context.type_decl.path)
+            self.enclosing = self.context.enclosing(ast.NodeKind.TRY_CATCH)
+
+    recorder = ProcRecorder()
+    flow.proc_decls("no_return")[0].walk(recorder)
+    assert recorder.where == ("no_return", p("/obj/flow"))
+    assert recorder.enclosing.kind == ast.NodeKind.TRY_CATCH
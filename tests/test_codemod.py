@@ -0,0 +1,122 @@
+import os
+import shutil
+
+import pytest
+
+from avulto import DME, ast, codemod
+
+
+def get_fixture_path(name):
+    return os.path.join(os.path.dirname(os.path.abspath(__file__)), "fixtures", name)
+
+
+@pytest.fixture
+def dme(tmp_path) -> DME:
+    shutil.copytree(get_fixture_path(""), tmp_path / "fixtures")
+    return DME.from_file(tmp_path / "fixtures" / "testenv.dme", parse_procs=True)
+
+
+@pytest.fixture
+def codemod_dme(tmp_path) -> DME:
+    shutil.copytree(get_fixture_path(""), tmp_path / "fixtures")
+    return DME.from_file(tmp_path / "fixtures" / "codemod.dme", parse_procs=True)
+
+
+class RenameCall(ast.NodeTransformer):
+    def __init__(self, old, new):
+        self.old = old
+        self.new = new
+
+    def visit_Call(self, node):
+        node = self.generic_visit(node)
+        if node.name.name == self.old:
+            return ast.Expression.Call(node.expr, ast.parse_expression(self.new), node.args, None)
+        return node
+
+
+class InlineHelper(ast.NodeTransformer):
+    def visit_Call(self, node):
+        if node.name.name == "old_helper":
+            return ast.parse_expression(f"{ast.unparse(node.args[0])} || 0")
+        return self.generic_visit(node)
+
+
+def test_rewrite_call(dme: DME):
+    dm_path = dme.filepath.parent / "testenv.dm"
+    original = dm_path.read_text()
+
+    patch = codemod.rewrite(dme, RenameCall("example_call", "renamed_call"), "/obj/test_object")
+    assert not patch.skipped
+    assert [edit.old_text for edit in patch.edits] == [
+        "example_call()",
+        "example_call(var_anr_return())",
+    ]
+    assert patch.files() == [dm_path]
+
+    diff = patch.diff()
+    assert "-\tif(!example_call())" in diff
+    assert "+\tif(!renamed_call())" in diff
+
+    patch.apply()
+    assert dm_path.read_text() == original.replace(
+        "example_call()", "renamed_call()"
+    ).replace("example_call(var_anr_return())", "renamed_call(var_anr_return())")
+
+
+def test_rewrite_nested(codemod_dme: DME):
+    dm_path = codemod_dme.filepath.parent / "codemod.dm"
+    original = dm_path.read_text()
+
+    patch = codemod.rewrite(codemod_dme, RenameCall("old_helper", "new_helper"))
+    assert not patch.skipped
+    assert sorted(edit.old_text for edit in patch.edits) == [
+        "old_helper(1)",
+        "old_helper(2)",
+        "old_helper(3)",
+        "old_helper(4)",
+        "old_helper(5)",
+        "old_helper(6)",
+        "old_helper(7)",
+        "old_helper(8)",
+        "src.old_helper(9)",
+    ]
+    assert patch.new_text(dm_path) == original.replace("old_helper(", "new_helper(").replace(
+        "proc/new_helper(", "proc/old_helper("
+    )
+
+
+def test_rewrite_parenthesizes(codemod_dme: DME):
+    dm_path = codemod_dme.filepath.parent / "codemod.dm"
+
+    patch = codemod.rewrite(codemod_dme, InlineHelper(), "/obj/rewrite")
+    new_text = patch.new_text(dm_path)
+    assert "\tvar/a = 1 || 0\n" in new_text
+    assert "\ta = (2 || 0) // keeps this comment\n" in new_text
+    assert "\tif(a && (3 || 0))\n" in new_text
+    assert "\t\ta += ((4 || 0) + 1) * 2\n" in new_text
+    assert "\tvar/b = a ? (5 || 0) : -(6 || 0)\n" in new_text
+    assert '\tvar/list/L = list("key" = (7 || 0), (8 || 0))\n' in new_text
+
+
+def test_source_span(codemod_dme: DME):
+    data = (codemod_dme.filepath.parent / "codemod.dm").read_bytes()
+    texts = []
+
+    class SpanCollector(ast.NodeVisitor):
+        def generic_visit(self, node):
+            if isinstance(node, ast.Expression):
+                span = codemod_dme.source_span(node)
+                if span is not None:
+                    texts.append(data[span[1] : span[2]].decode())
+            super().generic_visit(node)
+
+    codemod_dme.walk_procs(SpanCollector(), "/obj/rewrite")
+    assert "a = old_helper(2)" in texts
+    assert "a && old_helper(3)" in texts
+    assert "(old_helper(4) + 1) * 2" in texts
+    assert "old_helper(4) + 1" in texts
+    assert "a ? old_helper(5) : -old_helper(6)" in texts
+    assert "-old_helper(6)" in texts
+    assert 'list("key" = old_helper(7), old_helper(8))' in texts
+    assert "src.old_helper(9).len" in texts
+    assert "src.old_helper(9).len + b + L.len" in texts
@@ -1,8 +1,9 @@
+import json
 import os
 
 import pytest
 
-from avulto import DME, Path as p
+from avulto import DME, Path as p, ast
 
 
 def get_fixture_path(name):
@@ -30,6 +31,29 @@ def test_dme_typesof(dme: DME):
     assert all([x in datum_subtypes for x in foo_types])
 
 
+def test_type_navigation(dme: DME):
+    foo = dme.types["/obj/foo"]
+    assert foo.parent.path == p("/obj")
+    assert [child.path for child in foo.children] == [p("/obj/foo/bar"), p("/obj/foo/baz")]
+    assert foo.children[0].parent.path == p("/obj/foo")
+    assert [ty.path for ty in foo.ancestors()] == [
+        p("/obj"),
+        p("/atom/movable"),
+        p("/atom"),
+        p("/datum"),
+        p("/"),
+    ]
+    assert [ty.path for ty in foo.descendants()] == [p("/obj/foo/bar"), p("/obj/foo/baz")]
+    assert dme.types["/obj/foo/baz"].children == []
+
+    root = dme.types["/"]
+    assert root.parent is None
+    assert root.ancestors() == []
+
+    obj_descendants = sorted(ty.path for ty in dme.types["/obj"].descendants())
+    assert obj_descendants == dme.subtypesof("/obj")
+
+
 def test_missing_type(dme: DME):
     assert "/missing_type" not in dme.types
 
@@ -73,7 +97,175 @@ def test_root_lookups(dme: DME):
     assert "hell_yeah" in root.proc_names(declared=True)
 
 
+def test_type_vars(dme: DME):
+    foo_vars = {var.name: var for var in dme.types["/obj/foo"].vars()}
+    assert foo_vars["a"].const_val == 3
+    assert foo_vars["a"].declaring_type == p("/obj/foo")
+    assert foo_vars["name"].declaring_type == p("/atom")
+    assert foo_vars["shared_count"].is_static
+    assert foo_vars["max_things"].is_const
+    assert foo_vars["cache"].is_tmp
+    assert not foo_vars["a"].is_static
+    assert foo_vars["shared_count"].is_global
+    assert foo_vars["shared_count"].var_type == "/static"
+    assert foo_vars["a"].var_type == ""
+    assert not foo_vars["a"].is_private
+    assert foo_vars["a"].input_type is None
+
+    bar_vars = {var.name: var for var in dme.types["/obj/foo/bar"].vars()}
+    assert bar_vars["a"].const_val == 4
+    assert bar_vars["a"].type_path == p("/obj/foo/bar")
+    assert bar_vars["a"].declaring_type == p("/obj/foo")
+    assert bar_vars["icon_state"].const_val == "red_circle"
+    assert bar_vars["icon_state"].type_path == p("/obj/foo")
+    assert bar_vars["a"].evaluated_val == 4
+
+    evaluated = dme.types["/obj/evaluated"]
+    assert evaluated.var_decl("doubled").const_val is None
+    assert evaluated.var_decl("doubled").evaluated_val == 4
+
+
 def test_var_decl_type_path(dme: DME):
     foo = dme.types["/obj/foo"]
     var_decl = foo.var_decl("a")
     assert p("/obj/foo") == var_decl.type_path
+
+
+def test_defines_table(dme: DME):
+    define = dme.defines["TEST_DEFINE_VALUE"]
+    assert define.name == "TEST_DEFINE_VALUE"
+    assert define.value == "30"
+    assert define.source_loc.file_path.name == "testenv.dm"
+    assert define.args is None
+    assert not define.is_undefined
+
+    add = dme.defines["TEST_DEFINE_ADD"]
+    assert add.args == ["a", "b"]
+
+    assert dme.defines["TEST_DEFINE_TEMPORARY"].is_undefined
+
+    redefined = dme.defines["TEST_DEFINE_REDEFINED"]
+    assert redefined.value == "2"
+    assert not redefined.is_undefined
+    assert dme.expand_macro("TEST_DEFINE_REDEFINED") == "2"
+    assert dme.defines["TEST_DEFINE_REDEFINED_TEMPORARY"].is_undefined
+
+
+def test_expand_macro(dme: DME):
+    assert dme.expand_macro("TEST_DEFINE_VALUE") == "30"
+    assert dme.expand_macro("TEST_DEFINE_ADD(1, 2)") == "((1)+(2))"
+    assert dme.expand_macro("TEST_DEFINE_TEMPORARY") == "TEST_DEFINE_TEMPORARY"
+
+
+def test_from_file_defines():
+    assert "/obj/testing_only" not in DME.from_file(get_fixture_path("testenv.dme")).types
+
+    dme = DME.from_file(get_fixture_path("testenv.dme"), defines={"AVULTO_TESTING": 1})
+    assert "/obj/testing_only" in dme.types
+    assert dme.defines["AVULTO_TESTING"].value == "1"
+    assert dme.defines["AVULTO_TESTING"].source_loc.file_path.name == "(defines)"
+    # The environment's own lines are not shifted by the defines.
+    assert dme.defines["FILE_DIR"].source_loc.line == 4
+
+
+def test_from_file_includes():
+    assert "/obj/from_excluded_file" in DME.from_file(get_fixture_path("includes.dme")).types
+
+    dme = DME.from_file(
+        get_fixture_path("includes.dme"),
+        extra_includes=["extra.dm"],
+        exclude=["exclu*.dm"],
+    )
+    assert "/obj/from_extra_file" in dme.types
+    assert "/obj/from_excluded_file" not in dme.types
+    assert "/obj/foo" in dme.types
+
+
+def test_proc_metadata():
+    dme = DME.from_file(get_fixture_path("testenv.dme"), parse_procs=True)
+    setup = dme.types["/obj/proc_meta"].proc_decls("setup")[0]
+    assert not setup.is_verb
+    assert not setup.is_override
+    assert setup.should_call_parent
+    assert setup.should_not_sleep
+    assert setup.waitfor
+    assert setup.parent_proc() is None
+
+    child_setup = dme.types["/obj/proc_meta/child"].proc_decls("setup")[0]
+    assert child_setup.is_override
+    assert not child_setup.should_call_parent
+    assert not child_setup.waitfor
+    parent = child_setup.parent_proc()
+    assert parent.type_path == p("/obj/proc_meta")
+    assert parent.name == "setup"
+
+    interact = dme.types["/obj/proc_meta"].proc_decls("interact")[0]
+    assert interact.is_verb
+    assert interact.settings == {"name": "Interact", "category": "Object", "hidden": 1}
+
+    dupe = dme.types["/obj/test_object_2"].proc_decls("dupe_named_proc")
+    assert not dupe[0].is_override
+    assert dupe[1].is_override
+    assert dupe[1].parent_proc().source_loc.line == dupe[0].source_loc.line
+
+
+def test_proc_args():
+    dme = DME.from_file(get_fixture_path("testenv.dme"), parse_procs=True)
+    configure = dme.types["/obj/proc_meta"].proc_decls("configure")[0]
+    user, amount, message, choice = configure.args
+    assert user.arg_type == p("/mob")
+    assert user.default is None
+    assert amount.default.constant.val == 1
+    assert message.input_type == "text"
+    assert choice.in_list.kind == ast.NodeKind.LIST
+    assert user.source_loc.line == configure.source_loc.line
+
+    assert configure.signature() == (
+        '/obj/proc_meta/proc/configure(mob/user, amount = 1, message as text, choice in list("a", "b"))'
+    )
+    interact = dme.types["/obj/proc_meta"].proc_decls("interact")[0]
+    assert interact.signature() == "/obj/proc_meta/verb/interact()"
+
+
+def test_export_types_json(dme: DME):
+    exported = json.loads(dme.export_types(prefix="/obj/foo"))
+    types = {ty["path"]: ty for ty in exported["types"]}
+    assert list(types) == ["/obj/foo", "/obj/foo/bar", "/obj/foo/baz"]
+
+    foo = types["/obj/foo"]
+    assert foo["parent"] == "/obj"
+    assert foo["source_loc"]["file"].endswith("testenv.dm")
+    foo_vars = {var["name"]: var for var in foo["vars"]}
+    assert foo_vars["a"]["declared"]
+    assert foo_vars["a"]["value"] == "3"
+    assert foo_vars["a"]["source_loc"]["line"] == 4
+    assert not foo_vars["icon"]["declared"]
+    assert foo_vars["icon"]["value"] == "'icon1.dmi'"
+    assert foo_vars["icon_state"]["value"] == '"red_circle"'
+    assert [proc["signature"] for proc in foo["procs"]] == [
+        "/obj/foo/proc/proc1(mob/M)",
+        "/obj/foo/proc/proc2(mob/M)",
+    ]
+    assert not foo["procs"][0]["is_override"]
+
+    bar = types["/obj/foo/bar"]
+    assert bar["parent"] == "/obj/foo"
+    assert [(var["name"], var["declared"], var["value"]) for var in bar["vars"]] == [
+        ("a", False, "4")
+    ]
+    assert bar["procs"] == []
+
+    bare = json.loads(dme.export_types(prefix="/obj/foo", include_vars=False, include_procs=False))
+    assert set(bare["types"][0]) == {"path", "parent", "source_loc"}
+
+
+def test_export_types_dot(dme: DME):
+    dot = dme.export_types("dot", prefix="/obj/foo", include_procs=False)
+    assert dot.startswith("digraph types {")
+    assert '"/obj/foo" -> "/obj/foo/bar";' in dot
+    assert '"/obj/foo/bar" [label="/obj/foo/bar\\lvar a = 4\\l"];' in dot
+    assert '"/obj/foo/baz";' in dot
+    assert '"/obj" -> "/obj/foo";' not in dot
+
+    with pytest.raises(ValueError):
+        dme.export_types("xml")
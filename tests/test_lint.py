@@ -0,0 +1,79 @@
+import os
+
+import pytest
+
+from avulto import DME, exceptions, lint
+
+
+def get_fixture_path(name):
+    return os.path.join(os.path.dirname(os.path.abspath(__file__)), "fixtures", name)
+
+
+@pytest.fixture
+def dme() -> DME:
+    return DME.from_file(get_fixture_path("testenv.dme"), parse_procs=True)
+
+
+def lint_findings(dme, rule):
+    return [
+        (finding.proc.name, finding.source_loc.line)
+        for finding in lint.run(dme, rules=[rule])
+        if str(finding.proc.type_path).startswith("/obj/lint")
+    ]
+
+
+def fixture_line(text):
+    with open(get_fixture_path("testenv.dm")) as f:
+        for i, line in enumerate(f.read().splitlines(), 1):
+            if line.strip() == text:
+                return i
+    raise ValueError(text)
+
+
+def test_missing_parent_call(dme: DME):
+    assert lint_findings(dme, "missing_parent_call") == [
+        ("cleanup", fixture_line("/obj/lint/child/cleanup()")),
+    ]
+
+
+def test_sleep_in_should_not_sleep(dme: DME):
+    (finding,) = [
+        f for f in lint.run(dme, rules=["sleep_in_should_not_sleep"]) if f.proc.name == "checks"
+    ]
+    assert finding.source_loc.line == fixture_line("nap()")
+    assert "nap() -> sleep()" in finding.message
+
+
+def test_statement_rules(dme: DME):
+    assert lint_findings(dme, "del_usage") == [("cleanup", fixture_line("del(src)"))]
+    assert lint_findings(dme, "spawn_usage") == [("cleanup", fixture_line("spawn(1)"))]
+    assert lint_findings(dme, "unreachable_code") == [("cleanup", fixture_line("spawn(1)"))]
+    assert lint_findings(dme, "unused_local") == [
+        ("cleanup", fixture_line("var/unused_thing = 1")),
+    ]
+    assert lint_findings(dme, "assignment_in_condition") == [
+        ("cleanup", fixture_line("if(used = 3)")),
+    ]
+    assert lint_findings(dme, "istype_missing_path") == [
+        ("checks", fixture_line("if(istype(x, /obj/does_not_exist))")),
+    ]
+
+
+def test_bodiless_procs(dme: DME):
+    (stub,) = dme.types["/obj/lint"].proc_decls("stub")
+    with pytest.raises(exceptions.EmptyProcError):
+        stub.cfg()
+    assert not [f for f in lint.run(dme) if f.proc is not None and f.proc.name == "stub"]
+
+
+def test_run(dme: DME):
+    findings = lint.run(dme)
+    assert {f.rule for f in findings} == set(lint.RULES)
+    locs = [(str(f.source_loc.file_path), f.source_loc.line, f.source_loc.column) for f in findings]
+    assert locs == sorted(locs)
+
+    data = findings[0].to_dict()
+    assert set(data) == {"rule", "message", "file", "line", "column", "proc"}
+
+    with pytest.raises(ValueError):
+        lint.run(dme, rules=["no_such_rule"])
@@ -2,7 +2,7 @@ import os
 
 import pytest
 
-from avulto import ast, DME
+from avulto import ast, DME, Path as p
 
 
 def get_fixture_path(name):
@@ -14,6 +14,14 @@ def dme() -> DME:
     return DME.from_file(get_fixture_path("testenv.dme"), parse_procs=True)
 
 
+def fixture_line(text):
+    with open(get_fixture_path("testenv.dm")) as f:
+        for i, line in enumerate(f.read().splitlines(), 1):
+            if line.strip() == text:
+                return i
+    raise ValueError(text)
+
+
 def test_walker_base(dme: DME):
     class VarAndReturnWalker:
         def visit_Return(self, node, source_loc):
@@ -47,3 +55,141 @@ def test_visit_call(dme: DME):
     dme.types["/obj/test_object"].proc_decls("test_visit_call")[0].walk(walker)
     assert len(walker.calls) == 2
     assert all([isinstance(call, ast.Expression.Call) for call in walker.calls])
+
+
+def test_walk_procs(dme: DME):
+    class ProcCounter:
+        def __init__(self):
+            self.entered = []
+            self.exited = []
+            self.returns = 0
+
+        def enter_proc(self, proc):
+            self.entered.append((proc.type_path, proc.name))
+
+        def exit_proc(self, proc):
+            self.exited.append((proc.type_path, proc.name))
+
+        def visit_Return(self, node, source_loc):
+            self.returns += 1
+
+    class ThrowCollector(ast.NodeVisitor):
+        def __init__(self):
+            self.throws = []
+
+        def visit_Throw(self, node):
+            self.throws.append(self.context.proc.name)
+
+    counter = ProcCounter()
+    throws = ThrowCollector()
+    dme.walk_procs([counter, throws], filter="/obj/flow")
+    assert counter.entered == [(p("/obj/flow"), "branches"), (p("/obj/flow"), "no_return")]
+    assert counter.exited == counter.entered
+    assert counter.returns == 3
+    assert throws.throws == ["no_return"]
+    assert throws.context.proc is None
+
+    everything = ProcCounter()
+    dme.walk_procs(everything)
+    assert (p("/obj/flow"), "branches") in everything.entered
+    assert (p("/datum"), "New") not in everything.entered
+
+    with_builtins = ProcCounter()
+    dme.walk_procs(with_builtins, include_builtins=True)
+    assert (p("/datum"), "New") in with_builtins.entered
+
+
+def test_walk_procs_requires_parse_procs():
+    dme = DME.from_file(get_fixture_path("testenv.dme"))
+    with pytest.raises(RuntimeError):
+        dme.walk_procs(object())
+
+
+def test_walk_vars(dme: DME):
+    class VarWalker:
+        def __init__(self):
+            self.vars = []
+            self.news = []
+            self.constants = []
+
+        def enter_var(self, var):
+            self.vars.append(var.name)
+
+        def visit_NewPrefab(self, node, source_loc):
+            self.news.append(node.prefab.path)
+
+        def visit_Constant(self, constant):
+            self.constants.append(constant.val)
+
+    walker = VarWalker()
+    dme.types["/obj/initialized"].walk_vars(walker)
+    assert walker.vars == ["name", "parts", "first"]
+    assert walker.news == [p("/obj/initialized/part")]
+    assert "initialized" in walker.constants
+
+    class NewCollector(ast.NodeVisitor):
+        def __init__(self):
+            self.types = []
+
+        def visit_NewPrefab(self, node):
+            self.types.append(self.context.type_decl.path)
+
+    collector = NewCollector()
+    dme.types["/obj/initialized"].walk_vars(collector)
+    assert collector.types == [p("/obj/initialized")]
+    assert collector.context.type_decl is None
+
+
+def test_walk_arg_defaults(dme: DME):
+    class DefaultWalker:
+        def __init__(self):
+            self.kinds = []
+
+        def visit_Constant(self, constant):
+            self.kinds.append(("constant", constant.val))
+
+        def visit_NewImplicit(self, node, source_loc):
+            self.kinds.append(("new", source_loc.line))
+
+        def visit_Identifier(self, node, source_loc):
+            self.kinds.append(("ident", node.name))
+
+    walker = DefaultWalker()
+    dme.types["/obj/initialized"].proc_decls("setup")[0].walk(walker)
+    assert walker.kinds[0] == ("constant", 3)
+    assert walker.kinds[1][0] == "new"
+    assert walker.kinds[2] == ("ident", "count")
+
+
+def test_query(dme: DME):
+    calls = dme.query(ast.Pattern("Call", name="finish"), "/obj/callgraph")
+    assert len(calls) == 3
+    assert all(match.proc.name == "start" for match in calls)
+    assert [match.source_loc.line for match in calls] == [
+        fixture_line("partner.finish()"),
+        fixture_line("other.finish()"),
+        fixture_line("untyped.finish()"),
+    ]
+
+    comparisons = dme.query(
+        ast.Pattern("BinaryOp", op=ast.BinaryOperator.EQ, lhs="x", rhs=ast.Any()),
+        "/obj/flow",
+        proc="branches",
+    )
+    assert [match.node.rhs.constant.val for match in comparisons] == [1, 2, 5]
+
+    returns = dme.query(ast.Pattern("Return", retval=1), "/obj/flow")
+    assert len(returns) == 1
+    assert returns[0].source_loc.line == fixture_line("return 1")
+
+
+def test_pattern():
+    pattern = ast.Pattern("Call", name="qdel", args=[ast.Any()])
+    assert pattern.matches(ast.parse_expression("qdel(src)"))
+    assert not pattern.matches(ast.parse_expression("qdel(src, TRUE)"))
+    assert not pattern.matches(ast.parse_expression("foo(src)"))
+
+    with pytest.raises(ValueError):
+        ast.Pattern("NotAKind")
+    with pytest.raises(ValueError):
+        ast.Pattern("Call", bogus=1).matches(ast.parse_expression("foo()"))